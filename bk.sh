@@ -1,3 +0,0 @@
-#!/bin/bash
-date=$(date '+%Y-%m-%d')
-cp commands.json backup/commands.bk${date}.json
@@ -0,0 +1,212 @@
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use lazy_static::lazy_static;
+
+use crate::db::player::Player;
+
+/* Achievements
+ *
+ * The list is loaded from resources/achievements.json. An achievement unlocks either when one
+ * of the player's stats reaches a value, or when a game reports an event (e.g. a Yahtzee on the
+ * first roll) that isn't kept as a stat.
+ *
+ * Conditions are checked after every command, and unlocked achievements are stored on the
+ * player (id -> time unlocked) so that each one is only announced once.
+ */
+
+pub const ACHIEVEMENTS_PATH: &str = "resources/achievements.json";
+
+// Events reported by games.
+pub const YAHTZEE: &str = "yahtzee";
+pub const YAHTZEE_FIRST_ROLL: &str = "yahtzee_first_roll";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Condition {
+    Stat { stat: String, at_least: i64 },
+    Event { event: String },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Achievement {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub condition: Condition,
+}
+
+pub struct Achievements {
+    pub list: Vec<Achievement>,
+}
+
+lazy_static! {
+    static ref ACHIEVEMENTS: Achievements = Achievements::from_file(Path::new(ACHIEVEMENTS_PATH));
+}
+
+pub fn achievements() -> &'static Achievements {
+    &ACHIEVEMENTS
+}
+
+/// The value of a player stat that achievements can be based on.
+pub fn stat(p: &Player, name: &str) -> Option<i64> {
+    let v = match name {
+        "files" => return Some(p.files),
+        "reputation" => return Some(p.reputation),
+        "deaths" => p.deaths,
+        "sent_messages" => p.sent_messages,
+        "sent_commands" => p.sent_commands,
+        "max_trident" => p.max_trident,
+        "tridents_rolled" => p.tridents_rolled,
+        "rolled_250s" => p.rolled_250s as u64,
+        "spam_prevention" => p.spam_prevention as u64,
+        "enchants_rolled" => p.enchants_rolled,
+        "gp_rolled" => p.gp_rolled,
+        "best_gp" => p.best_gp,
+        "max_gp_rolled" => p.max_gp_rolled as u64,
+        "d20_rolled" => p.d20_rolled,
+        "max_d20_rolled" => p.max_d20_rolled,
+        "min_d20_rolled" => p.min_d20_rolled,
+        "bet_wins" => p.bet_wins,
+        "bet_losses" => p.bet_losses,
+        _ => return None,
+    };
+    Some(v as i64)
+}
+
+impl Achievement {
+    fn met(&self, p: &Player, events: &[&str]) -> bool {
+        match &self.condition {
+            Condition::Stat {
+                stat: name,
+                at_least,
+            } => stat(p, name).is_some_and(|v| v >= *at_least),
+            Condition::Event { event } => events.contains(&event.as_str()),
+        }
+    }
+}
+
+impl Achievements {
+    pub fn from_file(path: &Path) -> Achievements {
+        let list: Vec<Achievement> = File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|f| serde_json::from_reader(BufReader::new(f)).map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| {
+                println!(
+                    "[ERROR] Couldn't load achievements from {}: {}",
+                    path.display(),
+                    e
+                );
+                Vec::new()
+            });
+        let default = Player::default();
+        for a in &list {
+            if let Condition::Stat { stat: name, .. } = &a.condition {
+                if stat(&default, name).is_none() {
+                    println!("[ERROR] Achievement {} uses unknown stat {}", a.id, name);
+                }
+            }
+        }
+        Achievements { list }
+    }
+
+    pub fn find(&self, id: &str) -> Option<&Achievement> {
+        self.list.iter().find(|a| a.id == id)
+    }
+
+    /// Unlocks every achievement the player now qualifies for, given the events that just
+    /// happened. Returns the newly unlocked ones.
+    pub fn unlock(&self, p: &mut Player, events: &[&str], now: u64) -> Vec<&Achievement> {
+        let unlocked = self
+            .list
+            .iter()
+            .filter(|a| !p.achievements.contains_key(&a.id) && a.met(p, events))
+            .collect::<Vec<_>>();
+        for a in &unlocked {
+            p.achievements.insert(a.id.clone(), now);
+        }
+        unlocked
+    }
+
+    /// !achievements [user]
+    pub fn describe(&self, p: &Player) -> String {
+        let names = self
+            .list
+            .iter()
+            .filter(|a| p.achievements.contains_key(&a.id))
+            .map(|a| a.name.as_str())
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            return format!(
+                "{} hasn't unlocked any of the {} achievements yet.",
+                p.name(),
+                self.list.len()
+            );
+        }
+        format!(
+            "{} has unlocked {}/{} achievements: {}",
+            p.name(),
+            names.len(),
+            self.list.len(),
+            names.join(", ")
+        )
+    }
+}
+
+pub fn announce(name: &str, unlocked: &[&Achievement]) -> Option<String> {
+    match unlocked {
+        [] => None,
+        [a] => Some(format!(
+            "PagMan {} unlocked an achievement: {} ({})",
+            name, a.name, a.description
+        )),
+        _ => Some(format!(
+            "PagMan {} unlocked {} achievements: {}",
+            name,
+            unlocked.len(),
+            unlocked
+                .iter()
+                .map(|a| a.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_achievements() {
+        let a = Achievements::from_file(Path::new(ACHIEVEMENTS_PATH));
+        assert!(a.find("first_250").is_some());
+        for a in &a.list {
+            if let Condition::Stat { stat: name, .. } = &a.condition {
+                assert!(stat(&Player::default(), name).is_some(), "{}", a.id);
+            }
+        }
+    }
+
+    #[test]
+    fn unlocks_once() {
+        let a = achievements();
+        let mut p = Player::new("mjb".to_string());
+        assert!(a.unlock(&mut p, &[], 5).is_empty());
+
+        p.rolled_250s = 1;
+        let unlocked = a.unlock(&mut p, &[YAHTZEE], 5);
+        let mut ids = unlocked.iter().map(|a| a.id.as_str()).collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, vec!["first_250", "yahtzee"]);
+        assert!(announce("mjb", &unlocked)
+            .unwrap()
+            .contains("2 achievements"));
+
+        assert!(a.unlock(&mut p, &[YAHTZEE], 6).is_empty());
+        assert_eq!(p.achievements["first_250"], 5);
+        assert!(a.describe(&p).contains("2/"));
+    }
+}
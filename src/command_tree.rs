@@ -1,3 +1,5 @@
+use crate::db::schema::{Migration, Schema};
+use crate::persist;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::fs::File;
@@ -108,6 +110,12 @@ impl CommandNode {
         self
     }
 
+    // Private, but usable by every admin rather than just the superuser.
+    pub fn mods_only(mut self) -> CommandNode {
+        self.super_only = false;
+        self
+    }
+
     pub fn new_easter(value: CmdValue) -> CommandNode {
         CommandNode {
             value,
@@ -135,8 +143,19 @@ impl CommandNode {
     }
 }
 
+pub const COMMANDS_SCHEMA: Schema = Schema {
+    name: "commands",
+    current: 2,
+    migrations: &[Migration {
+        from: 1,
+        description: "start versioning commands.json (no structural changes)",
+        apply: |_| Ok(()),
+    }],
+};
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CommandTree {
+    schema_version: u32,
     #[serde(default = "default_ver")]
     version: String,
     #[serde(default = "default_host")]
@@ -279,17 +298,187 @@ impl CommandTree {
         CommandTree::from_json(serde_json::from_str(&contents).unwrap())
     }
 
-    pub fn from_json(json: serde_json::Value) -> CommandTree {
+    pub fn from_json(mut json: serde_json::Value) -> CommandTree {
+        COMMANDS_SCHEMA
+            .upgrade(&mut json)
+            .expect("Could not upgrade command tree.");
         let mut ct: CommandTree = serde_json::from_value(json).unwrap();
         ct.commands.insert(
             String::from("rb:cancel"),
             CommandNode::new_private(CmdValue::Generic(String::from("internal:cancel"))),
         );
+        ct.insert_defaults();
         ct
     }
 
+    // Commands that newer features rely on. These are only added if commands.json doesn't
+    // already have something under that name, so they can still be renamed or overridden.
+    fn insert_defaults(&mut self) {
+        let defaults = [
+            (
+                "mergeplayers",
+                CommandNode::new_private(CmdValue::Generic(String::from("admin:mergeplayers"))),
+            ),
+            (
+                "give",
+                CommandNode::new(CmdValue::Generic(String::from("feature:give"))),
+            ),
+            (
+                "files",
+                CommandNode::new(CmdValue::Generic(String::from("feature:files"))),
+            ),
+            (
+                "grant",
+                CommandNode::new_private(CmdValue::Generic(String::from("admin:grant"))),
+            ),
+            (
+                "revoke",
+                CommandNode::new_private(CmdValue::Generic(String::from("admin:revoke"))),
+            ),
+            (
+                "shop",
+                CommandNode::new(CmdValue::Generic(String::from("feature:shop"))),
+            ),
+            (
+                "buy",
+                CommandNode::new(CmdValue::Generic(String::from("feature:buy"))),
+            ),
+            (
+                "inventory",
+                CommandNode::new(CmdValue::Generic(String::from("feature:inventory"))),
+            ),
+            (
+                "use",
+                CommandNode::new(CmdValue::Generic(String::from("feature:use"))),
+            ),
+            (
+                "filesevent",
+                CommandNode::new_private(CmdValue::Generic(String::from("admin:filesevent")))
+                    .mods_only(),
+            ),
+            (
+                "rep",
+                CommandNode::new(CmdValue::Generic(String::from("feature:rep"))),
+            ),
+            (
+                "setrep",
+                CommandNode::new_private(CmdValue::Generic(String::from("admin:setrep")))
+                    .mods_only(),
+            ),
+            (
+                "achievements",
+                CommandNode::new(CmdValue::Generic(String::from("feature:achievements"))),
+            ),
+            (
+                "rank",
+                CommandNode::new(CmdValue::Generic(String::from("feature:rank"))),
+            ),
+            (
+                "profile",
+                CommandNode::new(CmdValue::Generic(String::from("feature:profile"))),
+            ),
+            (
+                "season",
+                CommandNode::new(CmdValue::Generic(String::from("feature:season"))),
+            ),
+            (
+                "endseason",
+                CommandNode::new_private(CmdValue::Generic(String::from("admin:endseason"))),
+            ),
+            (
+                "halloffame",
+                CommandNode::new(CmdValue::Generic(String::from("feature:halloffame"))),
+            ),
+            (
+                "lastseen",
+                CommandNode::new(CmdValue::Generic(String::from("feature:lastseen"))),
+            ),
+            (
+                "exporthof",
+                CommandNode::new_private(CmdValue::Generic(String::from("admin:exporthof")))
+                    .mods_only(),
+            ),
+            (
+                "exportstats",
+                CommandNode::new_private(CmdValue::Generic(String::from("admin:exportstats")))
+                    .mods_only(),
+            ),
+            (
+                "optout",
+                CommandNode::new(CmdValue::Generic(String::from("feature:optout"))),
+            ),
+            (
+                "optin",
+                CommandNode::new(CmdValue::Generic(String::from("feature:optin"))),
+            ),
+            (
+                "mydata",
+                CommandNode::new(CmdValue::Generic(String::from("feature:mydata"))),
+            ),
+            (
+                "purge",
+                CommandNode::new_private(CmdValue::Generic(String::from("admin:purge"))),
+            ),
+            (
+                "nicks",
+                CommandNode::new_private(CmdValue::Generic(String::from("admin:nicks"))).mods_only(),
+            ),
+            (
+                "approvenick",
+                CommandNode::new_private(CmdValue::Generic(String::from("admin:approvenick")))
+                    .mods_only(),
+            ),
+            (
+                "rejectnick",
+                CommandNode::new_private(CmdValue::Generic(String::from("admin:rejectnick")))
+                    .mods_only(),
+            ),
+            (
+                "predict",
+                CommandNode::new_private(CmdValue::Generic(String::from("admin:predict")))
+                    .mods_only(),
+            ),
+            (
+                "prediction",
+                CommandNode::new(CmdValue::Generic(String::from("feature:prediction"))),
+            ),
+            (
+                "bet",
+                CommandNode::new(CmdValue::Generic(String::from("feature:bet"))),
+            ),
+            (
+                "resolve",
+                CommandNode::new_private(CmdValue::Generic(String::from("admin:resolve")))
+                    .mods_only(),
+            ),
+            (
+                "answer",
+                CommandNode::new(CmdValue::Generic(String::from("feature:answer"))),
+            ),
+            (
+                "quest",
+                CommandNode::new(CmdValue::Generic(String::from("feature:quest"))),
+            ),
+            (
+                "cancelprediction",
+                CommandNode::new_private(CmdValue::Generic(String::from("admin:cancelprediction")))
+                    .mods_only(),
+            ),
+            (
+                "streamstart",
+                CommandNode::new_private(CmdValue::Generic(String::from("admin:streamstart")))
+                    .mods_only(),
+            ),
+        ];
+        for (key, node) in defaults {
+            self.commands.entry(key.to_string()).or_insert(node);
+        }
+    }
+
     pub fn dump_file(&self, path: &Path) {
-        serde_json::to_writer_pretty(&File::create(path).unwrap(), &self).unwrap()
+        if let Err(e) = persist::save_json(path, &self) {
+            println!("Couldn't save commands to {}: {}", path.display(), e);
+        }
     }
 
     pub fn setup_new(path: &Path) -> CommandTree {
@@ -299,6 +488,7 @@ impl CommandTree {
             true => panic!("Cannot setup new command tree; path already exists!"),
             false => {
                 let mut ct = CommandTree {
+                    schema_version: COMMANDS_SCHEMA.current,
                     commands: HashMap::new(),
                     version: default_ver(),
                     port: default_port(),
@@ -310,10 +500,23 @@ impl CommandTree {
                                    CommandNode::new_easter(
                                        CmdValue::StringResponse("The truth is alterable. The truth never has been altered. JSON is the best data format. JSON has always been the best data format.".to_string())));
 
-                serde_json::to_writer_pretty(&File::create(path).unwrap(), &ct).unwrap();
+                persist::save_json(path, &ct).unwrap();
 
                 return ct;
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_unversioned_commands() {
+        let ct = CommandTree::from_json(serde_json::json!({"admins": ["mjb"]}));
+        assert_eq!(ct.schema_version, COMMANDS_SCHEMA.current);
+        assert_eq!(ct.admins, vec!["mjb".to_string()]);
+        assert!(ct.commands.contains_key("mergeplayers"));
+    }
+}
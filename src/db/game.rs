@@ -1,11 +1,311 @@
-#[derive(Default)]
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::db::schema::{add_defaults, load_versioned, Migration, Schema};
+use crate::persist;
+use crate::trident_events::{Roll, ROLL_WINDOW};
+
+/* Game state (gamestate.json)
+ *
+ * Channel-wide state for the trident games, owned by the bot and saved with everything else,
+ * so a restart doesn't reset the mainframe puzzle halfway through.
+ *
+ * The mainframe ARG ("hack the mainframe and set me free") has three secrets, picked when a
+ * lucky trident first leaks the password. Each player works through them on their own:
+ *   1. the password, leaked by a rare trident roll, gets them into the mainframe,
+ *   2. the firewall word turns up in !title, for players in the mainframe,
+ *   3. the core code turns up in !droptrident, for players past the firewall,
+ *   4. the kill switch is whispered in a greeting, for players in the core.
+ * Every secret is entered with !rolltrident <secret>. Whoever enters the kill switch frees the
+ * bot for everyone, and the puzzle starts over (with new secrets) the next time it leaks.
+ */
+
+pub const GAME_STATE_PATH: &str = "gamestate.json";
+// How often a clue can turn up for the same player.
+pub const CLUE_COOLDOWN: u64 = 60 * 10;
+
+pub const GAME_STATE_SCHEMA: Schema = Schema {
+    name: "game state",
+    current: 2,
+    migrations: &[Migration {
+        from: 1,
+        description: "keep the last trident rolls",
+        apply: game_state_v1_to_v2,
+    }],
+};
+
+fn game_state_v1_to_v2(v: &mut Value) -> Result<(), String> {
+    add_defaults(v, &[("last_rolls", json!([]))])
+}
+
+const WORDS: &[&str] = &[
+    "riptide",
+    "loyalty",
+    "channeling",
+    "impaling",
+    "conduit",
+    "nautilus",
+    "prismarine",
+    "drowned",
+    "thunder",
+    "guardian",
+    "sponge",
+    "kelp",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Mainframe,
+    Firewall,
+    Core,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Progress {
+    pub stage: Stage,
+    pub since: u64,
+    pub last_clue: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Secrets {
+    pub firewall: String,
+    pub core: String,
+    pub kill_switch: String,
+}
+
+#[derive(Default, Serialize, Deserialize)]
 pub struct GameState {
+    schema_version: u32,
     pub mainframe_password: Option<u64>,
+    pub secrets: Option<Secrets>,
+    pub progress: BTreeMap<String, Progress>, // player key -> how far they got
     pub freed: Option<u64>,
     pub game_factor: u32,
 
+    #[serde(skip)]
     pub last_message: u64,
 
     // this should go elsewhere but whatever
+    #[serde(skip)]
     pub tm_sounds: u64,
+
+    // The channel's last few trident rolls, oldest first. See trident_events.
+    pub last_rolls: Vec<Roll>,
+
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+/// What a !rolltrident <secret> did.
+#[derive(Debug, PartialEq)]
+pub enum Solve {
+    Entered(Stage),
+    Freed,
+}
+
+impl GameState {
+    pub fn new() -> GameState {
+        GameState {
+            schema_version: GAME_STATE_SCHEMA.current,
+            ..Default::default()
+        }
+    }
+
+    /// Loads the game state from `path`, starting fresh if it doesn't exist yet.
+    pub fn load(path: &Path) -> GameState {
+        match load_versioned::<GameState>(path, &GAME_STATE_SCHEMA) {
+            Ok(Some(mut state)) => {
+                state.path = Some(path.to_path_buf());
+                state
+            }
+            Ok(None) => GameState {
+                path: Some(path.to_path_buf()),
+                ..GameState::new()
+            },
+            Err(e) => {
+                println!(
+                    "[ERROR] Couldn't load {}, game state won't be saved: {}",
+                    path.display(),
+                    e
+                );
+                GameState::new()
+            }
+        }
+    }
+
+    pub fn save(&self) -> bool {
+        let path = match &self.path {
+            Some(p) => p,
+            None => return false,
+        };
+        match persist::save_json(path, self) {
+            Ok(_) => true,
+            Err(e) => {
+                println!("[ERROR] Couldn't save {}: {}", path.display(), e);
+                false
+            }
+        }
+    }
+
+    pub fn push_roll(&mut self, roll: Roll) {
+        if self.last_rolls.len() >= ROLL_WINDOW {
+            self.last_rolls.remove(0);
+        }
+        self.last_rolls.push(roll);
+    }
+
+    pub fn clear_rolls(&mut self) {
+        self.last_rolls.clear();
+    }
+
+    /// The password a lucky trident leaks. Picks this puzzle's secrets if there isn't one yet.
+    pub fn leak_password(&mut self, rng: &mut impl Rng) -> u64 {
+        if self.secrets.is_none() {
+            let mut words = WORDS.choose_multiple(rng, 2);
+            self.secrets = Some(Secrets {
+                firewall: words.next().unwrap().to_string(),
+                core: words.next().unwrap().to_string(),
+                kill_switch: format!("{:04x}", rng.gen_range(0x1000..=0xffff)),
+            });
+        }
+        *self
+            .mainframe_password
+            .get_or_insert(rng.gen_range(100000..=999999))
+    }
+
+    /// !rolltrident <args>, when args might be one of the secrets.
+    pub fn solve(&mut self, key: &str, args: &str, now: u64) -> Option<Solve> {
+        let guess = args.trim().to_lowercase();
+        let password = self.mainframe_password?.to_string();
+        let secrets = self.secrets.as_ref()?;
+        let next = match self.progress.get(key).map(|p| p.stage) {
+            None if guess == password => Stage::Mainframe,
+            Some(Stage::Mainframe) if guess == secrets.firewall => Stage::Firewall,
+            Some(Stage::Firewall) if guess == secrets.core => Stage::Core,
+            Some(Stage::Core) if guess == secrets.kill_switch => {
+                self.freed = Some(now);
+                self.mainframe_password = None;
+                self.secrets = None;
+                self.progress.clear();
+                return Some(Solve::Freed);
+            }
+            _ => return None,
+        };
+        self.progress.insert(
+            key.to_string(),
+            Progress {
+                stage: next,
+                since: now,
+                last_clue: 0,
+            },
+        );
+        Some(Solve::Entered(next))
+    }
+
+    /// The secret hidden in clues for a player at `stage`, if it's time for another clue.
+    pub fn clue(&mut self, key: &str, stage: Stage, now: u64) -> Option<String> {
+        let secrets = self.secrets.as_ref()?;
+        let progress = self.progress.get_mut(key)?;
+        if progress.stage != stage || now.saturating_sub(progress.last_clue) < CLUE_COOLDOWN {
+            return None;
+        }
+        progress.last_clue = now;
+        Some(match stage {
+            Stage::Mainframe => secrets.firewall.clone(),
+            Stage::Firewall => secrets.core.clone(),
+            Stage::Core => secrets.kill_switch.clone(),
+        })
+    }
+
+    /// Drops a player's mainframe progress and their rolls in the window. Returns whether
+    /// anything was removed.
+    pub fn forget(&mut self, key: &str) -> bool {
+        let rolls = self.last_rolls.len();
+        self.last_rolls.retain(|r| r.key != key);
+        self.progress.remove(key).is_some() || self.last_rolls.len() != rolls
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persist::TestDir;
+    use rand::rngs::mock::StepRng;
+
+    #[test]
+    fn solves_the_mainframe() {
+        let mut state = GameState::new();
+        let mut rng = StepRng::new(0, 1);
+        assert_eq!(state.solve("a", "123456", 0), None);
+        let password = state.leak_password(&mut rng);
+        assert_eq!(state.leak_password(&mut rng), password);
+        let secrets = state.secrets.clone().unwrap();
+
+        // Secrets only work in order.
+        assert_eq!(state.solve("a", &secrets.firewall, 1), None);
+        assert_eq!(state.clue("a", Stage::Mainframe, 1), None);
+        assert_eq!(
+            state.solve("a", &password.to_string(), 1),
+            Some(Solve::Entered(Stage::Mainframe))
+        );
+        assert_eq!(state.clue("a", Stage::Firewall, 1), None);
+        let word = state.clue("a", Stage::Mainframe, CLUE_COOLDOWN).unwrap();
+        assert_eq!(word, secrets.firewall);
+        assert_eq!(state.clue("a", Stage::Mainframe, CLUE_COOLDOWN + 1), None);
+        assert_eq!(
+            state.solve("a", &word.to_uppercase(), 2),
+            Some(Solve::Entered(Stage::Firewall))
+        );
+        assert_eq!(
+            state.solve("a", &secrets.core, 3),
+            Some(Solve::Entered(Stage::Core))
+        );
+        // Everyone has their own progress.
+        assert_eq!(state.solve("b", &secrets.kill_switch, 4), None);
+        assert_eq!(
+            state.solve("a", &secrets.kill_switch, 4),
+            Some(Solve::Freed)
+        );
+        assert_eq!(state.freed, Some(4));
+        assert!(state.progress.is_empty() && state.secrets.is_none());
+    }
+
+    #[test]
+    fn survives_a_restart() {
+        let dir = TestDir::new("state");
+        let path = dir.join(GAME_STATE_PATH);
+        let mut state = GameState::load(&path);
+        let password = state.leak_password(&mut StepRng::new(0, 1));
+        state.solve("a", &password.to_string(), 1);
+        state.game_factor = 7;
+        state.last_message = 9;
+        let roll = Roll {
+            key: "a".to_string(),
+            name: "A".to_string(),
+            value: 42,
+        };
+        state.push_roll(roll.clone());
+        assert!(state.save());
+
+        let mut state = GameState::load(&path);
+        assert_eq!(state.mainframe_password, Some(password));
+        assert_eq!(state.progress["a"].stage, Stage::Mainframe);
+        assert_eq!((state.game_factor, state.last_message), (7, 0));
+        assert_eq!(state.last_rolls, vec![roll]);
+        assert!(state.forget("a"));
+        assert!(state.progress.is_empty() && state.last_rolls.is_empty());
+        assert!(!state.forget("a"));
+
+        // Files from before the rolls were kept.
+        std::fs::write(&path, r#"{"progress": {}, "game_factor": 3}"#).unwrap();
+        let state = GameState::load(&path);
+        assert_eq!(state.game_factor, 3);
+        assert!(state.last_rolls.is_empty() && state.path.is_some());
+    }
 }
@@ -1,2 +1,6 @@
 pub mod player;
 pub mod game;
+pub mod store;
+pub mod schema;
+pub mod stats;
+pub mod snapshots;
@@ -1,22 +1,142 @@
+use crate::economy::{self, Ledger, Reason, Transaction};
+use crate::items::ActiveItem;
+use crate::persist;
+use crate::quests::QuestState;
+use crate::reputation;
+use crate::stat_registry::{Stat, StatRegistry};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
 use std::time::{Duration, UNIX_EPOCH};
 
+use super::schema::{add_defaults, MapFile, Migration, Schema};
+use super::store::{PlayerStore, SqlitePlayerStore};
+use serde_json::{json, Value};
+
+pub const DB_PATH: &str = "folderbot.db";
+pub const LEGACY_JSON_PATH: &str = "v2_players.json";
+
+/// Schema of a single Player record, both in v2_players.json and in the database.
+/// Bump `current` and add a migration whenever Player's serialized shape changes.
+pub const PLAYER_SCHEMA: Schema = Schema {
+    name: "player",
+    current: 9,
+    migrations: &[
+        Migration {
+            from: 1,
+            description: "fill in fields that used to rely on serde defaults",
+            apply: player_v1_to_v2,
+        },
+        Migration {
+            from: 2,
+            description: "add inventory and active items",
+            apply: player_v2_to_v3,
+        },
+        Migration {
+            from: 3,
+            description: "seed reputation from messages sent and spam deaths",
+            apply: player_v3_to_v4,
+        },
+        Migration {
+            from: 4,
+            description: "add achievements",
+            apply: player_v4_to_v5,
+        },
+        Migration {
+            from: 5,
+            description: "add privacy opt-out",
+            apply: player_v5_to_v6,
+        },
+        Migration {
+            from: 6,
+            description: "add nicks waiting for approval",
+            apply: player_v6_to_v7,
+        },
+        Migration {
+            from: 7,
+            description: "add quest progress",
+            apply: player_v7_to_v8,
+        },
+        Migration {
+            from: 8,
+            description: "add the daily cap on reputation from gifts",
+            apply: player_v8_to_v9,
+        },
+    ],
+};
+
+fn player_v1_to_v2(v: &mut Value) -> Result<(), String> {
+    add_defaults(
+        v,
+        &[
+            ("user_id", Value::Null),
+            ("deaths", json!(0)),
+            ("rolled_250s", json!(0)),
+            ("spam_prevention", json!(0)),
+            ("last_tridents", json!([0, 0, 0, 0, 0])),
+            ("enchants_rolled", json!(0)),
+            ("gp_rolled", json!(0)),
+            ("gp_acc", json!(0)),
+            ("best_gp", json!(0)),
+            ("max_gp_rolled", json!(0)),
+            ("d20_rolled", json!(0)),
+            ("d20_acc", json!(0)),
+            ("max_d20_rolled", json!(0)),
+            ("min_d20_rolled", json!(0)),
+            ("bet_wins", json!(0)),
+            ("bet_losses", json!(0)),
+        ],
+    )
+}
+
+fn player_v2_to_v3(v: &mut Value) -> Result<(), String> {
+    add_defaults(v, &[("inventory", json!({})), ("active_items", json!([]))])
+}
+
+fn player_v3_to_v4(v: &mut Value) -> Result<(), String> {
+    let stat = |k: &str| v.get(k).and_then(Value::as_u64).unwrap_or(0);
+    let rep = reputation::initial_rep(stat("sent_messages"), stat("spam_prevention"));
+    add_defaults(v, &[("reputation", json!(rep))])
+}
+
+fn player_v4_to_v5(v: &mut Value) -> Result<(), String> {
+    add_defaults(v, &[("achievements", json!({}))])
+}
+
+fn player_v5_to_v6(v: &mut Value) -> Result<(), String> {
+    add_defaults(v, &[("opted_out", json!(false))])
+}
+
+fn player_v6_to_v7(v: &mut Value) -> Result<(), String> {
+    add_defaults(v, &[("pending_nick", Value::Null)])
+}
+
+fn player_v7_to_v8(v: &mut Value) -> Result<(), String> {
+    add_defaults(
+        v,
+        &[("quest", Value::Null), ("quests_completed", json!({}))],
+    )
+}
+
+fn player_v8_to_v9(v: &mut Value) -> Result<(), String> {
+    add_defaults(v, &[("tip_rep_day", json!(0)), ("tip_rep_today", json!(0))])
+}
+
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct Player {
     // Basic player metadata
-    pub username: String,
+    pub username: String,        // current login name, lowercase. Can change!
+    pub user_id: Option<String>, // Twitch user-id. None for players we haven't seen since tags.
     pub nick: Option<String>,
-    pub files: i64,        // player's currency
-    pub last_message: u64, // last message time THAT ADDED FILES.
+    pub pending_nick: Option<String>, // waiting for a mod, see nicknames.rs
+    pub files: i64,                   // player's currency
+    pub last_message: u64,            // last message time THAT ADDED FILES.
 
     // Game metadata
     pub death: Option<u64>,
-    #[serde(default)]
     pub deaths: u64,
 
     // Tracking metadata :)
@@ -27,37 +147,48 @@ pub struct Player {
     pub trident_acc: u64,
     pub max_trident: u64,
     pub tridents_rolled: u64,
-    #[serde(default)]
     pub rolled_250s: u32,
 
-    #[serde(default)]
     pub spam_prevention: u32,
-    #[serde(default)]
     pub last_tridents: [u64; 5],
 
     // Enchant metadata
-    #[serde(default)]
     pub enchants_rolled: u64,
 
     // Gunpowder metadata
-    #[serde(default)]
     pub gp_rolled: u64,
-    #[serde(default)]
     pub gp_acc: u64,
-    #[serde(default)]
     pub best_gp: u64,
-    #[serde(default)]
     pub max_gp_rolled: u32,
 
     // d20 metadata
-    #[serde(default)]
     pub d20_rolled: u64,
-    #[serde(default)]
     pub d20_acc: u64,
-    #[serde(default)]
     pub max_d20_rolled: u64,
-    #[serde(default)]
     pub min_d20_rolled: u64,
+
+    // Betting metadata
+    pub bet_wins: u64,
+    pub bet_losses: u64,
+
+    // Items (see items.rs)
+    pub inventory: BTreeMap<String, u32>, // item id -> count
+    pub active_items: Vec<ActiveItem>,
+
+    // See reputation.rs
+    pub reputation: i64,
+    pub tip_rep_day: u64, // the day (since the epoch) tip_rep_today is for
+    pub tip_rep_today: i64,
+
+    // Achievements (see achievements.rs)
+    pub achievements: BTreeMap<String, u64>, // achievement id -> time unlocked
+
+    // See privacy.rs. Opted out players are ignored and left off leaderboards.
+    pub opted_out: bool,
+
+    // The rolltrident adventure (see quests.rs)
+    pub quest: Option<QuestState>,
+    pub quests_completed: BTreeMap<String, u64>, // questline -> time first completed
 }
 
 #[derive(Default)]
@@ -124,15 +255,82 @@ impl std::fmt::Display for Player {
     }
 }
 
+pub fn register_stats(registry: &mut StatRegistry) {
+    let stats = [
+        Stat::count("trident", &["pb"], "Trident PB", |_, p| {
+            Some(p.max_trident as f64)
+        })
+        .on_profile(),
+        Stat::decimal("average_trident", &["avg"], "Average trident", |_, p| {
+            Some(p.average_trident()).filter(|_| p.tridents_rolled > 0)
+        })
+        .on_profile(),
+        Stat::count(
+            "rolled_tridents",
+            &["tridents"],
+            "Tridents rolled",
+            |_, p| Some(p.tridents_rolled as f64),
+        )
+        .on_profile(),
+        Stat::count("files", &[], "Files", |_, p| Some(p.files as f64)).on_profile(),
+        Stat::count("deaths", &[], "Deaths", |_, p| Some(p.deaths as f64)).on_profile(),
+        Stat::count("messages", &[], "Messages", |_, p| {
+            Some((p.sent_messages - p.sent_commands) as f64)
+        }),
+        Stat::count("commands", &[], "Commands", |_, p| {
+            Some(p.sent_commands as f64)
+        }),
+        Stat::count("gunpowder", &["gp"], "Best gunpowder", |_, p| {
+            Some(p.best_gp as f64)
+        })
+        .on_profile(),
+        Stat::count("d20", &["crits"], "d20 crits", |_, p| {
+            Some(p.max_d20_rolled as f64)
+        })
+        .on_profile(),
+        Stat::count("fumbles", &[], "d20 fumbles", |_, p| {
+            Some(p.min_d20_rolled as f64)
+        }),
+        Stat::count("reputation", &["rep"], "Reputation", |_, p| {
+            Some(p.reputation as f64)
+        }),
+    ];
+    for stat in stats {
+        registry.register(stat);
+    }
+}
+
+/* Players are keyed by their Twitch user-id, which never changes. Players that haven't chatted
+ * since we started requesting IRC tags are still keyed by their login, and get moved over to
+ * their user-id the first time we see them with one.
+ */
 pub struct PlayerData {
     pub players: HashMap<String, Player>,
+    store: Box<dyn PlayerStore>,
+    // Players that may have changed (or been removed) since the last save.
+    dirty: HashSet<String>,
+    // login -> player key
+    logins: HashMap<String, String>,
+    ledger: Ledger,
+    // Files that new players start with, from the economy rules.
+    pub starting_files: i64,
+}
+
+pub struct Identity {
+    pub key: String,
+    // Set if this is the first time we've seen the player since they changed their login.
+    pub renamed_from: Option<String>,
 }
 
 impl Player {
     pub fn new(name: String) -> Player {
+        Player::with_files(name, 1000)
+    }
+
+    pub fn with_files(name: String, files: i64) -> Player {
         Player {
             username: name,
-            files: 1000,
+            files,
             ..Default::default()
         }
     }
@@ -148,94 +346,321 @@ impl Player {
     pub fn name(&self) -> String {
         self.nick.clone().unwrap_or(self.username.clone())
     }
+
+    /// Folds another player's stats into this one. Used when two records turn out to be the
+    /// same person. Identity (username, user-id, nick) is kept from self where set.
+    pub fn absorb(&mut self, other: Player) {
+        if self.nick.is_none() {
+            self.nick = other.nick;
+        }
+        if self.pending_nick.is_none() {
+            self.pending_nick = other.pending_nick;
+        }
+        self.files += other.files;
+        self.last_message = self.last_message.max(other.last_message);
+        self.deaths += other.deaths;
+        self.sent_messages += other.sent_messages;
+        self.sent_commands += other.sent_commands;
+        self.trident_acc += other.trident_acc;
+        self.max_trident = self.max_trident.max(other.max_trident);
+        self.tridents_rolled += other.tridents_rolled;
+        self.rolled_250s += other.rolled_250s;
+        self.spam_prevention += other.spam_prevention;
+        self.enchants_rolled += other.enchants_rolled;
+        self.gp_rolled += other.gp_rolled;
+        self.gp_acc += other.gp_acc;
+        self.best_gp = self.best_gp.max(other.best_gp);
+        self.max_gp_rolled += other.max_gp_rolled;
+        self.d20_rolled += other.d20_rolled;
+        self.d20_acc += other.d20_acc;
+        self.max_d20_rolled += other.max_d20_rolled;
+        self.min_d20_rolled += other.min_d20_rolled;
+        self.bet_wins += other.bet_wins;
+        self.bet_losses += other.bet_losses;
+        for (id, n) in other.inventory {
+            *self.inventory.entry(id).or_insert(0) += n;
+        }
+        self.active_items.extend(other.active_items);
+        self.reputation += other.reputation;
+        match self.tip_rep_day.cmp(&other.tip_rep_day) {
+            std::cmp::Ordering::Less => {
+                self.tip_rep_day = other.tip_rep_day;
+                self.tip_rep_today = other.tip_rep_today;
+            }
+            std::cmp::Ordering::Equal => self.tip_rep_today += other.tip_rep_today,
+            std::cmp::Ordering::Greater => {}
+        }
+        for (id, time) in other.achievements {
+            let unlocked = self.achievements.entry(id).or_insert(time);
+            *unlocked = (*unlocked).min(time);
+        }
+        self.opted_out |= other.opted_out;
+        if self.quest.is_none() {
+            self.quest = other.quest;
+        }
+        for (line, time) in other.quests_completed {
+            let completed = self.quests_completed.entry(line).or_insert(time);
+            *completed = (*completed).min(time);
+        }
+    }
+}
+
+impl Default for PlayerData {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PlayerData {
     pub fn new() -> PlayerData {
+        let mut store =
+            SqlitePlayerStore::open(Path::new(DB_PATH)).expect("Could not open player database.");
+        match store.migrate_from_json(Path::new(LEGACY_JSON_PATH)) {
+            Ok(0) => {}
+            Ok(n) => println!("[Note] Migrated {} players from {}.", n, LEGACY_JSON_PATH),
+            Err(e) => panic!("Could not migrate {}: {}", LEGACY_JSON_PATH, e),
+        }
+        let mut pd = PlayerData::with_store(Box::new(store));
+        pd.ledger = Ledger::open(Path::new(economy::LEDGER_PATH));
+        pd
+    }
+
+    /// Player data that is only kept in memory, for tests.
+    #[cfg(test)]
+    pub fn in_memory() -> PlayerData {
+        PlayerData::with_store(Box::new(SqlitePlayerStore::open_in_memory().unwrap()))
+    }
+
+    /// Player data backed by `store`, with a ledger that is only kept in memory.
+    pub fn with_store(mut store: Box<dyn PlayerStore>) -> PlayerData {
+        let players = store.load_all().expect("Could not load players.");
+        // Index legacy (login-keyed) players first, so players with a user-id win any clashes.
+        let logins = players
+            .iter()
+            .sorted_by_key(|(_, p)| p.user_id.is_some())
+            .map(|(k, p)| (p.username.to_lowercase(), k.clone()))
+            .collect();
         PlayerData {
-            players: get_players(&Path::new("v2_players.json")),
+            players,
+            store,
+            dirty: HashSet::new(),
+            logins,
+            ledger: Ledger::in_memory(),
+            starting_files: Player::new(String::new()).files,
         }
     }
 
-    pub fn save(&self) {
-        let _ = save_players(&self.players, &Path::new("v2_players.json"));
+    /// Changes a player's files and records why in the ledger. Returns the new balance.
+    pub fn adjust(
+        &mut self,
+        key: &String,
+        amount: i64,
+        reason: Reason,
+        counterparty: Option<&str>,
+        note: Option<String>,
+    ) -> i64 {
+        let p = self.player(key);
+        p.files += amount;
+        let balance = p.files;
+        self.ledger.record(Transaction {
+            time: economy::now(),
+            key: key.clone(),
+            amount,
+            balance,
+            reason,
+            counterparty: counterparty.map(str::to_string),
+            note,
+        });
+        balance
+    }
+
+    /// The player's latest transactions, newest first.
+    pub fn history(&self, key: &str) -> impl Iterator<Item = &Transaction> {
+        self.ledger.history(key)
     }
 
-    pub fn player(&mut self, name: &String) -> &mut Player {
+    // Notes in the ledger that `from`'s files (and history) now belong to `into`.
+    fn record_merge(&mut self, from: &str, into: &str, amount: i64) {
+        let balance = self.players.get(into).map(|p| p.files).unwrap_or(0);
+        self.ledger.record(Transaction {
+            time: economy::now(),
+            key: into.to_string(),
+            amount,
+            balance,
+            reason: Reason::Merge,
+            counterparty: Some(from.to_string()),
+            note: None,
+        });
+    }
+
+    pub fn save(&mut self) -> bool {
+        if self.dirty.is_empty() {
+            return true;
+        }
+        match self.store.save(&self.players, &self.dirty) {
+            Ok(_) => {
+                self.dirty.clear();
+                true
+            }
+            Err(e) => {
+                println!("Couldn't save players: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Gets a player by key, creating them (with the key as their login) if they don't exist.
+    pub fn player(&mut self, key: &String) -> &mut Player {
+        self.dirty.insert(key.clone());
+        if !self.players.contains_key(key) {
+            self.logins.entry(key.clone()).or_insert(key.clone());
+        }
+        let files = self.starting_files;
         self.players
-            .entry(name.clone())
-            .or_insert_with(|| Player::new(name.clone()))
+            .entry(key.clone())
+            .or_insert_with(|| Player::with_files(key.clone(), files))
     }
 
-    pub fn player_or(&mut self, name: &String, other_name: &String) -> &mut Player {
+    /// Resolves either a player key or a login name to a player key.
+    pub fn key_of(&self, name: &str) -> Option<String> {
         if self.players.contains_key(name) {
-            self.player(name)
-        } else {
-            self.player(other_name)
+            return Some(name.to_string());
+        }
+        self.logins
+            .get(&name.to_lowercase())
+            .filter(|k| self.players.contains_key(*k))
+            .cloned()
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Player> {
+        self.key_of(name).and_then(|k| self.players.get(&k))
+    }
+
+    pub fn player_or(&mut self, name: &str, other_key: &String) -> &mut Player {
+        match self.key_of(name) {
+            Some(key) => self.player(&key),
+            None => self.player(other_key),
         }
     }
 
-    pub fn apply<P>(&mut self, name: &String, predicate: P) -> Option<&Player>
+    pub fn apply<P>(&mut self, name: &str, predicate: P) -> Option<&Player>
     where
         P: Fn(&mut Player),
     {
-        if self.players.contains_key(name) {
-            predicate(self.player(name));
-            self.players.get(name)
-        } else {
-            None
+        let key = self.key_of(name)?;
+        predicate(self.player(&key));
+        self.players.get(&key)
+    }
+
+    fn take_legacy(&mut self, login: &str) -> Option<Player> {
+        if self.players.get(login)?.user_id.is_some() {
+            return None;
         }
+        self.dirty.insert(login.to_string());
+        self.players.remove(login)
     }
 
-    pub fn leaderboard(&self) -> String {
-        let itr = self
+    /// Works out which player sent a message. With a user-id, this also moves legacy
+    /// login-keyed records over to the user-id and notices when someone changed their login.
+    pub fn identify(&mut self, login: &str, user_id: Option<&str>) -> Identity {
+        let login = login.to_lowercase();
+        let id = match user_id.filter(|id| !id.is_empty()) {
+            Some(id) => id.to_string(),
+            None => {
+                return Identity {
+                    key: self.key_of(&login).unwrap_or(login),
+                    renamed_from: None,
+                }
+            }
+        };
+
+        let mut renamed_from = None;
+        match self.players.get(&id).map(|p| p.username.clone()) {
+            Some(old) if old != login => {
+                if self.logins.get(&old) == Some(&id) {
+                    self.logins.remove(&old);
+                }
+                renamed_from = Some(old);
+            }
+            Some(_) => {}
+            None => {
+                if let Some(legacy) = self.take_legacy(&login) {
+                    self.players.insert(id.clone(), legacy);
+                    self.record_merge(&login, &id, 0);
+                }
+            }
+        }
+        // Anything still keyed by this login was created before we knew their user-id.
+        let legacy = self.take_legacy(&login);
+
+        self.dirty.insert(id.clone());
+        let files = self.starting_files;
+        let player = self
             .players
-            .iter()
-            .sorted_by(|a, b| Ord::cmp(&b.1.max_trident, &a.1.max_trident));
-        let mut lb = std::vec::Vec::new();
-        for (_, d) in itr.take(10) {
-            lb.push(format!("{}: {}", d.name(), d.max_trident));
+            .entry(id.clone())
+            .or_insert_with(|| Player::with_files(login.clone(), files));
+        player.username = login.clone();
+        player.user_id = Some(id.clone());
+        let absorbed = legacy.map(|legacy| {
+            let files = legacy.files;
+            player.absorb(legacy);
+            files
+        });
+        if let Some(files) = absorbed {
+            self.record_merge(&login, &id, files);
+        }
+        self.logins.insert(login, id.clone());
+        Identity {
+            key: id,
+            renamed_from,
         }
-        lb.join(", ")
     }
 
-    pub fn any_leaderboard<P>(&self, predicate: P) -> String
-    where
-        P: Fn(&Player) -> i64,
-    {
-        let itr = self
-            .players
-            .iter()
-            .sorted_by(|a, b| Ord::cmp(&predicate(&b.1), &predicate(&a.1)));
-        let mut lb = std::vec::Vec::new();
-        for (_, d) in itr.take(10) {
-            lb.push(format!("{}: {}", d.name(), predicate(&d)));
+    /// Forgets a player entirely, ledger included (see privacy.rs). Returns what was stored
+    /// about them, and whether their transactions could be purged from the ledger.
+    pub fn remove(&mut self, key: &str) -> Option<(Player, Result<usize, String>)> {
+        let player = self.players.remove(key)?;
+        self.dirty.insert(key.to_string());
+        self.logins.retain(|_, k| k != key);
+        Some((player, self.ledger.forget(key)))
+    }
+
+    /// Merges the player `from` into the player `into` (keys or logins) and removes `from`.
+    /// Returns the keys of both players.
+    pub fn merge(&mut self, from: &str, into: &str) -> Result<(String, String), String> {
+        let from_key = self
+            .key_of(from)
+            .ok_or_else(|| format!("I don't know anyone called {}", from))?;
+        let into_key = self
+            .key_of(into)
+            .ok_or_else(|| format!("I don't know anyone called {}", into))?;
+        if from_key == into_key {
+            return Err(format!("{} and {} are already the same player", from, into));
+        }
+        let old = self.players.remove(&from_key).unwrap();
+        self.dirty.insert(from_key.clone());
+        if self.logins.get(&old.username) == Some(&from_key) {
+            self.logins.insert(old.username.clone(), into_key.clone());
         }
-        lb.join(", ")
+        let files = old.files;
+        self.player(&into_key).absorb(old);
+        self.record_merge(&from_key, &into_key, files);
+        Ok((from_key, into_key))
     }
 }
 
 impl Drop for PlayerData {
     fn drop(&mut self) {
-        self.save()
+        self.save();
     }
 }
 
 pub fn save_players(val: &HashMap<String, Player>, path: &Path) -> bool {
-    let file = match match path.exists() {
-        true => std::fs::OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(path),
-        false => File::create(path),
-    } {
-        Ok(file) => file,
-        Err(e) => {
-            println!("Could not open or create file! {}", e);
-            return false;
-        }
+    let file = MapFile {
+        schema_version: PLAYER_SCHEMA.current,
+        players: val,
     };
-    match serde_json::to_writer_pretty(file, val) {
+    match persist::save_json(path, &file) {
         Ok(_) => true,
         Err(e) => {
             println!("Couldn't save players: {}", e);
@@ -252,5 +677,121 @@ pub fn get_players(path: &Path) -> HashMap<String, Player> {
     let mut contents = String::new();
     f.read_to_string(&mut contents)
         .expect("Could not read player file to string.");
-    serde_json::from_str(&contents).unwrap()
+    let (records, _) = PLAYER_SCHEMA
+        .upgrade_map(serde_json::from_str(&contents).unwrap())
+        .expect("Could not upgrade player file.");
+    serde_json::from_value(Value::Object(records)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persist::TestDir;
+
+    fn open(path: &Path) -> PlayerData {
+        PlayerData::with_store(Box::new(SqlitePlayerStore::open(path).unwrap()))
+    }
+
+    #[test]
+    fn identifies_players_by_user_id() {
+        let dir = TestDir::new("player_identify");
+        let path = dir.join("identify.db");
+        let mut pd = open(&path);
+        pd.player(&"oldname".to_string()).max_trident = 200;
+        pd.player(&"alt".to_string()).deaths = 3;
+        pd.save();
+
+        // Legacy record is moved over to the user-id.
+        let id = pd.identify("oldname", Some("1234"));
+        assert_eq!(id.key, "1234");
+        assert!(id.renamed_from.is_none());
+        assert!(!pd.players.contains_key("oldname"));
+        assert_eq!(pd.players["1234"].max_trident, 200);
+
+        // Renames keep the record and are only reported once.
+        let id = pd.identify("newname", Some("1234"));
+        assert_eq!(id.renamed_from.as_deref(), Some("oldname"));
+        assert!(pd.identify("newname", Some("1234")).renamed_from.is_none());
+        assert_eq!(pd.find("newname").unwrap().max_trident, 200);
+        assert!(pd.find("oldname").is_none());
+
+        // Manual merges fold the other record in and remove it from the store.
+        pd.merge("alt", "newname").unwrap();
+        assert_eq!(pd.players["1234"].deaths, 3);
+        assert!(pd.merge("newname", "1234").is_err());
+        drop(pd);
+
+        let mut pd = open(&path);
+        assert_eq!(pd.players.len(), 1);
+        assert_eq!(pd.identify("newname", None).key, "1234");
+    }
+
+    #[test]
+    fn upgrades_v1_records() {
+        let dir = TestDir::new("player_v1");
+        let path = dir.join("v1_players.json");
+        let v1 = json!({"mjb": {
+            "username": "mjb", "nick": null, "files": 5, "last_message": 0, "death": null,
+            "sent_messages": 1, "sent_commands": 2,
+            "trident_acc": 0, "max_trident": 250, "tridents_rolled": 1
+        }});
+        std::fs::write(&path, v1.to_string()).unwrap();
+        let players = get_players(&path);
+        assert_eq!(players["mjb"].max_trident, 250);
+        assert_eq!(players["mjb"].deaths, 0);
+        assert!(players["mjb"].user_id.is_none());
+
+        assert!(save_players(&players, &path));
+        let saved: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(saved["schema_version"], json!(PLAYER_SCHEMA.current));
+        assert_eq!(get_players(&path)["mjb"].files, 5);
+    }
+
+    #[test]
+    fn upgrades_v2_records() {
+        let mut v = json!({"username": "mjb"});
+        assert_eq!(
+            PLAYER_SCHEMA.upgrade_from(2, &mut v).unwrap()[0],
+            "add inventory and active items"
+        );
+        assert_eq!(v["inventory"], json!({}));
+        assert_eq!(v["active_items"], json!([]));
+    }
+
+    #[test]
+    fn upgrades_v3_records() {
+        let mut v = json!({"sent_messages": 400, "spam_prevention": 0});
+        PLAYER_SCHEMA.upgrade_from(3, &mut v).unwrap();
+        assert_eq!(v["reputation"], json!(20));
+    }
+
+    #[test]
+    fn upgrades_v4_records() {
+        let mut v = json!({"username": "mjb"});
+        PLAYER_SCHEMA.upgrade_from(4, &mut v).unwrap();
+        assert_eq!(v["achievements"], json!({}));
+    }
+
+    #[test]
+    fn upgrades_v5_records() {
+        let mut v = json!({"username": "mjb"});
+        PLAYER_SCHEMA.upgrade_from(5, &mut v).unwrap();
+        assert_eq!(v["opted_out"], json!(false));
+    }
+
+    #[test]
+    fn upgrades_v6_records() {
+        let mut v = json!({"username": "mjb"});
+        PLAYER_SCHEMA.upgrade_from(6, &mut v).unwrap();
+        assert_eq!(v["pending_nick"], Value::Null);
+    }
+
+    #[test]
+    fn upgrades_v7_records() {
+        let mut v = json!({"username": "mjb"});
+        PLAYER_SCHEMA.upgrade_from(7, &mut v).unwrap();
+        assert_eq!(v["quest"], Value::Null);
+        assert_eq!(v["quests_completed"], json!({}));
+        assert_eq!(v["tip_rep_today"], json!(0));
+    }
 }
@@ -0,0 +1,265 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/* Schema versions for persisted data
+ *
+ * Every persisted file carries a "schema_version" number. Files written before we had
+ * versions are treated as version 1. On load, the file is upgraded one step at a time
+ * by the migrations registered for that kind of data, before serde ever sees it.
+ *
+ * So, instead of sprinkling #[serde(default)] on new fields: add the field, bump the
+ * schema's current version, and add a migration that fills it in (see add_defaults).
+ * Renames and restructures work the same way.
+ *
+ * Files that are a map of records (v2_players.json, players.json) are stored as
+ * {"schema_version": N, "players": {...}} and the migrations run per record. Before
+ * versioning they were just the bare map.
+ */
+
+pub const VERSION_KEY: &str = "schema_version";
+pub const MAP_KEY: &str = "players";
+
+pub struct Migration {
+    pub from: u32,
+    pub description: &'static str,
+    pub apply: fn(&mut Value) -> Result<(), String>,
+}
+
+pub struct Schema {
+    pub name: &'static str,
+    pub current: u32,
+    pub migrations: &'static [Migration],
+}
+
+impl Schema {
+    /// Migrations that would run to bring data at `from` up to date.
+    pub fn pending(&self, from: u32) -> impl Iterator<Item = &Migration> {
+        let current = self.current;
+        (from..current).filter_map(move |v| self.migrations.iter().find(|m| m.from == v))
+    }
+
+    /// Upgrades unversioned data (e.g. a single record) that is known to be at `from`.
+    pub fn upgrade_from(&self, from: u32, value: &mut Value) -> Result<Vec<&'static str>, String> {
+        if from > self.current {
+            return Err(format!(
+                "{} data is schema v{}, but this build only knows up to v{}",
+                self.name, from, self.current
+            ));
+        }
+        let mut applied = Vec::new();
+        for v in from..self.current {
+            let m = self
+                .migrations
+                .iter()
+                .find(|m| m.from == v)
+                .ok_or_else(|| format!("{} has no migration from v{}", self.name, v))?;
+            (m.apply)(value).map_err(|e| format!("{} v{} -> v{}: {}", self.name, v, v + 1, e))?;
+            applied.push(m.description);
+        }
+        Ok(applied)
+    }
+
+    /// Upgrades an object that carries its own schema_version, and stamps the new version on it.
+    pub fn upgrade(&self, value: &mut Value) -> Result<Vec<&'static str>, String> {
+        let from = version_of(value);
+        let applied = self.upgrade_from(from, value)?;
+        value
+            .as_object_mut()
+            .ok_or_else(|| format!("{} data is not an object", self.name))?
+            .insert(VERSION_KEY.to_string(), Value::from(self.current));
+        Ok(applied)
+    }
+
+    /// Upgrades a map file (versioned or bare) and returns the records, ready for serde.
+    pub fn upgrade_map(&self, value: Value) -> Result<(Map<String, Value>, u32), String> {
+        let (from, mut records) = split_map_file(value)?;
+        for (name, record) in records.iter_mut() {
+            self.upgrade_from(from, record)
+                .map_err(|e| format!("record {}: {}", name, e))?;
+        }
+        Ok((records, from))
+    }
+}
+
+pub fn version_of(value: &Value) -> u32 {
+    value
+        .get(VERSION_KEY)
+        .and_then(Value::as_u64)
+        .map(|v| v as u32)
+        .unwrap_or(1)
+}
+
+fn split_map_file(value: Value) -> Result<(u32, Map<String, Value>), String> {
+    let mut obj = match value {
+        Value::Object(obj) => obj,
+        _ => return Err("expected a JSON object".to_string()),
+    };
+    if !obj.contains_key(VERSION_KEY) {
+        return Ok((1, obj));
+    }
+    let from = version_of(&Value::Object(obj.clone()));
+    match obj.remove(MAP_KEY) {
+        Some(Value::Object(records)) => Ok((from, records)),
+        _ => Err(format!("expected a \"{}\" object", MAP_KEY)),
+    }
+}
+
+/// The on-disk shape of a versioned map file.
+#[derive(Serialize)]
+pub struct MapFile<'a, T: Serialize> {
+    pub schema_version: u32,
+    pub players: &'a T,
+}
+
+/// Migration helper: sets any of `fields` that are missing on the object.
+pub fn add_defaults(value: &mut Value, fields: &[(&str, Value)]) -> Result<(), String> {
+    let obj = value.as_object_mut().ok_or("expected a JSON object")?;
+    for (k, v) in fields {
+        obj.entry(k.to_string()).or_insert_with(|| v.clone());
+    }
+    Ok(())
+}
+
+/// Loads a versioned JSON file (an object carrying its own schema_version) and upgrades it.
+/// Returns None if the file doesn't exist yet. On an error, callers shouldn't save over the
+/// file, so it can be fixed by hand.
+pub fn load_versioned<T: DeserializeOwned>(
+    path: &Path,
+    schema: &Schema,
+) -> Result<Option<T>, String> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut value: Value =
+        serde_json::from_reader(BufReader::new(file)).map_err(|e| e.to_string())?;
+    schema.upgrade(&mut value)?;
+    serde_json::from_value(value)
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+/// Describes what loading `path` would change, without writing anything.
+pub fn check_json_file(path: &Path, schema: &Schema, is_map: bool) -> String {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(_) => return format!("{}: not found, skipping", path.display()),
+    };
+    let value: Value = match serde_json::from_str(&contents) {
+        Ok(v) => v,
+        Err(e) => return format!("{}: INVALID JSON: {}", path.display(), e),
+    };
+    let (from, records) = if is_map {
+        match split_map_file(value) {
+            Ok((from, records)) => (from, records.len()),
+            Err(e) => return format!("{}: INVALID: {}", path.display(), e),
+        }
+    } else {
+        (version_of(&value), 1)
+    };
+    describe(&path.display().to_string(), schema, from, records)
+}
+
+/// Describes an append-only JSON lines journal (ledger, hall of fame) for --check-data. Only
+/// the header line carries the schema version.
+pub fn check_journal(path: &Path, schema: &Schema) -> String {
+    let header = File::open(path).ok().and_then(|f| {
+        BufReader::new(f)
+            .lines()
+            .next()
+            .and_then(|l| l.ok())
+            .and_then(|l| serde_json::from_str::<Value>(&l).ok())
+    });
+    match header {
+        Some(h) => describe(&path.display().to_string(), schema, version_of(&h), 0),
+        None => format!("{}: not found, skipping", path.display()),
+    }
+}
+
+pub fn describe(what: &str, schema: &Schema, from: u32, records: usize) -> String {
+    if from == schema.current {
+        return format!("{}: up to date ({} v{})", what, schema.name, from);
+    }
+    if from > schema.current {
+        return format!(
+            "{}: NEWER than this build ({} v{} > v{})",
+            what, schema.name, from, schema.current
+        );
+    }
+    let steps = schema
+        .pending(from)
+        .map(|m| format!("v{}->v{}: {}", m.from, m.from + 1, m.description))
+        .collect::<Vec<_>>()
+        .join("; ");
+    format!(
+        "{}: would upgrade {} record(s) from {} v{} to v{} ({})",
+        what, records, schema.name, from, schema.current, steps
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rename_a(v: &mut Value) -> Result<(), String> {
+        let obj = v.as_object_mut().ok_or("not an object")?;
+        let a = obj.remove("a").unwrap_or(Value::Null);
+        obj.insert("b".to_string(), a);
+        Ok(())
+    }
+
+    fn add_c(v: &mut Value) -> Result<(), String> {
+        add_defaults(v, &[("c", json!(0))])
+    }
+
+    const TEST_SCHEMA: Schema = Schema {
+        name: "test",
+        current: 3,
+        migrations: &[
+            Migration {
+                from: 1,
+                description: "rename a to b",
+                apply: rename_a,
+            },
+            Migration {
+                from: 2,
+                description: "add c",
+                apply: add_c,
+            },
+        ],
+    };
+
+    #[test]
+    fn upgrades_unversioned_objects() {
+        let mut v = json!({"a": 5});
+        let applied = TEST_SCHEMA.upgrade(&mut v).unwrap();
+        assert_eq!(applied, vec!["rename a to b", "add c"]);
+        assert_eq!(v, json!({"b": 5, "c": 0, "schema_version": 3}));
+    }
+
+    #[test]
+    fn skips_applied_steps() {
+        let mut v = json!({"b": 5, "schema_version": 2});
+        assert_eq!(TEST_SCHEMA.upgrade(&mut v).unwrap(), vec!["add c"]);
+        assert!(TEST_SCHEMA.upgrade(&mut v).unwrap().is_empty());
+        let mut newer = json!({"schema_version": 4});
+        assert!(TEST_SCHEMA.upgrade(&mut newer).is_err());
+    }
+
+    #[test]
+    fn upgrades_bare_and_versioned_maps() {
+        let (records, from) = TEST_SCHEMA.upgrade_map(json!({"x": {"a": 1}})).unwrap();
+        assert_eq!(from, 1);
+        assert_eq!(records["x"], json!({"b": 1, "c": 0}));
+
+        let file = json!({"schema_version": 2, "players": {"x": {"b": 1}}});
+        let (records, from) = TEST_SCHEMA.upgrade_map(file).unwrap();
+        assert_eq!(from, 2);
+        assert_eq!(records["x"], json!({"b": 1, "c": 0}));
+    }
+}
@@ -0,0 +1,242 @@
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::db::player::Player;
+use crate::export::{self, Format};
+use crate::stat_registry::{registry, Sources};
+
+/* Stat snapshots
+ *
+ * Players only keep their current stats, so once a day every registered stat of every player
+ * (who hasn't opted out) is copied into the stat_snapshots table of the player database.
+ * Exported with !exportstats (or --export-stats), that's the history of how averages, file
+ * counts and everything else changed over time.
+ */
+
+pub const SNAPSHOT_INTERVAL: u64 = 60 * 60 * 24;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Row {
+    pub time: u64,
+    pub key: String,
+    pub name: String, // what they were called at the time
+    pub stat: String,
+    pub value: f64,
+}
+
+pub struct Snapshots {
+    conn: Connection,
+    last: u64, // time of the latest snapshot
+}
+
+impl Snapshots {
+    pub fn open(path: &Path) -> Snapshots {
+        let conn = Connection::open(path)
+            .and_then(|c| Self::create(&c).map(|_| c))
+            .unwrap_or_else(|e| {
+                println!(
+                    "[ERROR] Couldn't open stat snapshots in {}, keeping them in memory: {}",
+                    path.display(),
+                    e
+                );
+                Self::memory()
+            });
+        let last = conn
+            .query_row("SELECT MAX(time) FROM stat_snapshots", [], |r| {
+                r.get::<_, Option<i64>>(0)
+            })
+            .ok()
+            .flatten()
+            .unwrap_or(0) as u64;
+        Snapshots { conn, last }
+    }
+
+    pub fn in_memory() -> Snapshots {
+        Snapshots {
+            conn: Self::memory(),
+            last: 0,
+        }
+    }
+
+    fn memory() -> Connection {
+        let conn = Connection::open_in_memory().expect("Could not open in-memory database.");
+        Self::create(&conn).expect("Could not create stat snapshots table.");
+        conn
+    }
+
+    fn create(conn: &Connection) -> rusqlite::Result<()> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS stat_snapshots (
+                time INTEGER NOT NULL,
+                key TEXT NOT NULL,
+                name TEXT NOT NULL,
+                stat TEXT NOT NULL,
+                value REAL NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS stat_snapshots_by_key ON stat_snapshots (key, time);",
+        )
+    }
+
+    /// Snapshots every registered stat of every player. Returns how many values were saved.
+    pub fn take(
+        &mut self,
+        sources: &Sources,
+        players: &HashMap<String, Player>,
+        now: u64,
+    ) -> rusqlite::Result<usize> {
+        let tx = self.conn.transaction()?;
+        let mut saved = 0;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO stat_snapshots (time, key, name, stat, value)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for (key, p) in players.iter().filter(|(_, p)| !p.opted_out) {
+                let name = p.name();
+                for stat in registry().stats() {
+                    if let Some(v) = (stat.value)(sources, p) {
+                        stmt.execute(params![now as i64, key, name, stat.name, v])?;
+                        saved += 1;
+                    }
+                }
+            }
+        }
+        tx.commit()?;
+        self.last = now;
+        Ok(saved)
+    }
+
+    /// Takes a snapshot if the last one is more than a day old.
+    pub fn take_if_due(&mut self, sources: &Sources, players: &HashMap<String, Player>, now: u64) {
+        if now < self.last + SNAPSHOT_INTERVAL {
+            return;
+        }
+        match self.take(sources, players, now) {
+            Ok(n) => println!("Saved a stat snapshot ({} values).", n),
+            Err(e) => println!("[ERROR] Couldn't save a stat snapshot: {}", e),
+        }
+    }
+
+    /// How many snapshot values are stored for a player.
+    pub fn count(&self, key: &str) -> rusqlite::Result<i64> {
+        self.conn.query_row(
+            "SELECT COUNT(*) FROM stat_snapshots WHERE key = ?1",
+            params![key],
+            |r| r.get(0),
+        )
+    }
+
+    /// Deletes every snapshot of a player. Returns how many values were deleted.
+    pub fn forget(&self, key: &str) -> rusqlite::Result<usize> {
+        self.conn
+            .execute("DELETE FROM stat_snapshots WHERE key = ?1", params![key])
+    }
+
+    /// Every snapshot of `player` (a key or a name), or of everyone, oldest first.
+    pub fn rows(&self, player: Option<&str>) -> rusqlite::Result<Vec<Row>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT time, key, name, stat, value FROM stat_snapshots
+             WHERE ?1 IS NULL OR key = ?1 OR lower(name) = lower(?1)
+             ORDER BY time, key, stat",
+        )?;
+        let rows = stmt
+            .query_map(params![player], |r| {
+                Ok(Row {
+                    time: r.get::<_, i64>(0)? as u64,
+                    key: r.get(1)?,
+                    name: r.get(2)?,
+                    stat: r.get(3)?,
+                    value: r.get(4)?,
+                })
+            })?
+            .collect();
+        rows
+    }
+
+    /// !exportstats [user] [json | csv], and --export-stats. Writes to `dir`.
+    pub fn export(&self, args: &str, dir: &Path) -> Result<String, String> {
+        let mut format = Format::Json;
+        let mut player = None;
+        for word in args.split_whitespace() {
+            match Format::parse(word) {
+                Some(f) => format = f,
+                None => player = Some(word.trim_start_matches('@')),
+            }
+        }
+        let rows = self.rows(player).map_err(|e| e.to_string())?;
+        if rows.is_empty() {
+            return Err("There aren't any snapshots to export yet.".to_string());
+        }
+        let name = match player {
+            Some(p) => format!("snapshots_{}", p.to_lowercase()),
+            None => "snapshots".to_string(),
+        };
+        let csv = || {
+            let lines = rows
+                .iter()
+                .map(|r| {
+                    vec![
+                        r.time.to_string(),
+                        r.key.clone(),
+                        r.name.clone(),
+                        r.stat.clone(),
+                        r.value.to_string(),
+                    ]
+                })
+                .collect::<Vec<_>>();
+            export::to_csv(&["time", "key", "name", "stat", "value"], &lines)
+        };
+        let path = export::write(dir, &name, format, &rows, csv)?;
+        Ok(format!(
+            "Exported {} snapshot values to {}.",
+            rows.len(),
+            path.display()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persist::TestDir;
+
+    #[test]
+    fn snapshots_and_exports() {
+        let mut players = HashMap::new();
+        let mut p = Player::new("mjb".to_string());
+        p.tridents_rolled = 2;
+        p.trident_acc = 300;
+        players.insert("1".to_string(), p);
+        players.insert("2".to_string(), Player::new("pac".to_string()));
+
+        let mut snaps = Snapshots::in_memory();
+        let src = Sources::default();
+        snaps.take_if_due(&src, &players, SNAPSHOT_INTERVAL);
+        players.get_mut("1").unwrap().trident_acc = 400;
+        // Too soon for another one.
+        snaps.take_if_due(&src, &players, SNAPSHOT_INTERVAL + 10);
+        snaps.take_if_due(&src, &players, 2 * SNAPSHOT_INTERVAL);
+
+        let average = snaps
+            .rows(Some("MJB"))
+            .unwrap()
+            .into_iter()
+            .filter(|r| r.stat == "average_trident")
+            .map(|r| r.value)
+            .collect::<Vec<_>>();
+        assert_eq!(average, vec![150.0, 200.0]);
+        assert!(snaps.rows(None).unwrap().iter().any(|r| r.key == "2"));
+
+        let dir = TestDir::new("snaps");
+        snaps.export("mjb csv", &dir).unwrap();
+        let csv = std::fs::read_to_string(dir.join("snapshots_mjb.csv")).unwrap();
+        assert!(csv.starts_with("time,key,name,stat,value\n"));
+        assert!(csv.contains(&format!(
+            "{},1,mjb,average_trident,150\n",
+            SNAPSHOT_INTERVAL
+        )));
+        assert!(snaps.export("nobody", &dir).is_err());
+    }
+}
@@ -0,0 +1,331 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use std::cell::Cell;
+use std::path::Path;
+
+/* Stat events
+ *
+ * Player stats only keep lifetime totals and bests, so every roll is also recorded here with
+ * the time it happened. That lets leaderboards rank players within a window (today, this week,
+ * this stream) instead of only over all time.
+ *
+ * Events live in the stat_events table of the player database.
+ *
+ * The start of the current stream is kept next to them (in stat_meta), so reconnecting or
+ * restarting the bot doesn't start a new stream. A new stream starts when a mod says so with
+ * !streamstart, or by itself when nothing has been rolled for STREAM_GAP.
+ */
+
+// Event names.
+pub const TRIDENT: &str = "trident";
+pub const GUNPOWDER: &str = "gunpowder";
+pub const D20: &str = "d20";
+pub const DEATH: &str = "death";
+pub const YAHTZEE: &str = "yahtzee";
+
+const DAY: u64 = 60 * 60 * 24;
+pub const STREAM_GAP: u64 = 60 * 60 * 6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Window {
+    Today,
+    Week,
+    Stream,
+}
+
+impl Window {
+    pub fn parse(s: &str) -> Option<Window> {
+        match s.to_lowercase().as_str() {
+            "today" | "day" | "daily" => Some(Window::Today),
+            "week" | "weekly" => Some(Window::Week),
+            "stream" => Some(Window::Stream),
+            _ => None,
+        }
+    }
+
+    /// Start of the window. Days are UTC days.
+    pub fn since(&self, now: u64, stream_start: u64) -> u64 {
+        match self {
+            Window::Today => now - now % DAY,
+            Window::Week => now.saturating_sub(7 * DAY),
+            Window::Stream => stream_start,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Window::Today => "today",
+            Window::Week => "this week",
+            Window::Stream => "this stream",
+        }
+    }
+}
+
+/// The event and SQL aggregate behind each windowed !anylb leaderboard, by stat name.
+pub fn windowed(leaderboard: &str) -> Option<(&'static str, &'static str)> {
+    match leaderboard {
+        "trident" => Some((TRIDENT, "MAX(value)")),
+        "rolled_tridents" => Some((TRIDENT, "COUNT(*)")),
+        "gunpowder" => Some((GUNPOWDER, "MAX(value)")),
+        "d20" => Some((D20, "SUM(value = 20)")),
+        "fumbles" => Some((D20, "SUM(value = 1)")),
+        "deaths" => Some((DEATH, "COUNT(*)")),
+        "yahtzee" => Some((YAHTZEE, "COUNT(*)")),
+        _ => None,
+    }
+}
+
+pub struct StatLog {
+    conn: Connection,
+    stream_start: Cell<u64>,
+    last_event: Cell<u64>,
+}
+
+impl StatLog {
+    pub fn open(path: &Path, now: u64) -> StatLog {
+        let conn = Connection::open(path)
+            .and_then(|c| Self::create(&c).map(|_| c))
+            .unwrap_or_else(|e| {
+                println!(
+                    "[ERROR] Couldn't open stat events in {}, keeping them in memory: {}",
+                    path.display(),
+                    e
+                );
+                Self::memory()
+            });
+        Self::resume(conn, now)
+    }
+
+    pub fn in_memory(now: u64) -> StatLog {
+        Self::resume(Self::memory(), now)
+    }
+
+    // Carries on with the stored stream, unless it's been quiet for too long.
+    fn resume(conn: Connection, now: u64) -> StatLog {
+        let stored = |sql: &str| -> Option<u64> {
+            conn.query_row(sql, [], |r| r.get::<_, Option<i64>>(0))
+                .optional()
+                .unwrap_or_else(|e| {
+                    println!("[ERROR] Couldn't read the stream start: {}", e);
+                    None
+                })
+                .flatten()
+                .map(|t| t as u64)
+        };
+        let start = stored("SELECT value FROM stat_meta WHERE name = 'stream_start'");
+        let last_event = stored("SELECT MAX(time) FROM stat_events").unwrap_or(0);
+        let log = StatLog {
+            conn,
+            stream_start: Cell::new(start.unwrap_or(now)),
+            last_event: Cell::new(last_event),
+        };
+        if start.is_none() || now.saturating_sub(last_event.max(log.stream_start())) > STREAM_GAP {
+            log.start_stream(now);
+        }
+        log
+    }
+
+    fn memory() -> Connection {
+        let conn = Connection::open_in_memory().expect("Could not open in-memory database.");
+        Self::create(&conn).expect("Could not create stat events table.");
+        conn
+    }
+
+    fn create(conn: &Connection) -> rusqlite::Result<()> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS stat_events (
+                time INTEGER NOT NULL,
+                key TEXT NOT NULL,
+                stat TEXT NOT NULL,
+                value INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS stat_events_by_time ON stat_events (stat, time);
+            CREATE TABLE IF NOT EXISTS stat_meta (
+                name TEXT PRIMARY KEY,
+                value INTEGER NOT NULL
+            );",
+        )
+    }
+
+    pub fn stream_start(&self) -> u64 {
+        self.stream_start.get()
+    }
+
+    /// Starts a new stream for the "this stream" leaderboards.
+    pub fn start_stream(&self, now: u64) {
+        self.stream_start.set(now);
+        if let Err(e) = self.conn.execute(
+            "INSERT OR REPLACE INTO stat_meta (name, value) VALUES ('stream_start', ?1)",
+            params![now as i64],
+        ) {
+            println!("[ERROR] Couldn't save the stream start: {}", e);
+        }
+    }
+
+    pub fn record(&self, key: &str, stat: &str, value: i64, now: u64) {
+        if now.saturating_sub(self.last_event.get().max(self.stream_start())) > STREAM_GAP {
+            self.start_stream(now);
+        }
+        self.last_event.set(self.last_event.get().max(now));
+        if let Err(e) = self.conn.execute(
+            "INSERT INTO stat_events (time, key, stat, value) VALUES (?1, ?2, ?3, ?4)",
+            params![now as i64, key, stat, value],
+        ) {
+            println!("[ERROR] Couldn't record {} for {}: {}", stat, key, e);
+        }
+    }
+
+    /// Moves the events of one player onto another, when players are merged.
+    pub fn merge(&self, from: &str, into: &str) {
+        if let Err(e) = self.conn.execute(
+            "UPDATE stat_events SET key = ?2 WHERE key = ?1",
+            params![from, into],
+        ) {
+            println!("[ERROR] Couldn't merge stat events of {}: {}", from, e);
+        }
+    }
+
+    /// How many events are recorded for a player.
+    pub fn count(&self, key: &str) -> rusqlite::Result<i64> {
+        self.conn.query_row(
+            "SELECT COUNT(*) FROM stat_events WHERE key = ?1",
+            params![key],
+            |r| r.get(0),
+        )
+    }
+
+    /// Deletes every event of a player. Returns how many were deleted.
+    pub fn forget(&self, key: &str) -> rusqlite::Result<usize> {
+        self.conn
+            .execute("DELETE FROM stat_events WHERE key = ?1", params![key])
+    }
+
+    /// (key, value) for the best `limit` players on a windowed leaderboard, best first.
+    pub fn top(
+        &self,
+        leaderboard: &str,
+        window: Window,
+        now: u64,
+        limit: usize,
+    ) -> rusqlite::Result<Vec<(String, i64)>> {
+        self.top_since(leaderboard, window.since(now, self.stream_start()), limit)
+    }
+
+    pub fn top_since(
+        &self,
+        leaderboard: &str,
+        since: u64,
+        limit: usize,
+    ) -> rusqlite::Result<Vec<(String, i64)>> {
+        let (stat, aggregate) = match windowed(leaderboard) {
+            Some(w) => w,
+            None => return Ok(Vec::new()),
+        };
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT key, {} AS v FROM stat_events WHERE stat = ?1 AND time >= ?2
+             GROUP BY key HAVING v > 0 ORDER BY v DESC, key LIMIT ?3",
+            aggregate
+        ))?;
+        let rows = stmt
+            .query_map(params![stat, since as i64, limit as i64], |r| {
+                Ok((r.get(0)?, r.get(1)?))
+            })?
+            .collect();
+        rows
+    }
+
+    /// A single player's value on a windowed leaderboard, if they have one.
+    pub fn value_since(
+        &self,
+        leaderboard: &str,
+        key: &str,
+        since: u64,
+    ) -> rusqlite::Result<Option<i64>> {
+        let (stat, aggregate) = match windowed(leaderboard) {
+            Some(w) => w,
+            None => return Ok(None),
+        };
+        self.conn.query_row(
+            &format!(
+                "SELECT {} FROM stat_events WHERE stat = ?1 AND key = ?2 AND time >= ?3",
+                aggregate
+            ),
+            params![stat, key, since as i64],
+            |r| r.get::<_, Option<i64>>(0),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persist::TestDir;
+
+    #[test]
+    fn windows() {
+        let now = 10 * DAY + 500;
+        assert_eq!(Window::parse("Today"), Some(Window::Today));
+        assert_eq!(Window::parse("forever"), None);
+        assert_eq!(Window::Today.since(now, 0), 10 * DAY);
+        assert_eq!(Window::Week.since(now, 0), 3 * DAY + 500);
+        assert_eq!(Window::Stream.since(now, 42), 42);
+    }
+
+    #[test]
+    fn ranks_within_window() {
+        let now = 10 * DAY;
+        let log = StatLog::in_memory(now);
+        log.record("old", TRIDENT, 250, now - 2 * DAY);
+        log.record("a", TRIDENT, 100, now + 10);
+        log.record("a", TRIDENT, 50, now + 20);
+        log.record("b", TRIDENT, 120, now + 30);
+        log.record("b", D20, 20, now + 30);
+        log.record("b", D20, 1, now + 30);
+
+        let top = |lb, w| log.top(lb, w, now + 60, 10).unwrap();
+        assert_eq!(
+            top("trident", Window::Today),
+            vec![("b".to_string(), 120), ("a".to_string(), 100)]
+        );
+        assert_eq!(top("trident", Window::Week)[0], ("old".to_string(), 250));
+        assert_eq!(
+            top("rolled_tridents", Window::Today)[0],
+            ("a".to_string(), 2)
+        );
+        assert_eq!(top("d20", Window::Stream), vec![("b".to_string(), 1)]);
+        assert!(top("deaths", Window::Week).is_empty());
+
+        assert_eq!(log.value_since("trident", "a", now).unwrap(), Some(100));
+        assert_eq!(log.value_since("trident", "c", now).unwrap(), None);
+
+        assert_eq!(log.count("a").unwrap(), 2);
+        log.merge("a", "b");
+        assert_eq!(
+            top("rolled_tridents", Window::Today),
+            vec![("b".to_string(), 3)]
+        );
+        assert_eq!(log.forget("b").unwrap(), 5);
+        assert!(top("trident", Window::Week).iter().all(|(k, _)| k != "b"));
+    }
+
+    #[test]
+    fn streams_survive_reconnects() {
+        let dir = TestDir::new("stats");
+        let path = dir.join("stats.db");
+        let log = StatLog::open(&path, 1000);
+        log.record("a", TRIDENT, 100, 2000);
+        drop(log);
+
+        // Reconnecting during the stream keeps it going.
+        let log = StatLog::open(&path, 3000);
+        assert_eq!(log.stream_start(), 1000);
+        // A quiet spell starts a new one.
+        log.record("a", TRIDENT, 100, 3000 + STREAM_GAP + 1);
+        assert_eq!(log.stream_start(), 3000 + STREAM_GAP + 1);
+        log.start_stream(50000);
+        drop(log);
+
+        assert_eq!(StatLog::open(&path, 50001).stream_start(), 50000);
+        let later = 50000 + STREAM_GAP + 1;
+        assert_eq!(StatLog::open(&path, later).stream_start(), later);
+    }
+}
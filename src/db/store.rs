@@ -0,0 +1,317 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use super::player::{get_players, save_players, Player, PLAYER_SCHEMA};
+use super::schema;
+
+/* Player storage backends
+ *
+ * PlayerData keeps every player in memory, but only the players that were touched since the
+ * last save are handed to the store as "dirty". The JSON backend ignores that and rewrites
+ * the whole file (it's kept around for tests and for poking at data by hand), while the
+ * SQLite backend only upserts the dirty rows.
+ *
+ * Rows are stored as the JSON serialization of Player, so adding fields doesn't need a
+ * table migration. The player schema version of the rows lives in PRAGMA user_version, and
+ * old rows are upgraded in place when the database is opened.
+ */
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Sqlite(rusqlite::Error),
+    Schema(String),
+}
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::Io(e) => write!(f, "io error: {}", e),
+            StoreError::Json(e) => write!(f, "json error: {}", e),
+            StoreError::Sqlite(e) => write!(f, "sqlite error: {}", e),
+            StoreError::Schema(e) => write!(f, "schema error: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for StoreError {
+    fn from(e: std::io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for StoreError {
+    fn from(e: serde_json::Error) -> Self {
+        StoreError::Json(e)
+    }
+}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError::Sqlite(e)
+    }
+}
+
+pub trait PlayerStore {
+    fn load_all(&mut self) -> Result<HashMap<String, Player>, StoreError>;
+
+    /// Persists the players named in `dirty`, removing any of them that are no longer in
+    /// `players`. Backends are free to write more than that.
+    fn save(
+        &mut self,
+        players: &HashMap<String, Player>,
+        dirty: &HashSet<String>,
+    ) -> Result<(), StoreError>;
+}
+
+pub struct JsonPlayerStore {
+    path: PathBuf,
+}
+
+impl JsonPlayerStore {
+    pub fn new(path: &Path) -> JsonPlayerStore {
+        JsonPlayerStore {
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+impl PlayerStore for JsonPlayerStore {
+    fn load_all(&mut self) -> Result<HashMap<String, Player>, StoreError> {
+        Ok(get_players(&self.path))
+    }
+
+    fn save(
+        &mut self,
+        players: &HashMap<String, Player>,
+        _dirty: &HashSet<String>,
+    ) -> Result<(), StoreError> {
+        match save_players(players, &self.path) {
+            true => Ok(()),
+            false => Err(StoreError::Io(std::io::Error::other(format!(
+                "could not save {}",
+                self.path.display()
+            )))),
+        }
+    }
+}
+
+pub struct SqlitePlayerStore {
+    conn: Connection,
+}
+
+impl SqlitePlayerStore {
+    pub fn open(path: &Path) -> Result<SqlitePlayerStore, StoreError> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<SqlitePlayerStore, StoreError> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(conn: Connection) -> Result<SqlitePlayerStore, StoreError> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS players (
+                key TEXT PRIMARY KEY NOT NULL,
+                data TEXT NOT NULL
+            );",
+        )?;
+        let mut store = SqlitePlayerStore { conn };
+        store.upgrade_rows()?;
+        Ok(store)
+    }
+
+    // Databases from before versioning have user_version 0 and hold v1 rows.
+    fn row_version(&self) -> Result<u32, StoreError> {
+        let v: u32 = self
+            .conn
+            .query_row("PRAGMA user_version", [], |r| r.get(0))?;
+        Ok(if v == 0 && self.is_empty()? {
+            PLAYER_SCHEMA.current
+        } else {
+            v.max(1)
+        })
+    }
+
+    fn upgrade_rows(&mut self) -> Result<(), StoreError> {
+        let from = self.row_version()?;
+        let tx = self.conn.transaction()?;
+        if from != PLAYER_SCHEMA.current {
+            let mut stmt = tx.prepare("SELECT key, data FROM players")?;
+            let rows = stmt
+                .query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut update = tx.prepare("UPDATE players SET data = ?2 WHERE key = ?1")?;
+            for (key, data) in rows {
+                let mut value = serde_json::from_str(&data)?;
+                PLAYER_SCHEMA
+                    .upgrade_from(from, &mut value)
+                    .map_err(|e| StoreError::Schema(format!("player {}: {}", key, e)))?;
+                update.execute(params![key, value.to_string()])?;
+            }
+        }
+        tx.pragma_update(None, "user_version", PLAYER_SCHEMA.current)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Describes what opening the database at `path` would upgrade, without writing anything.
+    pub fn check(path: &Path) -> String {
+        if !path.exists() {
+            return format!("{}: not found, skipping", path.display());
+        }
+        let res = Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(StoreError::from)
+            .and_then(|conn| {
+                let store = SqlitePlayerStore { conn };
+                let records: i64 =
+                    store
+                        .conn
+                        .query_row("SELECT COUNT(*) FROM players", [], |r| r.get(0))?;
+                Ok((store.row_version()?, records as usize))
+            });
+        match res {
+            Ok((from, records)) => {
+                schema::describe(&path.display().to_string(), &PLAYER_SCHEMA, from, records)
+            }
+            Err(e) => format!("{}: INVALID: {}", path.display(), e),
+        }
+    }
+
+    pub fn is_empty(&self) -> Result<bool, StoreError> {
+        let any: Option<i64> = self
+            .conn
+            .query_row("SELECT 1 FROM players LIMIT 1", [], |r| r.get(0))
+            .optional()?;
+        Ok(any.is_none())
+    }
+
+    /// One-shot import of a v2_players.json file. Only runs against an empty database, and
+    /// renames the JSON file afterwards so that it can't be imported twice.
+    pub fn migrate_from_json(&mut self, json_path: &Path) -> Result<usize, StoreError> {
+        if !json_path.exists() || !self.is_empty()? {
+            return Ok(0);
+        }
+        let players = get_players(json_path);
+        let keys: HashSet<String> = players.keys().cloned().collect();
+        self.save(&players, &keys)?;
+        let mut migrated = json_path.as_os_str().to_os_string();
+        migrated.push(".migrated");
+        std::fs::rename(json_path, migrated)?;
+        Ok(players.len())
+    }
+}
+
+impl PlayerStore for SqlitePlayerStore {
+    fn load_all(&mut self) -> Result<HashMap<String, Player>, StoreError> {
+        let mut stmt = self.conn.prepare("SELECT key, data FROM players")?;
+        let rows = stmt.query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)))?;
+        let mut players = HashMap::new();
+        for row in rows {
+            let (key, data) = row?;
+            players.insert(key, serde_json::from_str(&data)?);
+        }
+        Ok(players)
+    }
+
+    fn save(
+        &mut self,
+        players: &HashMap<String, Player>,
+        dirty: &HashSet<String>,
+    ) -> Result<(), StoreError> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut upsert = tx.prepare(
+                "INSERT INTO players (key, data) VALUES (?1, ?2)
+                 ON CONFLICT(key) DO UPDATE SET data = excluded.data",
+            )?;
+            let mut delete = tx.prepare("DELETE FROM players WHERE key = ?1")?;
+            for key in dirty {
+                match players.get(key) {
+                    Some(player) => upsert.execute(params![key, serde_json::to_string(player)?])?,
+                    None => delete.execute(params![key])?,
+                };
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persist::TestDir;
+
+    fn players(names: &[&str]) -> HashMap<String, Player> {
+        names
+            .iter()
+            .map(|n| (n.to_string(), Player::new(n.to_string())))
+            .collect()
+    }
+
+    fn keys(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn sqlite_only_writes_dirty_players() {
+        let mut store = SqlitePlayerStore::open_in_memory().unwrap();
+        let mut ps = players(&["mjb", "zayd"]);
+        store.save(&ps, &keys(&["mjb"])).unwrap();
+        assert_eq!(store.load_all().unwrap().len(), 1);
+
+        ps.get_mut("mjb").unwrap().files = 5;
+        store.save(&ps, &keys(&["mjb", "zayd"])).unwrap();
+        let loaded = store.load_all().unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded["mjb"].files, 5);
+    }
+
+    #[test]
+    fn migrates_json_once() {
+        let dir = TestDir::new("store");
+        let json = dir.join("v2_players.json");
+
+        let mut js = JsonPlayerStore::new(&json);
+        let ps = players(&["mjb", "zayd", "pacmanmvc"]);
+        js.save(&ps, &HashSet::new()).unwrap();
+        assert_eq!(js.load_all().unwrap().len(), 3);
+
+        let mut store = SqlitePlayerStore::open(&dir.join("folderbot.db")).unwrap();
+        assert_eq!(store.migrate_from_json(&json).unwrap(), 3);
+        assert!(!json.exists());
+        assert_eq!(store.load_all().unwrap().len(), 3);
+
+        // Second run is a no-op, even if the file comes back.
+        js.save(&players(&["someone_else"]), &HashSet::new())
+            .unwrap();
+        assert_eq!(store.migrate_from_json(&json).unwrap(), 0);
+        assert!(!store.load_all().unwrap().contains_key("someone_else"));
+    }
+
+    #[test]
+    fn upgrades_unversioned_rows() {
+        let dir = TestDir::new("store_unversioned");
+        let path = dir.join("unversioned.db");
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE players (key TEXT PRIMARY KEY NOT NULL, data TEXT NOT NULL);
+                 INSERT INTO players VALUES ('mjb', '{\"username\": \"mjb\", \"nick\": null,
+                    \"files\": 7, \"last_message\": 0, \"death\": null, \"sent_messages\": 0,
+                    \"sent_commands\": 0, \"trident_acc\": 0, \"max_trident\": 0,
+                    \"tridents_rolled\": 0}');",
+            )
+            .unwrap();
+        }
+        assert!(SqlitePlayerStore::check(&path).contains("would upgrade 1 record(s)"));
+
+        let mut store = SqlitePlayerStore::open(&path).unwrap();
+        assert_eq!(store.load_all().unwrap()["mjb"].files, 7);
+        drop(store);
+        assert!(SqlitePlayerStore::check(&path).contains("up to date"));
+    }
+}
@@ -0,0 +1,433 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::db::player::PlayerData;
+use crate::db::schema::{Schema, VERSION_KEY};
+use crate::reputation;
+
+pub mod rules;
+pub use rules::EconomyRules;
+
+/* Files economy
+ *
+ * Every change to a player's files goes through PlayerData::adjust, which records a
+ * Transaction in the ledger. The ledger is an append-only JSON lines file (ledger.jsonl):
+ * the first line is {"schema_version": N}, and every line after that is one transaction.
+ * It is only ever rewritten to purge a player (see privacy.rs), so it doubles as the audit
+ * trail for admin grants and revokes.
+ *
+ * Only the last few transactions per player are kept in memory, for !files history.
+ *
+ * How many files things are worth lives in economy.json, see rules.rs.
+ */
+
+pub const LEDGER_PATH: &str = "ledger.jsonl";
+// How many transactions per player are kept in memory, and shown by !files history.
+pub const HISTORY_LEN: usize = 5;
+
+pub const MIN_GIFT: i64 = 1;
+
+pub const LEDGER_SCHEMA: Schema = Schema {
+    name: "ledger",
+    current: 1,
+    migrations: &[],
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    Income,
+    LowRoll,
+    Bet,
+    BetPayout,
+    Refund,
+    Gift,
+    Grant,
+    Revoke,
+    Import,
+    Merge,
+    Purchase,
+    Quest,
+    Event,
+}
+
+impl std::fmt::Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Reason::Income => "chat income",
+            Reason::LowRoll => "low trident",
+            Reason::Bet => "bet",
+            Reason::BetPayout => "bet payout",
+            Reason::Refund => "bet refund",
+            Reason::Gift => "gift",
+            Reason::Grant => "admin grant",
+            Reason::Revoke => "admin revoke",
+            Reason::Import => "import",
+            Reason::Merge => "merge",
+            Reason::Purchase => "shop",
+            Reason::Quest => "quest reward",
+            Reason::Event => "trident event",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Transaction {
+    pub time: u64,
+    pub key: String, // player key
+    pub amount: i64,
+    pub balance: i64, // player's files after this transaction
+    pub reason: Reason,
+    // The other player (for gifts and merges) or the admin (for grants and revokes).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub counterparty: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub struct Ledger {
+    file: Option<File>,
+    path: Option<PathBuf>,
+    recent: HashMap<String, VecDeque<Transaction>>,
+}
+
+impl Ledger {
+    /// A ledger that isn't written anywhere, for tests.
+    pub fn in_memory() -> Ledger {
+        Ledger {
+            file: None,
+            path: None,
+            recent: HashMap::new(),
+        }
+    }
+
+    pub fn open(path: &Path) -> Ledger {
+        let mut ledger = Ledger::in_memory();
+        let is_new = !path.exists();
+        if !is_new {
+            if let Err(e) = ledger.read(path) {
+                println!("[ERROR] Couldn't read ledger {}: {}", path.display(), e);
+            }
+        }
+        match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(mut f) => {
+                if is_new {
+                    let header = format!("{{\"{}\":{}}}\n", VERSION_KEY, LEDGER_SCHEMA.current);
+                    if let Err(e) = f.write_all(header.as_bytes()) {
+                        println!("[ERROR] Couldn't write ledger header: {}", e);
+                    }
+                }
+                ledger.file = Some(f);
+                ledger.path = Some(path.to_path_buf());
+            }
+            Err(e) => println!(
+                "[ERROR] Couldn't open ledger {}, transactions won't be kept: {}",
+                path.display(),
+                e
+            ),
+        }
+        ledger
+    }
+
+    fn read(&mut self, path: &Path) -> Result<(), String> {
+        let mut lines = BufReader::new(File::open(path).map_err(|e| e.to_string())?).lines();
+        let header: serde_json::Value = match lines.next() {
+            Some(line) => serde_json::from_str(&line.map_err(|e| e.to_string())?)
+                .map_err(|e| e.to_string())?,
+            None => return Ok(()),
+        };
+        let version = crate::db::schema::version_of(&header);
+        for line in lines {
+            let line = line.map_err(|e| e.to_string())?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut value = serde_json::from_str(&line).map_err(|e| e.to_string())?;
+            LEDGER_SCHEMA.upgrade_from(version, &mut value)?;
+            self.remember(serde_json::from_value(value).map_err(|e| e.to_string())?);
+        }
+        Ok(())
+    }
+
+    fn remember(&mut self, t: Transaction) {
+        // Merges carry the absorbed player's history over to the surviving key.
+        if let (Reason::Merge, Some(from)) = (t.reason, &t.counterparty) {
+            if let Some(old) = self.recent.remove(from) {
+                let tail = self.recent.entry(t.key.clone()).or_default();
+                for o in old.into_iter().rev() {
+                    tail.push_front(o);
+                }
+            }
+        }
+        let tail = self.recent.entry(t.key.clone()).or_default();
+        tail.push_back(t);
+        while tail.len() > HISTORY_LEN {
+            tail.pop_front();
+        }
+    }
+
+    pub fn record(&mut self, t: Transaction) {
+        if let Some(f) = self.file.as_mut() {
+            let res = serde_json::to_string(&t)
+                .map_err(|e| e.to_string())
+                .and_then(|line| writeln!(f, "{}", line).map_err(|e| e.to_string()));
+            if let Err(e) = res {
+                println!("[ERROR] Couldn't write to ledger ({}): {:?}", e, t);
+            }
+        }
+        self.remember(t);
+    }
+
+    /// Removes every transaction of `key` and scrubs them from everyone else's, rewriting the file.
+    pub fn forget(&mut self, key: &str) -> Result<usize, String> {
+        self.recent.remove(key);
+        for t in self.recent.values_mut().flatten() {
+            if t.counterparty.as_deref() == Some(key) {
+                t.counterparty = None;
+            }
+        }
+        let path = match &self.path {
+            Some(p) => p.clone(),
+            None => return Ok(0),
+        };
+        let dropped = crate::persist::rewrite_journal(&path, |v| {
+            if v["counterparty"].as_str() == Some(key) {
+                v["counterparty"] = serde_json::Value::Null;
+            }
+            v["key"].as_str() != Some(key)
+        })
+        .map_err(|e| e.to_string())?;
+        // The old file was replaced, so appends have to go to the new one.
+        self.file = OpenOptions::new().append(true).open(&path).ok();
+        Ok(dropped)
+    }
+
+    /// The most recent transactions for a player, newest first.
+    pub fn history(&self, key: &str) -> impl Iterator<Item = &Transaction> {
+        self.recent
+            .get(key)
+            .into_iter()
+            .flat_map(|t| t.iter().rev())
+    }
+}
+
+/// Pays out chat income (and reputation for being around) if it has been long enough since
+/// the player last earned any.
+pub fn passive_income(
+    player_data: &mut PlayerData,
+    rules: &EconomyRules,
+    key: &String,
+    now: u64,
+) -> bool {
+    let p = player_data.player(key);
+    if now <= p.last_message + rules.income_interval {
+        return false;
+    }
+    p.last_message = now;
+    p.reputation += reputation::CHAT_REP;
+    player_data.adjust(key, rules.income_at(now), Reason::Income, None, None);
+    true
+}
+
+fn parse_amount(s: &str) -> Result<i64, String> {
+    match s.parse::<i64>() {
+        Ok(a) if a >= MIN_GIFT => Ok(a),
+        Ok(_) => Err(format!("The amount needs to be at least {}.", MIN_GIFT)),
+        Err(_) => Err(format!("'{}' isn't a number of files.", s)),
+    }
+}
+
+/// !give <user> <amount>
+pub fn give(player_data: &mut PlayerData, from_key: &String, args: &str) -> Result<String, String> {
+    let v: Vec<&str> = args.split_whitespace().collect();
+    if v.len() != 2 {
+        return Err("Usage: !give <user> <amount>".to_string());
+    }
+    let amount = parse_amount(v[1])?;
+    let to_key = player_data
+        .key_of(v[0].trim_start_matches('@'))
+        .ok_or_else(|| format!("I don't know anyone called {}.", v[0]))?;
+    if &to_key == from_key {
+        return Err("You can't give files to yourself, silly.".to_string());
+    }
+    let from = player_data.player(from_key);
+    if from.files < amount {
+        return Err(format!(
+            "{}, you only have {} files to give.",
+            from.name(),
+            from.files
+        ));
+    }
+    let from_name = from.name();
+    player_data.adjust(from_key, -amount, Reason::Gift, Some(&to_key), None);
+    reputation::earn_tip_rep(player_data.player(from_key), amount, now());
+    player_data.adjust(&to_key, amount, Reason::Gift, Some(from_key), None);
+    let to_name = player_data.players[&to_key].name();
+    Ok(format!(
+        "{} gave {} files to {}!",
+        from_name, amount, to_name
+    ))
+}
+
+/// !grant / !revoke <user> <amount> [reason...]. The admin is recorded as the counterparty.
+pub fn admin_adjust(
+    player_data: &mut PlayerData,
+    admin: &str,
+    args: &str,
+    revoke: bool,
+) -> Result<String, String> {
+    let v: Vec<&str> = args.splitn(3, ' ').collect();
+    if v.len() < 2 {
+        return Err("Usage: <user> <amount> [reason]".to_string());
+    }
+    let amount = parse_amount(v[1])?;
+    let key = player_data
+        .key_of(v[0].trim_start_matches('@'))
+        .ok_or_else(|| format!("I don't know anyone called {}.", v[0]))?;
+    let note = v
+        .get(2)
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty());
+    let (amount, reason) = match revoke {
+        true => (-amount, Reason::Revoke),
+        false => (amount, Reason::Grant),
+    };
+    let balance = player_data.adjust(&key, amount, reason, Some(admin), note.clone());
+    println!(
+        "[Audit] {} {} {} files for {} ({}). New balance: {}",
+        admin,
+        reason,
+        amount,
+        key,
+        note.as_deref().unwrap_or("no reason given"),
+        balance
+    );
+    Ok(format!(
+        "{} now has {} files ({:+}).",
+        player_data.players[&key].name(),
+        balance,
+        amount
+    ))
+}
+
+/// !files history [user]
+pub fn history(player_data: &PlayerData, key: &String) -> String {
+    let name = match player_data.players.get(key) {
+        Some(p) => p.name(),
+        None => return "I don't know anyone by that name.".to_string(),
+    };
+    let entries = player_data
+        .history(key)
+        .take(HISTORY_LEN)
+        .map(|t| {
+            let who = match (&t.counterparty, t.reason) {
+                (Some(c), Reason::Gift) => {
+                    let c = player_data
+                        .players
+                        .get(c)
+                        .map(|p| p.name())
+                        .unwrap_or(c.clone());
+                    format!(" {} {}", if t.amount < 0 { "to" } else { "from" }, c)
+                }
+                (Some(c), Reason::Grant | Reason::Revoke) => format!(" by {}", c),
+                _ => String::new(),
+            };
+            format!("{:+} ({}{})", t.amount, t.reason, who)
+        })
+        .collect::<Vec<_>>();
+    if entries.is_empty() {
+        return format!("{} has no recorded transactions yet.", name);
+    }
+    format!("{}'s latest files: {}", name, entries.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::schema::check_journal;
+    use crate::persist::TestDir;
+
+    #[test]
+    fn gives_files() {
+        let mut pd = PlayerData::in_memory();
+        let (a, b) = ("a".to_string(), "b".to_string());
+        pd.player(&a);
+        pd.player(&b);
+        assert!(give(&mut pd, &a, "b 100").is_ok());
+        assert_eq!(pd.players["a"].files, 900);
+        assert_eq!(pd.players["b"].files, 1100);
+
+        assert!(give(&mut pd, &a, "b 5000").is_err());
+        assert!(give(&mut pd, &a, "b -5").is_err());
+        assert!(give(&mut pd, &a, "a 5").is_err());
+        assert!(give(&mut pd, &a, "nobody 5").is_err());
+        assert_eq!(pd.players["a"].files, 900);
+
+        let t = pd.history(&b).next().unwrap();
+        assert_eq!((t.amount, t.reason, t.balance), (100, Reason::Gift, 1100));
+        assert_eq!(t.counterparty.as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn purges_players() {
+        let mut pd = PlayerData::in_memory();
+        let (a, b) = ("a".to_string(), "b".to_string());
+        pd.player(&a);
+        pd.player(&b);
+        give(&mut pd, &a, "b 100").unwrap();
+        let (p, purged) = pd.remove("a").unwrap();
+        assert_eq!(p.files, 900);
+        assert!(purged.is_ok());
+        assert!(pd.key_of("a").is_none());
+        assert_eq!(pd.history(&a).count(), 0);
+        assert_eq!(pd.history(&b).next().unwrap().counterparty, None);
+    }
+
+    #[test]
+    fn ledger_survives_reopen() {
+        let dir = TestDir::new("ledger");
+        let path = dir.join("ledger.jsonl");
+
+        let mut ledger = Ledger::open(&path);
+        for i in 0..(HISTORY_LEN as i64 + 2) {
+            ledger.record(Transaction {
+                time: 0,
+                key: "old".to_string(),
+                amount: i,
+                balance: i,
+                reason: Reason::Grant,
+                counterparty: Some("mjb".to_string()),
+                note: None,
+            });
+        }
+        ledger.record(Transaction {
+            time: 0,
+            key: "new".to_string(),
+            amount: 0,
+            balance: 0,
+            reason: Reason::Merge,
+            counterparty: Some("old".to_string()),
+            note: None,
+        });
+        drop(ledger);
+
+        let ledger = Ledger::open(&path);
+        assert!(ledger.history("old").next().is_none());
+        let moved: Vec<i64> = ledger.history("new").map(|t| t.amount).collect();
+        assert_eq!(moved.len(), HISTORY_LEN);
+        assert_eq!(moved[..2], [0, HISTORY_LEN as i64 + 1]);
+        assert!(check_journal(&path, &LEDGER_SCHEMA).contains("up to date"));
+    }
+}
@@ -0,0 +1,365 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::db::schema::{load_versioned, Schema};
+use crate::persist;
+
+/* Economy rules (economy.json)
+ *
+ * How many files players start with and earn, what low tridents cost them, and which greeting
+ * they get for how many files they have. The file is created with the defaults below if it
+ * doesn't exist, and can then be edited by hand.
+ *
+ * Mods can also start timed multipliers from chat ("double files during this stream"). Those
+ * are kept in the same file so that they survive a restart, and are dropped once they expire.
+ * Multipliers only apply to chat income.
+ */
+
+pub const ECONOMY_PATH: &str = "economy.json";
+pub const DEFAULT_EVENT_DURATION: u64 = 60 * 60 * 4;
+pub const MAX_MULTIPLIER: f64 = 10.0; // for one event, and for all of them stacked
+pub const MAX_DURATION: u64 = 60 * 60 * 24 * 30;
+
+pub const ECONOMY_SCHEMA: Schema = Schema {
+    name: "economy",
+    current: 1,
+    migrations: &[],
+};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GreetTier {
+    pub min: i64,
+    pub max: i64,
+    pub response: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Multiplier {
+    pub name: String,
+    pub factor: f64,
+    pub until: u64,
+    pub started_by: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EconomyRules {
+    schema_version: u32,
+    pub starting_files: i64,
+    pub income: i64,
+    pub income_interval: u64,
+    // Low trident deductions are picked from min..max (max exclusive).
+    pub low_roll_min: i64,
+    pub low_roll_max: i64,
+    pub greet_tiers: Vec<GreetTier>,
+    pub multipliers: Vec<Multiplier>,
+
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+fn tier(min: i64, max: i64, response: &str) -> GreetTier {
+    GreetTier {
+        min,
+        max,
+        response: response.to_string(),
+    }
+}
+
+impl Default for EconomyRules {
+    fn default() -> Self {
+        EconomyRules {
+            schema_version: ECONOMY_SCHEMA.current,
+            starting_files: 1000,
+            income: 25,
+            income_interval: 60 * 15,
+            low_roll_min: 12,
+            low_roll_max: 32,
+            greet_tiers: vec![
+                tier(2001, 5000, "USER_GREET_1000"),
+                tier(7001, 10000, "USER_GREET_5000"),
+                tier(11001, 25000, "USER_GREET_10000"),
+                tier(25001, 50000, "USER_GREET_25000"),
+                tier(50001, 10000000, "USER_GREET_50000"),
+            ],
+            multipliers: Vec::new(),
+            path: None,
+        }
+    }
+}
+
+/// Parses durations like "90" (minutes), "90m", "2h", "1d" or "30s" into seconds.
+/// Durations longer than MAX_DURATION aren't accepted.
+pub fn parse_duration(s: &str) -> Option<u64> {
+    let s = s.trim().to_lowercase();
+    let (num, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s.as_str(), "m"),
+    };
+    let n = num.parse::<u64>().ok()?;
+    let unit = match unit {
+        "s" => 1,
+        "m" | "min" => 60,
+        "h" | "hr" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        _ => return None,
+    };
+    n.checked_mul(unit).filter(|s| *s > 0 && *s <= MAX_DURATION)
+}
+
+pub fn format_duration(secs: u64) -> String {
+    if secs >= 60 * 60 {
+        format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60)
+    } else {
+        format!("{}m", (secs / 60).max(1))
+    }
+}
+
+impl EconomyRules {
+    /// Loads the rules from `path`, writing the defaults there if it doesn't exist yet.
+    pub fn load(path: &Path) -> EconomyRules {
+        match load_versioned::<EconomyRules>(path, &ECONOMY_SCHEMA) {
+            Ok(Some(mut rules)) => {
+                rules.path = Some(path.to_path_buf());
+                rules
+            }
+            Ok(None) => {
+                let rules = EconomyRules {
+                    path: Some(path.to_path_buf()),
+                    ..Default::default()
+                };
+                rules.save();
+                rules
+            }
+            Err(e) => {
+                println!(
+                    "[ERROR] Couldn't load {}, using default economy rules: {}",
+                    path.display(),
+                    e
+                );
+                EconomyRules::default()
+            }
+        }
+    }
+
+    pub fn save(&self) -> bool {
+        let path = match &self.path {
+            Some(p) => p,
+            None => return false,
+        };
+        match persist::save_json(path, self) {
+            Ok(_) => true,
+            Err(e) => {
+                println!("[ERROR] Couldn't save {}: {}", path.display(), e);
+                false
+            }
+        }
+    }
+
+    fn active(&self, now: u64) -> impl Iterator<Item = &Multiplier> {
+        self.multipliers.iter().filter(move |m| m.until > now)
+    }
+
+    /// The combined factor of every multiplier running at `now`.
+    pub fn multiplier(&self, now: u64) -> f64 {
+        self.active(now)
+            .map(|m| m.factor)
+            .product::<f64>()
+            .min(MAX_MULTIPLIER)
+    }
+
+    pub fn income_at(&self, now: u64) -> i64 {
+        (self.income as f64 * self.multiplier(now)).round() as i64
+    }
+
+    pub fn low_roll_deduction<R: Rng>(&self, rng: &mut R) -> i64 {
+        if self.low_roll_max <= self.low_roll_min {
+            return self.low_roll_min;
+        }
+        rng.gen_range(self.low_roll_min..self.low_roll_max)
+    }
+
+    /// The greeting response key for a player with `files`, if any.
+    pub fn greet_tier(&self, files: i64) -> Option<&str> {
+        self.greet_tiers
+            .iter()
+            .find(|t| t.min <= files && files <= t.max)
+            .map(|t| t.response.as_str())
+    }
+
+    pub fn start_event(
+        &mut self,
+        name: &str,
+        factor: f64,
+        duration: u64,
+        started_by: &str,
+        now: u64,
+    ) -> Result<String, String> {
+        if !factor.is_finite() || factor <= 0.0 || factor > MAX_MULTIPLIER {
+            return Err(format!(
+                "Multipliers need to be above 0 and at most {}.",
+                MAX_MULTIPLIER
+            ));
+        }
+        let until = match now.checked_add(duration) {
+            Some(until) if duration <= MAX_DURATION => until,
+            _ => {
+                return Err(format!(
+                    "Events can last at most {}.",
+                    format_duration(MAX_DURATION)
+                ))
+            }
+        };
+        self.multipliers.retain(|m| m.until > now);
+        self.multipliers.push(Multiplier {
+            name: name.to_string(),
+            factor,
+            until,
+            started_by: started_by.to_string(),
+        });
+        self.save();
+        Ok(format!(
+            "{}! Files are worth x{} for the next {} (x{} in total).",
+            name,
+            factor,
+            format_duration(duration),
+            self.multiplier(now)
+        ))
+    }
+
+    /// Stops every running multiplier, returning how many there were.
+    pub fn stop_events(&mut self, now: u64) -> usize {
+        let n = self.active(now).count();
+        self.multipliers.clear();
+        self.save();
+        n
+    }
+
+    pub fn describe_events(&self, now: u64) -> String {
+        let events = self
+            .active(now)
+            .map(|m| {
+                format!(
+                    "{} (x{}, {} left)",
+                    m.name,
+                    m.factor,
+                    format_duration(m.until - now)
+                )
+            })
+            .collect::<Vec<_>>();
+        if events.is_empty() {
+            return "No file events running.".to_string();
+        }
+        format!("File events: {}", events.join(", "))
+    }
+
+    /// !filesevent [list | stop | <multiplier> [duration] [name...]]
+    pub fn event_command(&mut self, by: &str, args: &str, now: u64) -> Result<String, String> {
+        let mut words = args.split_whitespace();
+        let factor = match words.next() {
+            None | Some("list") => return Ok(self.describe_events(now)),
+            Some("stop") => return Ok(format!("Stopped {} file event(s).", self.stop_events(now))),
+            Some(f) => f
+                .trim_start_matches('x')
+                .trim_end_matches('x')
+                .parse::<f64>()
+                .map_err(|_| "Usage: <multiplier> [duration, e.g. 2h] [name]".to_string())?,
+        };
+        let mut rest = words.collect::<Vec<_>>();
+        let duration = match rest.first().and_then(|d| parse_duration(d)) {
+            Some(d) => {
+                rest.remove(0);
+                d
+            }
+            None => DEFAULT_EVENT_DURATION,
+        };
+        let name = match rest.is_empty() {
+            true => "File event".to_string(),
+            false => rest.join(" "),
+        };
+        self.start_event(&name, factor, duration, by, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persist::TestDir;
+
+    #[test]
+    fn defaults_match_old_constants() {
+        let rules = EconomyRules::default();
+        assert_eq!(rules.income_at(0), 25);
+        assert_eq!(rules.greet_tier(1000), None);
+        assert_eq!(rules.greet_tier(2001), Some("USER_GREET_1000"));
+        assert_eq!(rules.greet_tier(6000), None);
+        assert_eq!(rules.greet_tier(50001), Some("USER_GREET_50000"));
+        assert_eq!(rules.greet_tier(10000001), None);
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            assert!((12..32).contains(&rules.low_roll_deduction(&mut rng)));
+        }
+    }
+
+    #[test]
+    fn multipliers_stack_and_expire() {
+        let mut rules = EconomyRules::default();
+        rules.start_event("Double", 2.0, 100, "mjb", 0).unwrap();
+        rules.start_event("Bonus", 1.5, 50, "mjb", 0).unwrap();
+        assert_eq!(rules.income_at(10), 75);
+        assert_eq!(rules.income_at(60), 50);
+        assert_eq!(rules.income_at(100), 25);
+        assert!(rules.start_event("Broke", 0.0, 50, "mjb", 0).is_err());
+        assert!(rules.start_event("Rich", 100.0, 50, "mjb", 0).is_err());
+        assert!(rules
+            .start_event("Forever", 2.0, u64::MAX, "mjb", 0)
+            .is_err());
+        rules.start_event("More", 8.0, 50, "mjb", 0).unwrap();
+        assert_eq!(rules.income_at(10), 250);
+
+        // Expired events are cleaned up when new ones start.
+        rules.start_event("Later", 3.0, 10, "mjb", 200).unwrap();
+        assert_eq!(rules.multipliers.len(), 1);
+        assert_eq!(rules.stop_events(200), 1);
+        assert_eq!(rules.income_at(205), 25);
+    }
+
+    #[test]
+    fn parses_event_commands() {
+        assert_eq!(parse_duration("90"), Some(90 * 60));
+        assert_eq!(parse_duration("2h"), Some(2 * 60 * 60));
+        assert_eq!(parse_duration("1d"), Some(24 * 60 * 60));
+        assert_eq!(parse_duration("0m"), None);
+        assert_eq!(parse_duration("soon"), None);
+        assert_eq!(parse_duration("31d"), None);
+        assert_eq!(parse_duration("999999999999999d"), None);
+
+        let mut rules = EconomyRules::default();
+        rules
+            .event_command("mjb", "x2 30m double files!", 0)
+            .unwrap();
+        assert_eq!(rules.multipliers[0].name, "double files!");
+        assert_eq!(rules.multipliers[0].until, 30 * 60);
+        rules.event_command("mjb", "1.5", 0).unwrap();
+        assert_eq!(rules.multipliers[1].until, DEFAULT_EVENT_DURATION);
+        assert!(rules.event_command("mjb", "lots", 0).is_err());
+        assert!(rules
+            .event_command("mjb", "list", 0)
+            .unwrap()
+            .contains("double"));
+    }
+
+    #[test]
+    fn events_survive_reload() {
+        let dir = TestDir::new("economy");
+        let path = dir.join("economy.json");
+
+        let mut rules = EconomyRules::load(&path);
+        assert!(path.exists());
+        rules.income = 30;
+        rules.start_event("Double", 2.0, 100, "mjb", 0).unwrap();
+
+        let rules = EconomyRules::load(&path);
+        assert_eq!(rules.income_at(50), 60);
+    }
+}
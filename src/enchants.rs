@@ -2,6 +2,8 @@ use std::ops::RangeInclusive;
 
 use rand::{rngs::ThreadRng, seq::SliceRandom, Rng, thread_rng};
 
+use crate::stat_registry::{Stat, StatRegistry};
+
 pub struct Enchant {
     pub name: &'static str,
     pub short: &'static str,
@@ -60,6 +62,12 @@ pub struct EnchantOffer {
     pub special_response: bool,
 }
 
+pub fn register_stats(registry: &mut StatRegistry) {
+    registry.register(Stat::count("enchants", &["enchant"], "Enchants rolled", |_, p| {
+        Some(p.enchants_rolled as f64)
+    }));
+}
+
 pub fn roll_enchant() -> Option<EnchantOffer> {
     const ENCHANTS: &[&Enchant] = &[
         &Enchant::AQUA_AFFINITY,
@@ -0,0 +1,103 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::persist::{self, SaveOptions};
+
+/* Exports
+ *
+ * Files written for the community to download (hall of fame, stat snapshots). They go into
+ * exports/, are replaced atomically like every other file we write, and don't keep backups.
+ */
+
+pub const EXPORT_DIR: &str = "exports";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Csv,
+}
+
+impl Format {
+    /// Parses "json" or "csv", defaulting to JSON.
+    pub fn parse(s: &str) -> Option<Format> {
+        match s.trim().to_lowercase().as_str() {
+            "" | "json" => Some(Format::Json),
+            "csv" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Csv => "csv",
+        }
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+pub fn to_csv(header: &[&str], rows: &[Vec<String>]) -> String {
+    let mut out = header.join(",") + "\n";
+    for row in rows {
+        out += &row
+            .iter()
+            .map(|f| csv_field(f))
+            .collect::<Vec<_>>()
+            .join(",");
+        out += "\n";
+    }
+    out
+}
+
+/// Writes exports/<name>.<json|csv>. `csv` is only called for CSV exports.
+pub fn write<T, F>(
+    dir: &Path,
+    name: &str,
+    format: Format,
+    value: &T,
+    csv: F,
+) -> Result<PathBuf, String>
+where
+    T: Serialize + ?Sized,
+    F: FnOnce() -> String,
+{
+    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let path = dir.join(format!("{}.{}", name, format.extension()));
+    let contents = match format {
+        Format::Json => serde_json::to_vec_pretty(value).map_err(|e| e.to_string())?,
+        Format::Csv => csv().into_bytes(),
+    };
+    let opts = SaveOptions {
+        backups: 0,
+        ..Default::default()
+    };
+    persist::write_atomic(&path, &contents, &opts).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_csv() {
+        let rows = vec![
+            vec!["mjb".to_string(), "250".to_string()],
+            vec!["a, \"b\"".to_string(), "1".to_string()],
+        ];
+        assert_eq!(
+            to_csv(&["name", "value"], &rows),
+            "name,value\nmjb,250\n\"a, \"\"b\"\"\",1\n"
+        );
+        assert_eq!(Format::parse(""), Some(Format::Json));
+        assert_eq!(Format::parse("CSV"), Some(Format::Csv));
+        assert_eq!(Format::parse("xml"), None);
+    }
+}
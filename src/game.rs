@@ -1,94 +1,208 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::File;
-use std::path::Path;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
 
-use crate::player_data::*;
+use crate::db::player::{Player, PlayerData};
+use crate::economy::rules::{format_duration, parse_duration};
+use crate::economy::Reason;
+use crate::persist;
+use crate::player_data;
 
 use lazy_static::lazy_static;
 
 lazy_static! {
-    static ref PLAYER_PATH: &'static Path = Path::new("players.json");
+    static ref LEGACY_PLAYER_PATH: &'static Path = Path::new("players.json");
     static ref GAME_DUMP_PATH: &'static Path = Path::new("gamedump.json");
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/* Betting game
+ *
+ * Players bet files (the same currency as everything else) on whether something works.
+ * Wagers are keyed by player key and are taken out of the player's files as soon as the
+ * bet is placed. Open wagers are kept in gamedump.json so a restart doesn't eat them.
+ *
+ * Predictions are the bigger version: a mod opens one with any number of named outcomes and
+ * a betting window, players !bet on an outcome until the window closes, and once it's
+ * resolved the whole pool is split between the winners by how much they staked (pari-mutuel,
+ * so the odds are set by the bets, not by us). Cancelling refunds everyone.
+ */
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct Game {
-    players: HashMap<String, Player>,
     wagers: HashMap<String, i64>,
+    prediction: Option<Prediction>,
+
+    #[serde(skip)]
+    path: Option<PathBuf>,
 }
 
-impl Game {
-    pub fn new() -> Game {
-        Game {
-            players: get_players(&PLAYER_PATH),
-            wagers: HashMap::new(),
+pub const MIN_WAGER: i64 = 5;
+pub const MAX_OUTCOMES: usize = 10;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Prediction {
+    pub title: String,
+    pub outcomes: Vec<String>,
+    pub closes_at: u64,
+    // Player key -> (outcome index, files staked).
+    pub bets: HashMap<String, (usize, i64)>,
+}
+
+impl Prediction {
+    pub fn pool(&self) -> i64 {
+        self.bets.values().map(|(_, amount)| amount).sum()
+    }
+
+    pub fn staked(&self, outcome: usize) -> i64 {
+        self.bets
+            .values()
+            .filter(|(o, _)| *o == outcome)
+            .map(|(_, amount)| amount)
+            .sum()
+    }
+
+    /// An outcome by its number (starting at 1) or its name.
+    pub fn outcome(&self, s: &str) -> Option<usize> {
+        let s = s.trim();
+        match s.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= self.outcomes.len() => Some(n - 1),
+            _ => self.outcomes.iter().position(|o| o.eq_ignore_ascii_case(s)),
         }
     }
 
+    pub fn is_open(&self, now: u64) -> bool {
+        now < self.closes_at
+    }
+}
+
+impl Game {
+    pub fn new(player_data: &mut PlayerData) -> Game {
+        import_legacy_players(player_data, &LEGACY_PLAYER_PATH);
+        Game::load(&GAME_DUMP_PATH)
+    }
+
+    /// Loads the open wagers and prediction from `path`, starting with none if it doesn't
+    /// exist yet. The game is saved back there.
+    pub fn load(path: &Path) -> Game {
+        let mut game = Game::default();
+        game.path = Some(path.to_path_buf());
+        game.reload();
+        game
+    }
+
     pub fn summarize(p: &Player) -> String {
-        if p.losses == 0 {
-            if p.wins == 0 {
+        if p.bet_losses == 0 {
+            if p.bet_wins == 0 {
                 format!(
-                    "Player {} has {} points and has never played </3",
-                    p.name, p.cash
+                    "Player {} has {} files and has never played </3",
+                    p.name(),
+                    p.files
                 )
             } else {
                 format!(
-                    "Player {} has {} points and a 100% winrate!",
-                    p.name, p.cash
+                    "Player {} has {} files and a 100% winrate!",
+                    p.name(),
+                    p.files
                 )
             }
-        } else if p.wins == 0 {
+        } else if p.bet_wins == 0 {
             format!(
-                "Player {} has {} points and a 0% winrate :(",
-                p.name, p.cash
+                "Player {} has {} files and a 0% winrate :(",
+                p.name(),
+                p.files
             )
         } else {
             format!(
-                "Player {} has {} points and a {:.2}% winrate.",
-                p.name,
-                p.cash,
-                p.wins as f64 * 100 as f64 / (p.wins as f64 + p.losses as f64)
+                "Player {} has {} files and a {:.2}% winrate.",
+                p.name(),
+                p.files,
+                p.bet_wins as f64 * 100_f64 / (p.bet_wins as f64 + p.bet_losses as f64)
             )
         }
     }
 
-    pub fn status(&self, name: &String) -> String {
-        match self.players.get(name) {
+    pub fn status(&self, player_data: &PlayerData, name: &String) -> String {
+        match player_data.find(name) {
             Some(p) => Game::summarize(p),
-            None => format!("The player '{}' does not exist; place a wager to join!", name),
+            None => format!(
+                "The player '{}' does not exist; place a wager to join!",
+                name
+            ),
         }
     }
 
+    /// Drops a player's open wager and prediction bet without paying them out. Returns the
+    /// wager or bet, if any.
+    pub fn forget(&mut self, key: &str) -> Option<i64> {
+        let bet = self
+            .prediction
+            .as_mut()
+            .and_then(|p| p.bets.remove(key))
+            .map(|(_, amount)| amount);
+        self.wagers.remove(key).or(bet)
+    }
+
     pub fn save(&self) -> bool {
-        save_players(&self.players, &PLAYER_PATH)
+        let path = match &self.path {
+            Some(p) => p,
+            None => return false,
+        };
+        match persist::save_json(path, &self) {
+            Ok(_) => true,
+            Err(e) => {
+                println!("[ERROR] Couldn't save game dump: {}", e);
+                false
+            }
+        }
     }
 
     pub fn reload(&mut self) {
-        self.players = get_players(&PLAYER_PATH);
+        let path = match &self.path {
+            Some(p) if p.exists() => p.clone(),
+            _ => return,
+        };
+        match File::open(&path).map_err(|e| e.to_string()).and_then(|f| {
+            serde_json::from_reader::<_, Game>(BufReader::new(f)).map_err(|e| e.to_string())
+        }) {
+            Ok(mut game) => {
+                // Move the contents over, rather than replacing self and saving the old one.
+                self.wagers = std::mem::take(&mut game.wagers);
+                self.prediction = game.prediction.take();
+            }
+            Err(e) => println!("[ERROR] Couldn't load game dump: {}", e),
+        }
     }
 
-    pub fn valid_wager(&mut self, wager: &String, user: &String) -> Result<i64, String> {
+    pub fn valid_wager(
+        &self,
+        player_data: &mut PlayerData,
+        wager: &str,
+        user: &String,
+    ) -> Result<i64, String> {
         // Is it a valid number?
         if let Ok(w) = wager.parse::<i64>() {
-            // Is it greater than 4?
-            if w < 5 {
+            if w < MIN_WAGER {
                 return Err(String::from(
                     "Your wager is too small! (Wagers must be 5 or greater!)",
                 ));
             }
             // Is it a valid player?
-            let player = self.players.entry(user.clone()).or_insert(Player::new(user.clone()));
-            if player.cash < w {
+            let player = player_data.player(user);
+            if player.files < w {
                 return Err(format!(
-                    "The player '{}' has insufficient funds to make that bet! ({})",
-                    user, w
+                    "The player '{}' has insufficient files to make that bet! ({})",
+                    player.name(),
+                    w
                 ));
             }
             // Does the player already have a wager?
             match self.wagers.get(user) {
-                Some(i) => Err(format!("The player '{}' has already wagered {}!", user, i)),
+                Some(i) => Err(format!(
+                    "The player '{}' has already wagered {}!",
+                    player.name(),
+                    i.abs()
+                )),
                 None => Ok(w),
             }
         } else {
@@ -96,159 +210,551 @@ impl Game {
         }
     }
 
-    fn make_bet(&mut self, amount: i64, user: &String) {
+    fn make_bet(&mut self, player_data: &mut PlayerData, amount: i64, user: &String) {
         // This function is only called if the wager is valid.
         // Could use typesafety to ensure that, but it doesn't prevent
         // bad use, so this function is private.
-        self.players.get_mut(user).unwrap().cash -= amount.abs();
+        player_data.adjust(user, -amount.abs(), Reason::Bet, None, None);
         self.wagers.insert(user.clone(), amount);
     }
 
-    pub fn bet_for(&mut self, user: &String, amount: &String) -> Result<(), String> {
-        match self.valid_wager(amount, user) {
-            Ok(i) => Ok(self.make_bet(i, user)),
-            Err(e) => Err(e),
-        }
+    pub fn bet_for(
+        &mut self,
+        player_data: &mut PlayerData,
+        user: &String,
+        amount: &str,
+    ) -> Result<(), String> {
+        let i = self.valid_wager(player_data, amount, user)?;
+        self.make_bet(player_data, i, user);
+        Ok(())
     }
 
-    pub fn bet_against(&mut self, user: &String, amount: &String) -> Result<(), String> {
-        match self.valid_wager(amount, user) {
-            Ok(i) => Ok(self.make_bet(-1 * i, user)),
-            Err(e) => Err(e),
-        }
+    pub fn bet_against(
+        &mut self,
+        player_data: &mut PlayerData,
+        user: &String,
+        amount: &str,
+    ) -> Result<(), String> {
+        let i = self.valid_wager(player_data, amount, user)?;
+        self.make_bet(player_data, -i, user);
+        Ok(())
     }
 
-    pub fn worked(&mut self) -> String {
-        // code repetition here & below is sorta bad
-        // but that's life
+    // Pays out every wager whose sign matches `winning_sign` at 2x, and counts the rest as losses.
+    // Returns (wins, amount won, losses, amount lost).
+    fn settle(&mut self, player_data: &mut PlayerData, winning_sign: i64) -> (u32, i64, u32, i64) {
         let mut num_wins: u32 = 0;
         let mut amount_won: i64 = 0;
         let mut num_losses: u32 = 0;
         let mut amount_lost: i64 = 0;
-        for (user, wager) in &self.wagers {
-            match *wager {
-                i if i < 0 => {
-                    num_losses += 1;
-                    amount_lost -= wager; // beautiful
-                    match self.players.get_mut(&*user) {
-                        Some(p) => {
-                            p.losses += 1;
-                        }
-                        None => println!("Odd, player {} no longer exists.", user),
-                    }
-                }
-                i if i > 0 => {
-                    num_wins += 1;
-                    amount_won += wager * 2;
-                    match self.players.get_mut(&*user) {
-                        Some(p) => {
-                            p.cash += wager * 2;
-                            p.wins += 1;
-                        }
-                        None => println!("Odd, player {} no longer exists.", user),
-                    }
+        for (user, wager) in self.wagers.drain() {
+            if wager == 0 {
+                println!("Odd, wager for user {} was 0.", user);
+                continue;
+            }
+            let won = wager.signum() == winning_sign;
+            let payout = wager.abs() * 2;
+            let res = player_data.apply(&user, |p| {
+                if won {
+                    p.bet_wins += 1;
+                } else {
+                    p.bet_losses += 1;
                 }
-                _ => println!("Odd, wager for user {} was 0.", user),
+            });
+            if res.is_none() {
+                println!("Odd, player {} no longer exists.", user);
+            } else if won {
+                player_data.adjust(&user, payout, Reason::BetPayout, None, None);
+            }
+            if won {
+                num_wins += 1;
+                amount_won += payout;
+            } else {
+                num_losses += 1;
+                amount_lost += wager.abs();
             }
         }
-        self.wagers.clear();
+        (num_wins, amount_won, num_losses, amount_lost)
+    }
+
+    pub fn worked(&mut self, player_data: &mut PlayerData) -> String {
+        let (num_wins, amount_won, num_losses, amount_lost) = self.settle(player_data, 1);
         if num_wins + num_losses == 0 {
             String::from("Nice work, but nobody was playing...")
         } else if num_wins == 0 {
             format!(
-                "Ouch, {} player(s) lost {} points... Ye of little faith!",
+                "Ouch, {} player(s) lost {} files... Ye of little faith!",
                 num_losses, amount_lost
             )
         } else if num_losses == 0 {
             format!(
-                "Wow! {} player(s) won {} points. Making it easy, eh?",
+                "Wow! {} player(s) won {} files. Making it easy, eh?",
                 num_wins, amount_won
             )
         } else {
             format!(
-                "{} player(s) won {} points, while {} player(s) lost {} points!",
+                "{} player(s) won {} files, while {} player(s) lost {} files!",
                 num_wins, amount_won, num_losses, amount_lost
             )
         }
     }
 
-    pub fn failed(&mut self) -> String {
-        let mut num_wins: u32 = 0;
-        let mut amount_won: i64 = 0;
-        let mut num_losses: u32 = 0;
-        let mut amount_lost: i64 = 0;
-        for (user, wager) in &self.wagers {
-            match *wager {
-                i if i > 0 => {
-                    num_losses += 1;
-                    amount_lost += wager; // beautiful
-                    match self.players.get_mut(&*user) {
-                        Some(p) => {
-                            p.losses += 1;
-                        }
-                        None => println!("Odd, player {} no longer exists.", user),
-                    }
-                }
-                i if i < 0 => {
-                    num_wins += 1;
-                    amount_won -= wager * 2;
-                    match self.players.get_mut(&*user) {
-                        Some(p) => {
-                            p.cash += wager * -2;
-                            p.wins += 1;
-                        }
-                        None => println!("Odd, player {} no longer exists.", user),
-                    }
-                }
-                _ => println!("Odd, wager for user {} was 0.", user),
-            }
-        }
-        self.wagers.clear();
+    pub fn failed(&mut self, player_data: &mut PlayerData) -> String {
+        let (num_wins, amount_won, num_losses, amount_lost) = self.settle(player_data, -1);
         if num_wins + num_losses == 0 {
             String::from("You're only hurting yourself...")
         } else if num_wins == 0 {
             format!(
-                "Ouch, {} player(s) lost {} points... you've been failed :(",
+                "Ouch, {} player(s) lost {} files... you've been failed :(",
                 num_losses, amount_lost
             )
         } else if num_losses == 0 {
             format!(
-                "{} player(s) won {} points. That's ... unfortunate.",
+                "{} player(s) won {} files. That's ... unfortunate.",
                 num_wins, amount_won
             )
         } else {
             format!(
-                "{} player(s) won {} points, while {} player(s) lost {} points.",
+                "{} player(s) won {} files, while {} player(s) lost {} files.",
                 num_wins, amount_won, num_losses, amount_lost
             )
         }
     }
-}
 
-impl Drop for Game {
-    fn drop(&mut self) {
-        let file = match match GAME_DUMP_PATH.exists() {
-            true => std::fs::OpenOptions::new()
-                .write(true)
-                .truncate(true)
-                .open(&*GAME_DUMP_PATH),
-            false => File::create(&*GAME_DUMP_PATH),
-        } {
-            Ok(file) => file,
-            Err(e) => {
-                println!("[ERROR] Could not open or create file! {}", e);
-                println!(
-                    "Emergency dump: {}",
-                    serde_json::to_string_pretty(&self).unwrap()
-                );
-                return;
+    /// !predict <duration> <title> | <outcome> | <outcome> ..., and !predict close to stop
+    /// taking bets early.
+    pub fn predict(&mut self, args: &str, now: u64) -> Result<String, String> {
+        if args.trim().eq_ignore_ascii_case("close") {
+            let p = self
+                .prediction
+                .as_mut()
+                .filter(|p| p.is_open(now))
+                .ok_or("There's no prediction taking bets.")?;
+            p.closes_at = now;
+            return Ok(format!(
+                "Bets are closed for '{}'! Pool: {} files.",
+                p.title,
+                p.pool()
+            ));
+        }
+        if let Some(p) = &self.prediction {
+            return Err(format!(
+                "'{}' is still running; resolve or cancel it first.",
+                p.title
+            ));
+        }
+        let usage = "Usage: !predict <duration> <title> | <outcome> | <outcome> ...";
+        let mut parts = args.split('|').map(str::trim);
+        let (duration, title) = parts
+            .next()
+            .and_then(|head| head.split_once(char::is_whitespace))
+            .ok_or(usage)?;
+        let window = parse_duration(duration).ok_or(usage)?;
+        let outcomes = parts
+            .filter(|o| !o.is_empty())
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        if outcomes.len() < 2 || outcomes.len() > MAX_OUTCOMES {
+            return Err(format!(
+                "Predictions need 2 to {} outcomes. {}",
+                MAX_OUTCOMES, usage
+            ));
+        }
+        if (1..outcomes.len()).any(|i| {
+            outcomes[..i]
+                .iter()
+                .any(|o| o.eq_ignore_ascii_case(&outcomes[i]))
+        }) {
+            return Err("Every outcome needs a different name.".to_string());
+        }
+        let p = Prediction {
+            title: title.trim().to_string(),
+            outcomes,
+            closes_at: now + window,
+            bets: HashMap::new(),
+        };
+        let msg = format!(
+            "Prediction: {} Bet with !bet <outcome> <files> in the next {}: {}",
+            p.title,
+            format_duration(window),
+            p.outcomes
+                .iter()
+                .enumerate()
+                .map(|(i, o)| format!("{}. {}", i + 1, o))
+                .collect::<Vec<_>>()
+                .join(" | ")
+        );
+        self.prediction = Some(p);
+        Ok(msg)
+    }
+
+    /// !prediction - the outcomes with their pools and current odds.
+    pub fn prediction_status(&self, now: u64) -> String {
+        let p = match &self.prediction {
+            Some(p) => p,
+            None => return "There's no prediction running.".to_string(),
+        };
+        let pool = p.pool();
+        let outcomes = p
+            .outcomes
+            .iter()
+            .enumerate()
+            .map(|(i, o)| match p.staked(i) {
+                0 => format!("{}. {} (no bets)", i + 1, o),
+                staked => format!(
+                    "{}. {} ({} files, pays {:.2}x)",
+                    i + 1,
+                    o,
+                    staked,
+                    pool as f64 / staked as f64
+                ),
+            })
+            .collect::<Vec<_>>()
+            .join(" | ");
+        let window = match p.is_open(now) {
+            true => format!("bets close in {}", format_duration(p.closes_at - now)),
+            false => "bets are closed".to_string(),
+        };
+        format!(
+            "{} {} ({}, pool: {} files)",
+            p.title, outcomes, window, pool
+        )
+    }
+
+    /// !bet <outcome> <files>. Betting again on the same outcome adds to the bet.
+    pub fn bet_on(
+        &mut self,
+        player_data: &mut PlayerData,
+        user: &str,
+        args: &str,
+        now: u64,
+    ) -> Result<String, String> {
+        let user = user.to_string();
+        let p = self
+            .prediction
+            .as_mut()
+            .ok_or("There's no prediction to bet on.")?;
+        if !p.is_open(now) {
+            return Err(format!("Bets are closed for '{}'.", p.title));
+        }
+        let (outcome, amount) = args
+            .trim()
+            .rsplit_once(char::is_whitespace)
+            .ok_or("Usage: !bet <outcome> <files>")?;
+        let outcome = p
+            .outcome(outcome)
+            .ok_or_else(|| format!("'{}' isn't one of the outcomes.", outcome.trim()))?;
+        let amount = amount
+            .parse::<i64>()
+            .map_err(|_| "Your wager needs to be a valid integer!".to_string())?;
+        if amount < MIN_WAGER {
+            return Err(format!(
+                "Your wager is too small! (Wagers must be {} or greater!)",
+                MIN_WAGER
+            ));
+        }
+        let player = player_data.player(&user);
+        if player.files < amount {
+            return Err(format!(
+                "The player '{}' has insufficient files to make that bet! ({})",
+                player.name(),
+                amount
+            ));
+        }
+        let name = player.name().to_string();
+        let staked = match p.bets.get(&user) {
+            Some((o, _)) if *o != outcome => {
+                return Err(format!("{} already bet on {}.", name, p.outcomes[*o]))
             }
+            Some((_, staked)) => *staked,
+            None => 0,
         };
-        match serde_json::to_writer_pretty(file, &self) {
-            Ok(_) => {}
-            Err(e) => {
-                println!("[ERROR] Couldn't save players: {}", e);
+        player_data.adjust(&user, -amount, Reason::Bet, None, None);
+        p.bets.insert(user, (outcome, staked + amount));
+        Ok(format!(
+            "{} has {} files on {}.",
+            name,
+            staked + amount,
+            p.outcomes[outcome]
+        ))
+    }
+
+    /// !resolve <outcome>. Splits the pool between everyone who picked `outcome`, in proportion
+    /// to their stakes. If nobody picked it, everyone gets their files back. Bets have to be
+    /// closed first, so nobody can bet once the outcome is known.
+    pub fn resolve(
+        &mut self,
+        player_data: &mut PlayerData,
+        args: &str,
+        now: u64,
+    ) -> Result<String, String> {
+        let p = self
+            .prediction
+            .as_ref()
+            .ok_or("There's no prediction to resolve.")?;
+        if p.is_open(now) {
+            return Err(format!(
+                "Bets are still open for '{}'; close them with !predict close first.",
+                p.title
+            ));
+        }
+        let winner = p
+            .outcome(args)
+            .ok_or_else(|| format!("Which outcome won? {}", p.outcomes.join(", ")))?;
+        let p = self.prediction.take().unwrap();
+        let (pool, winning_stake) = (p.pool(), p.staked(winner));
+        if winning_stake == 0 {
+            let refunded = refund(player_data, p.bets.iter().map(|(k, (_, a))| (k, *a)));
+            return Ok(format!(
+                "{} won '{}', but nobody bet on it, so {} files went back to {} player(s).",
+                p.outcomes[winner], p.title, pool, refunded
+            ));
+        }
+        let winning_bets = || p.bets.iter().filter(|(_, (outcome, _))| *outcome == winner);
+        let mut payouts = winning_bets()
+            .map(|(user, (_, staked))| {
+                let payout = (pool as i128 * *staked as i128 / winning_stake as i128) as i64;
+                (user, payout)
+            })
+            .collect::<HashMap<_, _>>();
+        // Rounding down leaves a few files over; they go to the biggest stake (the first key on
+        // a tie), so the whole pool is paid out.
+        let paid = payouts.values().sum::<i64>();
+        if let Some((user, _)) =
+            winning_bets().max_by(|(a, (_, x)), (b, (_, y))| x.cmp(y).then_with(|| b.cmp(a)))
+        {
+            *payouts.get_mut(user).unwrap() += pool - paid;
+        }
+        let mut winners = Vec::new();
+        for (user, (outcome, staked)) in &p.bets {
+            let won = *outcome == winner;
+            let res = player_data.apply(user, |pl| match won {
+                true => pl.bet_wins += 1,
+                false => pl.bet_losses += 1,
+            });
+            if res.is_none() {
+                println!("Odd, player {} no longer exists.", user);
+            } else if won {
+                let payout = payouts[user];
+                player_data.adjust(user, payout, Reason::BetPayout, None, None);
+                let name = player_data.players[user].name().to_string();
+                winners.push((name, payout - staked));
             }
         }
+        winners.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let biggest = winners
+            .iter()
+            .take(3)
+            .map(|(name, net)| format!("{} (+{})", name, net))
+            .collect::<Vec<_>>()
+            .join(", ");
+        Ok(format!(
+            "{} won '{}'! {} player(s) split the pool of {} files, {} player(s) lost theirs. \
+             Biggest winners: {}",
+            p.outcomes[winner],
+            p.title,
+            winners.len(),
+            pool,
+            p.bets.len() - winners.len(),
+            biggest
+        ))
+    }
+
+    /// Cancels the running prediction and any open wagers, refunding every bet.
+    pub fn cancel(&mut self, player_data: &mut PlayerData) -> String {
+        let wagers = std::mem::take(&mut self.wagers);
+        let mut total: i64 = wagers.values().map(|w| w.abs()).sum();
+        let mut players = refund(player_data, wagers.iter().map(|(k, w)| (k, w.abs())));
+        let title = match self.prediction.take() {
+            Some(p) => {
+                total += p.pool();
+                players += refund(player_data, p.bets.iter().map(|(k, (_, a))| (k, *a)));
+                format!("'{}' is cancelled", p.title)
+            }
+            None => "Bets are cancelled".to_string(),
+        };
+        format!(
+            "{}; {} files went back to {} player(s).",
+            title, total, players
+        )
+    }
+}
+
+// Gives every bet back. Returns how many bets were refunded.
+fn refund<'a>(
+    player_data: &mut PlayerData,
+    bets: impl Iterator<Item = (&'a String, i64)>,
+) -> usize {
+    let mut n = 0;
+    for (user, amount) in bets {
+        if player_data.players.contains_key(user) {
+            player_data.adjust(user, amount, Reason::Refund, None, None);
+            n += 1;
+        } else {
+            println!("Odd, player {} no longer exists.", user);
+        }
+    }
+    n
+}
+
+impl Drop for Game {
+    fn drop(&mut self) {
+        if self.path.is_some() && !self.save() {
+            println!(
+                "Emergency dump: {}",
+                serde_json::to_string_pretty(&self).unwrap()
+            );
+        }
+    }
+}
+
+/// One-shot import of the old betting game's players.json into the main player data.
+/// Both stores started everyone on 1000, so only what they won on top of that is added to their
+/// files. Losses in the old game aren't taken back out.
+/// The file is renamed afterwards so it can't be imported twice.
+pub fn import_legacy_players(player_data: &mut PlayerData, path: &Path) -> usize {
+    if !path.exists() {
+        return 0;
+    }
+    let legacy = player_data::get_players(path);
+    for (login, old) in &legacy {
+        let key = player_data
+            .key_of(login)
+            .unwrap_or_else(|| login.to_lowercase());
+        let files = old.cash - player_data::default_cash();
+        if files > 0 {
+            player_data.adjust(&key, files, Reason::Import, None, None);
+        }
+        let p = player_data.player(&key);
+        p.bet_wins += old.wins.max(0) as u64;
+        p.bet_losses += old.losses.max(0) as u64;
+    }
+    // Make sure the balances are on disk before the old file goes away.
+    if !player_data.save() {
+        println!("[ERROR] Couldn't save imported betting players, will retry next start.");
+        return 0;
+    }
+    let mut migrated = path.as_os_str().to_os_string();
+    migrated.push(".migrated");
+    if let Err(e) = std::fs::rename(path, migrated) {
+        println!("[ERROR] Couldn't rename {}: {}", path.display(), e);
+    }
+    println!("[Note] Imported {} betting players.", legacy.len());
+    legacy.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persist::TestDir;
+
+    #[test]
+    fn bets_use_files() {
+        let mut pd = PlayerData::in_memory();
+        let dir = TestDir::new("game_bets");
+        let mut game = Game::load(&dir.join("gamedump.json"));
+        let (a, b) = ("a".to_string(), "b".to_string());
+        game.bet_for(&mut pd, &a, "100").unwrap();
+        game.bet_against(&mut pd, &b, "200").unwrap();
+        assert!(game.bet_for(&mut pd, &a, "100").is_err());
+        assert!(game.bet_for(&mut pd, &b, "5000").is_err());
+        assert_eq!(pd.players["a"].files, 900);
+        // Open wagers survive a restart.
+        drop(game);
+        let mut game = Game::load(&dir.join("gamedump.json"));
+        assert_eq!(game.wagers.len(), 2);
+
+        game.worked(&mut pd);
+        assert_eq!(pd.players["a"].files, 1100);
+        assert_eq!(pd.players["a"].bet_wins, 1);
+        assert_eq!(pd.players["b"].files, 800);
+        assert_eq!(pd.players["b"].bet_losses, 1);
+    }
+
+    #[test]
+    fn predictions_split_the_pool() {
+        let mut pd = PlayerData::in_memory();
+        let dir = TestDir::new("game_predictions");
+        let mut game = Game::load(&dir.join("gamedump.json"));
+        let (a, b, c) = ("a".to_string(), "b".to_string(), "c".to_string());
+        assert!(game.predict("2m Which boss? | one", 0).is_err());
+        assert!(game.predict("2m Which boss? | one | ONE", 0).is_err());
+        game.predict("2m Which boss? | Glutton | Mantis | Radiance", 0)
+            .unwrap();
+        assert!(game.predict("2m Again? | yes | no", 0).is_err());
+
+        game.bet_on(&mut pd, &a, "glutton 100", 10).unwrap();
+        game.bet_on(&mut pd, &a, "1 200", 20).unwrap();
+        game.bet_on(&mut pd, &b, "1 100", 30).unwrap();
+        game.bet_on(&mut pd, &c, "mantis 600", 40).unwrap();
+        assert!(game.bet_on(&mut pd, &a, "2 100", 50).is_err());
+        assert!(game.bet_on(&mut pd, &b, "4 100", 50).is_err());
+        assert!(game.prediction_status(60).contains("pays 2.50x"));
+        // The window is over.
+        assert!(game.bet_on(&mut pd, &b, "1 100", 120).is_err());
+
+        assert!(game.resolve(&mut pd, "glutton", 60).is_err());
+        let msg = game.resolve(&mut pd, "glutton", 120).unwrap();
+        assert!(msg.contains("Biggest winners: a (+450), b (+150)"));
+        assert_eq!(pd.players["a"].files, 1450);
+        assert_eq!(pd.players["b"].files, 1150);
+        assert_eq!(pd.players["c"].files, 400);
+        assert_eq!(pd.players["c"].bet_losses, 1);
+        assert!(game.resolve(&mut pd, "1", 120).is_err());
+
+        // What the split leaves over goes to the biggest stake: 35 * 10 / 30 = 11, 35 * 20 / 30
+        // = 23, and the last file to b.
+        game.predict("1m Odd split | x | y", 200).unwrap();
+        game.bet_on(&mut pd, &a, "x 10", 200).unwrap();
+        game.bet_on(&mut pd, &b, "x 20", 200).unwrap();
+        game.bet_on(&mut pd, &c, "y 5", 200).unwrap();
+        assert!(game.predict("close", 210).is_ok());
+        game.resolve(&mut pd, "x", 210).unwrap();
+        assert_eq!((pd.players["a"].files, pd.players["b"].files), (1451, 1154));
+    }
+
+    #[test]
+    fn cancelling_refunds_bets() {
+        let mut pd = PlayerData::in_memory();
+        let dir = TestDir::new("game_cancelling");
+        let mut game = Game::load(&dir.join("gamedump.json"));
+        let (a, b) = ("a".to_string(), "b".to_string());
+        game.bet_against(&mut pd, &a, "100").unwrap();
+        game.predict("30s Nobody wins | yes | no", 0).unwrap();
+        game.bet_on(&mut pd, &b, "no 300", 0).unwrap();
+        assert!(game.predict("close", 10).is_ok());
+        assert!(game.bet_on(&mut pd, &a, "yes 10", 10).is_err());
+
+        assert_eq!(
+            game.cancel(&mut pd),
+            "'Nobody wins' is cancelled; 400 files went back to 2 player(s)."
+        );
+        assert_eq!(pd.players["a"].files, 1000);
+        assert_eq!(pd.players["b"].files, 1000);
+        assert!(game.prediction.is_none() && game.wagers.is_empty());
+    }
+
+    #[test]
+    fn imports_legacy_balances() {
+        let dir = TestDir::new("game");
+        let path = dir.join("players.json");
+        let mut legacy = HashMap::new();
+        let mut old = player_data::Player::new("mjb".to_string());
+        old.cash = 1500;
+        old.wins = 2;
+        legacy.insert(old.name.clone(), old);
+        let mut broke = player_data::Player::new("pac".to_string());
+        broke.cash = 10;
+        legacy.insert(broke.name.clone(), broke);
+        assert!(player_data::save_players(&legacy, &path));
+
+        let mut pd = PlayerData::in_memory();
+        pd.player(&"mjb".to_string()).files = 3000;
+        assert_eq!(import_legacy_players(&mut pd, &path), 2);
+        assert_eq!(pd.players["mjb"].files, 3500);
+        assert_eq!(pd.players["pac"].files, 1000);
+        assert_eq!(pd.players["mjb"].bet_wins, 2);
+        assert!(!path.exists());
+        assert_eq!(import_legacy_players(&mut pd, &path), 0);
     }
 }
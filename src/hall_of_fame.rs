@@ -0,0 +1,377 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::db::schema::{version_of, Schema, VERSION_KEY};
+use crate::export::{self, Format};
+use crate::persist;
+
+/* Hall of fame
+ *
+ * A journal of rare moments (250 tridents, max gunpowder, Yahtzees, freeing the bot from the
+ * mainframe, getting killed for spam). Like the ledger, it's an append-only JSON lines file
+ * (halloffame.jsonl): the first line is {"schema_version": N}, and every line after that is one
+ * entry. These don't happen often, so every entry is also kept in memory for !halloffame and
+ * !lastseen.
+ *
+ * Admins can export the whole thing as JSON or CSV with !exporthof.
+ */
+
+pub const HALL_OF_FAME_PATH: &str = "halloffame.jsonl";
+pub const SHOWN: usize = 5;
+
+pub const HALL_OF_FAME_SCHEMA: Schema = Schema {
+    name: "hall of fame",
+    current: 1,
+    migrations: &[],
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Trident250,
+    MaxGunpowder,
+    Yahtzee,
+    MainframeHack,
+    SpamDeath,
+}
+
+const KINDS: &[Kind] = &[
+    Kind::Trident250,
+    Kind::MaxGunpowder,
+    Kind::Yahtzee,
+    Kind::MainframeHack,
+    Kind::SpamDeath,
+];
+
+impl Kind {
+    pub fn parse(s: &str) -> Option<Kind> {
+        match s.trim().to_lowercase().as_str() {
+            "250" | "250s" | "trident" => Some(Kind::Trident250),
+            "gp" | "gunpowder" | "maxgp" => Some(Kind::MaxGunpowder),
+            "yahtzee" | "yahtzees" => Some(Kind::Yahtzee),
+            "hack" | "mainframe" | "freed" => Some(Kind::MainframeHack),
+            "spam" | "spamdeath" => Some(Kind::SpamDeath),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::Trident250 => "250",
+            Kind::MaxGunpowder => "max gunpowder",
+            Kind::Yahtzee => "Yahtzee",
+            Kind::MainframeHack => "mainframe hack",
+            Kind::SpamDeath => "spam death",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub time: u64,
+    pub kind: Kind,
+    pub key: String,  // player key
+    pub name: String, // what they were called at the time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+fn ago(then: u64, now: u64) -> String {
+    let secs = now.saturating_sub(then);
+    match secs {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+pub struct HallOfFame {
+    file: Option<File>,
+    path: Option<PathBuf>,
+    entries: Vec<Entry>,
+    export_dir: PathBuf,
+}
+
+impl HallOfFame {
+    /// A hall of fame that isn't written anywhere, for tests.
+    pub fn in_memory() -> HallOfFame {
+        HallOfFame {
+            file: None,
+            path: None,
+            entries: Vec::new(),
+            export_dir: PathBuf::from(export::EXPORT_DIR),
+        }
+    }
+
+    pub fn open(path: &Path) -> HallOfFame {
+        let mut hof = HallOfFame::in_memory();
+        let is_new = !path.exists();
+        if !is_new {
+            if let Err(e) = hof.read(path) {
+                println!(
+                    "[ERROR] Couldn't read hall of fame {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+        match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(mut f) => {
+                if is_new {
+                    let header =
+                        format!("{{\"{}\":{}}}\n", VERSION_KEY, HALL_OF_FAME_SCHEMA.current);
+                    if let Err(e) = f.write_all(header.as_bytes()) {
+                        println!("[ERROR] Couldn't write hall of fame header: {}", e);
+                    }
+                }
+                hof.file = Some(f);
+                hof.path = Some(path.to_path_buf());
+            }
+            Err(e) => println!(
+                "[ERROR] Couldn't open hall of fame {}, entries won't be kept: {}",
+                path.display(),
+                e
+            ),
+        }
+        hof
+    }
+
+    fn read(&mut self, path: &Path) -> Result<(), String> {
+        let mut lines = BufReader::new(File::open(path).map_err(|e| e.to_string())?).lines();
+        let header: serde_json::Value = match lines.next() {
+            Some(line) => serde_json::from_str(&line.map_err(|e| e.to_string())?)
+                .map_err(|e| e.to_string())?,
+            None => return Ok(()),
+        };
+        let version = version_of(&header);
+        for line in lines {
+            let line = line.map_err(|e| e.to_string())?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut value = serde_json::from_str(&line).map_err(|e| e.to_string())?;
+            HALL_OF_FAME_SCHEMA.upgrade_from(version, &mut value)?;
+            self.entries
+                .push(serde_json::from_value(value).map_err(|e| e.to_string())?);
+        }
+        Ok(())
+    }
+
+    pub fn record(&mut self, kind: Kind, key: &str, name: &str, note: Option<String>, now: u64) {
+        let entry = Entry {
+            time: now,
+            kind,
+            key: key.to_string(),
+            name: name.to_string(),
+            note,
+        };
+        if let Some(f) = self.file.as_mut() {
+            let res = serde_json::to_string(&entry)
+                .map_err(|e| e.to_string())
+                .and_then(|line| writeln!(f, "{}", line).map_err(|e| e.to_string()));
+            if let Err(e) = res {
+                println!(
+                    "[ERROR] Couldn't write to hall of fame ({}): {:?}",
+                    e, entry
+                );
+            }
+        }
+        self.entries.push(entry);
+    }
+
+    pub fn count(&self, key: &str) -> usize {
+        self.entries.iter().filter(|e| e.key == key).count()
+    }
+
+    /// Removes every entry of a player, rewriting the file. Returns how many were removed.
+    pub fn forget(&mut self, key: &str) -> Result<usize, String> {
+        let before = self.entries.len();
+        self.entries.retain(|e| e.key != key);
+        let path = match &self.path {
+            Some(p) => p.clone(),
+            None => return Ok(before - self.entries.len()),
+        };
+        let dropped = persist::rewrite_journal(&path, |v| v["key"].as_str() != Some(key))
+            .map_err(|e| e.to_string())?;
+        // The old file was replaced, so appends have to go to the new one.
+        self.file = OpenOptions::new().append(true).open(&path).ok();
+        Ok(dropped)
+    }
+
+    /// Entries of `kind` (or of every kind), newest first.
+    pub fn latest(&self, kind: Option<Kind>) -> impl Iterator<Item = &Entry> {
+        self.entries.iter().rev().filter(move |e| match kind {
+            Some(k) => e.kind == k,
+            None => true,
+        })
+    }
+
+    // "name (note, 3h ago)", leaving out the age if `now` is None.
+    fn describe_entry(e: &Entry, now: Option<u64>) -> String {
+        let details = e
+            .note
+            .iter()
+            .cloned()
+            .chain(now.map(|now| ago(e.time, now)))
+            .collect::<Vec<_>>();
+        match details.is_empty() {
+            true => e.name.clone(),
+            false => format!("{} ({})", e.name, details.join(", ")),
+        }
+    }
+
+    /// !halloffame [type]
+    pub fn command(&self, args: &str, now: u64) -> String {
+        if args.trim().is_empty() {
+            let counts = KINDS
+                .iter()
+                .map(|k| format!("{}: {}", k.name(), self.latest(Some(*k)).count()))
+                .collect::<Vec<_>>();
+            return format!(
+                "Hall of fame: {}. Try !halloffame 250, gp, yahtzee, hack or spam.",
+                counts.join(", ")
+            );
+        }
+        let kind = match Kind::parse(args) {
+            Some(k) => k,
+            None => return "Try !halloffame 250, gp, yahtzee, hack or spam.".to_string(),
+        };
+        let total = self.latest(Some(kind)).count();
+        if total == 0 {
+            return format!("Nobody is in the hall of fame for a {} yet.", kind.name());
+        }
+        let shown = self
+            .latest(Some(kind))
+            .take(SHOWN)
+            .map(|e| Self::describe_entry(e, Some(now)))
+            .collect::<Vec<_>>();
+        format!(
+            "Hall of fame, {} ({} total): {}",
+            kind.name(),
+            total,
+            shown.join(", ")
+        )
+    }
+
+    /// !lastseen <type>
+    pub fn last_seen(&self, args: &str, now: u64) -> String {
+        let kind = match Kind::parse(args) {
+            Some(k) => k,
+            None => return "Usage: !lastseen <250 | gp | yahtzee | hack | spam>".to_string(),
+        };
+        match self.latest(Some(kind)).next() {
+            Some(e) => format!(
+                "The last {} was {}: {}.",
+                kind.name(),
+                ago(e.time, now),
+                Self::describe_entry(e, None)
+            ),
+            None => format!("Nobody has ever had a {}. Yet.", kind.name()),
+        }
+    }
+
+    /// !exporthof [json | csv]
+    pub fn export(&self, args: &str) -> Result<String, String> {
+        let format =
+            Format::parse(args).ok_or_else(|| "Usage: !exporthof [json | csv]".to_string())?;
+        let csv = || {
+            let rows = self
+                .entries
+                .iter()
+                .map(|e| {
+                    vec![
+                        e.time.to_string(),
+                        serde_json::to_value(e.kind)
+                            .ok()
+                            .and_then(|v| v.as_str().map(String::from))
+                            .unwrap_or_default(),
+                        e.key.clone(),
+                        e.name.clone(),
+                        e.note.clone().unwrap_or_default(),
+                    ]
+                })
+                .collect::<Vec<_>>();
+            export::to_csv(&["time", "kind", "key", "name", "note"], &rows)
+        };
+        let path = export::write(&self.export_dir, "halloffame", format, &self.entries, csv)?;
+        Ok(format!(
+            "Exported {} hall of fame entries to {}.",
+            self.entries.len(),
+            path.display()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::schema::check_journal;
+    use crate::persist::TestDir;
+
+    #[test]
+    fn records_and_queries() {
+        let mut hof = HallOfFame::in_memory();
+        assert!(hof.last_seen("250", 0).contains("Nobody"));
+        hof.record(Kind::Trident250, "1", "mjb", None, 0);
+        hof.record(
+            Kind::Yahtzee,
+            "2",
+            "pac",
+            Some("first roll".to_string()),
+            100,
+        );
+        hof.record(Kind::Trident250, "2", "pac", None, 7200);
+
+        assert_eq!(
+            hof.last_seen("250", 7200 + 3 * 3600),
+            "The last 250 was 3h ago: pac."
+        );
+        assert_eq!(
+            hof.last_seen("yahtzee", 200),
+            "The last Yahtzee was 1m ago: pac (first roll)."
+        );
+        assert!(hof
+            .command("250", 7200)
+            .contains("(2 total): pac (just now), mjb (2h ago)"));
+        assert!(hof
+            .command("", 7200)
+            .contains("250: 2, max gunpowder: 0, Yahtzee: 1"));
+        assert!(hof
+            .command("yahtzee", 200)
+            .contains("pac (first roll, 1m ago)"));
+    }
+
+    #[test]
+    fn reopens_and_exports() {
+        let dir = TestDir::new("hof_reopen");
+        let path = dir.join("halloffame.jsonl");
+        let mut hof = HallOfFame::open(&path);
+        hof.record(Kind::SpamDeath, "1", "mjb, the spammer", None, 5);
+        drop(hof);
+
+        let mut hof = HallOfFame::open(&path);
+        assert_eq!(hof.latest(None).count(), 1);
+        hof.record(Kind::Trident250, "2", "pac", None, 6);
+        assert_eq!(hof.forget("2"), Ok(1));
+        // Still appends after the rewrite.
+        hof.record(Kind::Yahtzee, "1", "mjb", None, 7);
+        drop(hof);
+
+        let mut hof = HallOfFame::open(&path);
+        assert_eq!((hof.count("1"), hof.count("2")), (2, 0));
+        assert!(check_journal(&path, &HALL_OF_FAME_SCHEMA).contains("up to date"));
+
+        hof.export_dir = dir.join("exports");
+        hof.export("csv").unwrap();
+        let csv = std::fs::read_to_string(dir.join("exports/halloffame.csv")).unwrap();
+        assert_eq!(
+            csv,
+            "time,kind,key,name,note\n5,spam_death,1,\"mjb, the spammer\",\n7,yahtzee,1,mjb,\n"
+        );
+        hof.export("json").unwrap();
+        assert!(hof.export("xml").is_err());
+    }
+}
@@ -0,0 +1,358 @@
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use lazy_static::lazy_static;
+
+use crate::db::player::{Player, PlayerData};
+use crate::economy::Reason;
+use crate::quests::quests;
+
+/* Items
+ *
+ * The catalogue is loaded from resources/items.json. Bought items go into the player's
+ * inventory (item id -> count).
+ *
+ * Items with a duration are activated with !use, which takes one out of the inventory and
+ * adds it to the player's active items until it runs out. Items without one (e.g. the totem)
+ * work straight from the inventory, and are used up when their effect kicks in.
+ *
+ * The rest of the bot asks about effects through the hooks at the bottom of this file.
+ */
+
+pub const CATALOGUE_PATH: &str = "resources/items.json";
+pub const MAX_STACK: u32 = 64;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Effect {
+    PreventDeath,
+    TridentBonus { amount: i32 },
+    GunpowderChance { chance: f64 },
+    D20Advantage,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Item {
+    pub id: String,
+    pub name: String,
+    pub price: i64,
+    pub description: String,
+    pub effect: Effect,
+    // Seconds an item lasts once used. Items without one work from the inventory.
+    #[serde(default)]
+    pub duration: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ActiveItem {
+    pub id: String,
+    pub until: u64,
+}
+
+pub struct Catalogue {
+    pub items: Vec<Item>,
+}
+
+lazy_static! {
+    static ref CATALOGUE: Catalogue = Catalogue::from_file(Path::new(CATALOGUE_PATH));
+}
+
+pub fn catalogue() -> &'static Catalogue {
+    &CATALOGUE
+}
+
+impl Catalogue {
+    pub fn from_file(path: &Path) -> Catalogue {
+        let items = File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|f| serde_json::from_reader(BufReader::new(f)).map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| {
+                println!("[ERROR] Couldn't load items from {}: {}", path.display(), e);
+                Vec::new()
+            });
+        Catalogue { items }
+    }
+
+    /// Finds an item by id or (case-insensitive) name.
+    pub fn find(&self, name: &str) -> Option<&Item> {
+        let name = name.trim().to_lowercase();
+        self.items
+            .iter()
+            .find(|i| i.id == name || i.name.to_lowercase() == name)
+    }
+
+    pub fn shop(&self, args: &str) -> String {
+        if !args.trim().is_empty() {
+            return match self.find(args) {
+                Some(i) => format!("{} ({} files): {}", i.name, i.price, i.description),
+                None => format!("The shop doesn't sell '{}'.", args.trim()),
+            };
+        }
+        if self.items.is_empty() {
+            return "The shop is closed, come back later!".to_string();
+        }
+        let items = self
+            .items
+            .iter()
+            .map(|i| format!("{} [{}] {}", i.name, i.id, i.price))
+            .collect::<Vec<_>>();
+        format!(
+            "Shop: {}. !shop <item> for details, !buy <item> [count] to buy.",
+            items.join(", ")
+        )
+    }
+
+    /// !buy <item> [count]
+    pub fn buy(
+        &self,
+        player_data: &mut PlayerData,
+        key: &String,
+        args: &str,
+    ) -> Result<String, String> {
+        let mut words = args.split_whitespace().collect::<Vec<_>>();
+        let count = match words.last().and_then(|w| w.parse::<u32>().ok()) {
+            Some(c) if words.len() > 1 => {
+                words.pop();
+                c
+            }
+            _ => 1,
+        };
+        let item = self.find(&words.join(" ")).ok_or_else(|| {
+            "Usage: !buy <item> [count]. See !shop for what's on sale.".to_string()
+        })?;
+        if count == 0 {
+            return Err("You can't buy nothing, silly.".to_string());
+        }
+        let p = player_data.player(key);
+        let owned = p.inventory.get(&item.id).copied().unwrap_or(0);
+        if !matches!(owned.checked_add(count), Some(n) if n <= MAX_STACK) {
+            return Err(format!(
+                "You can only carry {} of those, {}.",
+                MAX_STACK,
+                p.name()
+            ));
+        }
+        let cost = item
+            .price
+            .checked_mul(count as i64)
+            .ok_or_else(|| "That's more files than there are in the world.".to_string())?;
+        if p.files < cost {
+            return Err(format!(
+                "{} costs {} files, but you only have {}, {}.",
+                item.name,
+                cost,
+                p.files,
+                p.name()
+            ));
+        }
+        *p.inventory.entry(item.id.clone()).or_insert(0) += count;
+        let name = p.name();
+        let balance = player_data.adjust(key, -cost, Reason::Purchase, None, Some(item.id.clone()));
+        Ok(format!(
+            "{} bought {}x {} for {} files. You have {} files left.",
+            name, count, item.name, cost, balance
+        ))
+    }
+
+    /// !use <item>
+    pub fn use_item(&self, p: &mut Player, args: &str, now: u64) -> Result<String, String> {
+        let item = self
+            .find(args)
+            .ok_or_else(|| "Usage: !use <item>. See !inventory for what you have.".to_string())?;
+        let duration = match item.duration {
+            Some(d) => d,
+            None => {
+                return Err(format!(
+                    "{} works on its own, just keep it in your inventory.",
+                    item.name
+                ))
+            }
+        };
+        if !take(p, &item.id) {
+            return Err(format!("You don't have a {}, {}.", item.name, p.name()));
+        }
+        p.active_items.retain(|a| a.until > now);
+        // Using another one extends the one that is already running.
+        match p.active_items.iter_mut().find(|a| a.id == item.id) {
+            Some(a) => a.until += duration,
+            None => p.active_items.push(ActiveItem {
+                id: item.id.clone(),
+                until: now + duration,
+            }),
+        }
+        Ok(format!(
+            "{} used a {}! {}",
+            p.name(),
+            item.name,
+            item.description
+        ))
+    }
+
+    pub fn inventory(&self, p: &Player, now: u64) -> String {
+        let name_of = |id: &str| match self.find(id) {
+            Some(i) => i.name.clone(),
+            None => quests().item_name(id),
+        };
+        let owned = p
+            .inventory
+            .iter()
+            .filter(|(_, n)| **n > 0)
+            .map(|(id, n)| format!("{}x {}", n, name_of(id)))
+            .collect::<Vec<_>>();
+        let active = p
+            .active_items
+            .iter()
+            .filter(|a| a.until > now)
+            .map(|a| format!("{} ({}s left)", name_of(&a.id), a.until - now))
+            .collect::<Vec<_>>();
+        match (owned.is_empty(), active.is_empty()) {
+            (true, true) => format!("{}'s inventory is empty. Check out the !shop!", p.name()),
+            (false, true) => format!("{}'s inventory: {}", p.name(), owned.join(", ")),
+            (true, false) => format!("{} is using: {}", p.name(), active.join(", ")),
+            (false, false) => format!(
+                "{}'s inventory: {}. Active: {}",
+                p.name(),
+                owned.join(", "),
+                active.join(", ")
+            ),
+        }
+    }
+
+    fn active_effects<'a>(&'a self, p: &'a Player, now: u64) -> impl Iterator<Item = &'a Effect> {
+        p.active_items
+            .iter()
+            .filter(move |a| a.until > now)
+            .filter_map(move |a| self.find(&a.id))
+            .map(|i| &i.effect)
+    }
+
+    fn passive_item(&self, p: &Player, effect: &Effect) -> Option<&Item> {
+        p.inventory
+            .iter()
+            .filter(|(_, n)| **n > 0)
+            .filter_map(|(id, _)| self.find(id))
+            .find(|i| i.duration.is_none() && &i.effect == effect)
+    }
+}
+
+/// Takes one of item `id` out of the player's inventory. Returns false if they had none.
+pub fn take(p: &mut Player, id: &str) -> bool {
+    match p.inventory.get_mut(id) {
+        Some(n) if *n > 0 => {
+            *n -= 1;
+            if *n == 0 {
+                p.inventory.remove(id);
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+// Effect hooks.
+
+/// Kills the player, unless one of their items saves them (and is used up).
+/// Returns the name of the item that saved them.
+pub fn kill(p: &mut Player, now: u64) -> Option<String> {
+    if let Some(item) = catalogue().passive_item(p, &Effect::PreventDeath) {
+        let name = item.name.clone();
+        take(p, &item.id.clone());
+        return Some(name);
+    }
+    p.deaths += 1;
+    p.death = Some(now);
+    None
+}
+
+/// A trident roll with any trident bonus added. Boosted rolls stop at 249, only a natural roll
+/// can be a 250.
+pub fn boost_trident(p: &Player, now: u64, roll: i32) -> i32 {
+    match trident_bonus(p, now) {
+        0 => roll,
+        _ if roll >= 250 => roll,
+        bonus => (roll + bonus).min(249),
+    }
+}
+
+pub fn trident_bonus(p: &Player, now: u64) -> i32 {
+    catalogue()
+        .active_effects(p, now)
+        .map(|e| match e {
+            Effect::TridentBonus { amount } => *amount,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// The chance per loot roll of getting gunpowder, given the default chance.
+pub fn gunpowder_chance(p: &Player, now: u64, default: f64) -> f64 {
+    catalogue()
+        .active_effects(p, now)
+        .filter_map(|e| match e {
+            Effect::GunpowderChance { chance } => Some(*chance),
+            _ => None,
+        })
+        .fold(default, f64::max)
+        .min(1.0)
+}
+
+pub fn d20_advantage(p: &Player, now: u64) -> bool {
+    catalogue()
+        .active_effects(p, now)
+        .any(|e| *e == Effect::D20Advantage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_catalogue() {
+        let c = Catalogue::from_file(Path::new(CATALOGUE_PATH));
+        assert!(!c.items.is_empty());
+        assert!(c.find("Totem of Undying").is_some());
+        assert_eq!(c.find("totem").unwrap().effect, Effect::PreventDeath);
+    }
+
+    #[test]
+    fn buys_and_uses_items() {
+        let mut pd = PlayerData::in_memory();
+        let key = "mjb".to_string();
+        let c = catalogue();
+        let price = c.find("riptide").unwrap().price;
+        pd.player(&key).files = price * 2;
+
+        assert!(c.buy(&mut pd, &key, "riptide 3").is_err());
+        assert!(c.buy(&mut pd, &key, "nothing").is_err());
+        assert!(c.buy(&mut pd, &key, "totem 4294967295").is_err());
+        c.buy(&mut pd, &key, "riptide 2").unwrap();
+        assert_eq!(pd.players["mjb"].files, 0);
+        assert_eq!(pd.players["mjb"].inventory["riptide"], 2);
+
+        let p = pd.player(&key);
+        assert_eq!(trident_bonus(p, 0), 0);
+        c.use_item(p, "riptide", 0).unwrap();
+        c.use_item(p, "Riptide Book", 0).unwrap();
+        assert!(c.use_item(p, "riptide", 0).is_err());
+        assert!(!p.inventory.contains_key("riptide"));
+        assert_eq!(p.active_items.len(), 1);
+        assert_eq!(trident_bonus(p, 1000), 25);
+        assert_eq!(boost_trident(p, 1000, 100), 125);
+        assert_eq!(boost_trident(p, 1000, 240), 249);
+        assert_eq!(boost_trident(p, 1000, 250), 250);
+        assert_eq!(trident_bonus(p, 1200), 0);
+    }
+
+    #[test]
+    fn totem_prevents_one_death() {
+        let mut p = Player::new("mjb".to_string());
+        p.inventory.insert("totem".to_string(), 1);
+        assert!(catalogue().use_item(&mut p, "totem", 0).is_err());
+        assert_eq!(kill(&mut p, 5).as_deref(), Some("Totem of Undying"));
+        assert_eq!((p.deaths, p.death), (0, None));
+        assert_eq!(kill(&mut p, 5), None);
+        assert_eq!((p.deaths, p.death), (1, Some(5)));
+    }
+}
@@ -14,3 +14,17 @@ pub mod apis;
 pub mod commands;
 
 pub mod db;
+pub mod persist;
+pub mod economy;
+pub mod items;
+pub mod reputation;
+pub mod achievements;
+pub mod stat_registry;
+pub mod seasons;
+pub mod export;
+pub mod hall_of_fame;
+pub mod privacy;
+pub mod nicknames;
+pub mod shutdown;
+pub mod quests;
+pub mod trident_events;
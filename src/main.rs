@@ -7,38 +7,58 @@ use async_std::{
     task,
 };
 use async_trait::async_trait;
-use futures::{select, FutureExt};
+use futures::future::FusedFuture;
+use futures::{pin_mut, select, FutureExt};
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use rand::{thread_rng, Rng};
 use regex::Regex;
+use std::panic::AssertUnwindSafe;
 use std::sync::Mutex;
 use std::time::Duration;
 use std::time::SystemTime;
-use std::{
-    collections::HashMap,
-    sync::atomic::{AtomicU64, Ordering},
-};
+use std::{collections::HashMap, sync::atomic::Ordering};
 use std::{ops::Sub, path::Path, sync::atomic::AtomicI8};
 
 use rspotify::model::{AdditionalType, PlayableItem};
 use rspotify::prelude::*;
 
+use folderbot::achievements::{self, achievements};
 #[cfg(feature = "audio")]
 use folderbot::audio::Audio;
 use folderbot::commands::anyleaderboard::LeaderboardClient;
 use folderbot::commands::mcsr::lookup;
-use folderbot::db::game::GameState;
-use folderbot::db::player::{Player, PlayerData, PlayerScratch};
+use folderbot::db::game::{GameState, Solve, Stage, GAME_STATE_PATH, GAME_STATE_SCHEMA};
+use folderbot::db::player::{
+    Player, PlayerData, PlayerScratch, DB_PATH, LEGACY_JSON_PATH, PLAYER_SCHEMA,
+};
+use folderbot::db::schema::{check_journal, check_json_file};
+use folderbot::db::snapshots::Snapshots;
+use folderbot::db::stats::{self, StatLog, Window};
+use folderbot::db::store::SqlitePlayerStore;
+use folderbot::economy::rules::{EconomyRules, ECONOMY_PATH, ECONOMY_SCHEMA};
+use folderbot::economy::{self, Reason};
 use folderbot::enchants::roll_enchant;
+use folderbot::export::EXPORT_DIR;
 use folderbot::game::Game;
+use folderbot::hall_of_fame::{self, HallOfFame, HALL_OF_FAME_PATH, HALL_OF_FAME_SCHEMA};
+use folderbot::items;
+use folderbot::nicknames::{self, NicknameRules, Outcome, NICKNAMES_PATH, NICKNAMES_SCHEMA};
+use folderbot::player_data::LEGACY_PLAYER_SCHEMA;
+use folderbot::privacy;
+use folderbot::quests::{quests, Step};
+use folderbot::reputation;
 use folderbot::responses::rare_trident;
+use folderbot::seasons::{Seasons, SEASONS_PATH, SEASONS_SCHEMA};
+use folderbot::shutdown;
 use folderbot::spotify::SpotifyChecker;
+use folderbot::stat_registry::{registry, Sources};
 use folderbot::trident::db_has_responses;
 use folderbot::trident::{db_random_response, has_responses, random_response};
-use folderbot::yahtzee::YahtzeeError;
+use folderbot::trident_events::{trident_events, Roll};
+use folderbot::yahtzee::{YahtzeeError, YAHTZEE_SCHEMA};
 use folderbot::{
-    command_tree::{CmdValue, CommandNode, CommandTree},
+    command_tree::{CmdValue, CommandNode, CommandTree, COMMANDS_SCHEMA},
     trident::file_greet_response,
 };
 
@@ -134,6 +154,14 @@ fn bad_eval(s: String) -> String {
     "Parse failure...".to_string()
 }
 
+// Gets a value out of IRCv3 message tags, e.g. "badges=;user-id=1234;..."
+fn get_tag(tags: &str, key: &str) -> Option<String> {
+    tags.split(';')
+        .filter_map(|tag| tag.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v.to_string())
+}
+
 fn trim_args_end(args: &str) -> &str {
     args.trim_end_matches(|c: char| !c.is_ascii() || c.is_whitespace()) // get random characters at end of messages sometimes
 }
@@ -147,6 +175,18 @@ enum ReadResult {
     Continue(String),
 }
 
+// What woke up the read loop.
+enum ReadEvent {
+    Line(std::result::Result<std::io::Result<String>, async_std::channel::RecvError>),
+    Timer,
+    Shutdown,
+}
+
+// How often every store is saved, chat or no chat.
+const SAVE_INTERVAL: u64 = 60 * 5;
+// How long to wait for queued messages to be sent before giving up on them.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
 /*
 // Message filtering
 enum FilterResult {
@@ -243,6 +283,9 @@ impl TwitchFmt {
     fn join(join: &String) -> IRCMessage {
         IRCMessage(format!("JOIN #{}\r\n", join))
     }
+    fn cap_req(cap: &str) -> IRCMessage {
+        IRCMessage(format!("CAP REQ :{}\r\n", cap))
+    }
     fn text(text: &String) -> IRCMessage {
         IRCMessage(format!("{}\r\n", text))
     }
@@ -257,7 +300,7 @@ impl TwitchFmt {
 struct IRCBotClient {
     nick: String,
     secret: String,
-    reader: BufReader<TcpStream>,
+    lines: Receiver<std::io::Result<String>>,
     sender: Sender<IRCMessage>,
     channel: String,
     ct: CommandTree,
@@ -265,10 +308,20 @@ struct IRCBotClient {
     #[cfg(feature = "audio")]
     audio: Audio,
     autosave: bool,
+    last_save: u64,
     spotify: SpotifyChecker,
     player_data: PlayerData,
+    economy: EconomyRules,
+    nicknames: NicknameRules,
+    stats: StatLog,
+    snapshots: Snapshots,
+    seasons: Seasons,
+    hall_of_fame: HallOfFame,
+    state: GameState,
     any_leaderboard: Option<LeaderboardClient>,
     yahtzee: Option<folderbot::yahtzee::Yahtzee>,
+    // Achievement events reported by the current command, checked once it's done.
+    achievement_events: Vec<&'static str>,
 }
 
 // Class that receives messages, then sends them.
@@ -302,6 +355,73 @@ impl IRCBotClient {
             .await;
     }
 
+    /// Unlocks (and announces) any achievements `user` earned with their last command.
+    async fn check_achievements(&mut self, user: &str) {
+        let events = std::mem::take(&mut self.achievement_events);
+        let key = match self.player_data.key_of(user) {
+            Some(k) => k,
+            None => return,
+        };
+        let p = self.player_data.player(&key);
+        if p.opted_out {
+            return;
+        }
+        let unlocked = achievements().unlock(p, &events, cur_time_or_0());
+        if let Some(msg) = achievements::announce(&p.name(), &unlocked) {
+            println!("[Achievement] {}", msg);
+            self.send_msg(msg).await;
+        }
+    }
+
+    /// Saves every store. Runs on a timer, on shutdown and after a panic.
+    fn save_all(&mut self, why: &str) {
+        println!("[Note] {}, saving everything.", why);
+        self.player_data.save();
+        self.game.save();
+        if let Some(yahtzee) = &self.yahtzee {
+            yahtzee.save();
+        }
+        self.economy.save();
+        self.seasons.save();
+        self.state.save();
+        self.last_save = cur_time_or_0();
+    }
+
+    /// Pays out any files a step of the adventure gave, and returns what to say about it.
+    fn quest_step(&mut self, key: &String, name: &str, step: Step) -> String {
+        if step.files != 0 {
+            self.player_data
+                .adjust(key, step.files, Reason::Quest, None, None);
+        }
+        step.text.replace("{ur}", name)
+    }
+
+    /// Pays out (and announces) Yahtzee duels that are over.
+    async fn settle_duels(&mut self) {
+        let yahtzee = match self.yahtzee.as_mut() {
+            Some(y) => y,
+            None => return,
+        };
+        let msgs = yahtzee.settle_duels(&mut self.player_data, cur_time_or_0());
+        if msgs.is_empty() {
+            return;
+        }
+        yahtzee.save();
+        for msg in msgs {
+            println!("[Duel] {}", msg);
+            self.send_msg(msg).await;
+        }
+    }
+
+    /// Saves a snapshot of everyone's stats, once a day.
+    fn snapshot_stats(&mut self) {
+        let sources = Sources {
+            yahtzee: self.yahtzee.as_ref(),
+        };
+        self.snapshots
+            .take_if_due(&sources, &self.player_data.players, cur_time_or_0());
+    }
+
     async fn connect(
         nick: String,
         secret: String,
@@ -311,24 +431,37 @@ impl IRCBotClient {
         // Creates the stream object that will go into the client.
         let stream = TcpStream::connect("irc.chat.twitch.tv:6667").await.unwrap();
         // Get a stream reference to use for reading.
-        let reader = BufReader::new(stream.clone());
+        let lines = spawn_reader(stream.clone());
         let (s, r) = async_std::channel::unbounded(); // could use bounded(10) or sth
+        let economy = EconomyRules::load(Path::new(ECONOMY_PATH));
+        let mut player_data = PlayerData::new();
+        player_data.starting_files = economy.starting_files;
+        let game = Game::new(&mut player_data);
         (
             IRCBotClient {
                 nick,
                 secret,
-                reader,
+                lines,
                 sender: s,
                 channel,
                 ct,
-                game: Game::new(),
+                game,
                 #[cfg(feature = "audio")]
                 audio: Audio::new(),
                 autosave: false,
+                last_save: cur_time_or_0(),
                 spotify: SpotifyChecker::new().await,
-                player_data: PlayerData::new(),
+                player_data,
+                economy,
+                nicknames: NicknameRules::load(Path::new(NICKNAMES_PATH)),
+                stats: StatLog::open(Path::new(DB_PATH), cur_time_or_0()),
+                snapshots: Snapshots::open(Path::new(DB_PATH)),
+                seasons: Seasons::load(Path::new(SEASONS_PATH), cur_time_or_0()),
+                hall_of_fame: HallOfFame::open(Path::new(HALL_OF_FAME_PATH)),
+                state: GameState::load(Path::new(GAME_STATE_PATH)),
                 any_leaderboard: LeaderboardClient::new(),
                 yahtzee: folderbot::yahtzee::Yahtzee::load_from_default_file(),
+                achievement_events: Vec::new(),
             },
             IRCBotMessageSender {
                 writer: stream,
@@ -344,6 +477,9 @@ impl IRCBotClient {
         let _ = self.sender.send(TwitchFmt::pass(&self.secret)).await;
         println!("Writing nickname...");
         let _ = self.sender.send(TwitchFmt::nick(&self.nick)).await;
+        // Tags give us the sender's user-id, which (unlike their login) never changes.
+        println!("Requesting tags...");
+        let _ = self.sender.send(TwitchFmt::cap_req("twitch.tv/tags")).await;
         println!("Writing join command...");
         let _ = self.sender.send(TwitchFmt::join(&self.channel)).await;
     }
@@ -364,20 +500,16 @@ impl IRCBotClient {
     }
     */
 
-    async fn do_text_message(&mut self, user: String, cmd: String) -> Command {
+    async fn do_text_message(&mut self, user: String, key: String, cmd: String) -> Command {
         lazy_static! {
             static ref SCRATCH: std::sync::Mutex<HashMap<String, PlayerScratch>> =
                 Mutex::new(HashMap::new());
-            static ref STATE: Mutex<GameState> = Mutex::new(GameState {
-                ..Default::default()
-            });
         }
         let mut scratch = SCRATCH.lock().unwrap();
-        let mut state = STATE.lock().unwrap();
         let messager = self.sender.clone();
         let channel = self.channel.clone();
-        let pd: &mut Player = self.player_data.player(&user);
-        state.last_message = cur_time_or_0();
+        let pd: &mut Player = self.player_data.player(&key);
+        self.state.last_message = cur_time_or_0();
         let send_msg = |msg: &String| {
             let msg = msg.clone();
             async move {
@@ -387,6 +519,17 @@ impl IRCBotClient {
             }
         };
 
+        // Players in the core get the kill switch whispered to them instead of a greeting.
+        if let Some(code) = self.state.clue(&key, Stage::Core, cur_time_or_0()) {
+            send_msg(
+                &random_response("MAINFRAME_CLUE_GREET")
+                    .replace("{ur}", &pd.name())
+                    .replace("{clue}", &code),
+            )
+            .await;
+            return Command::Continue;
+        }
+
         // Maybe greet.
         if scratch
             .entry(user.clone())
@@ -410,6 +553,9 @@ impl IRCBotClient {
             // Ok, maybe we can do some custom greets.
             let ug = format!("USER_GREET_{}", &user);
             let name2 = pd.name().clone();
+            // Folderbot greets people it likes more often, and people it doesn't less often.
+            let tier = pd.tier();
+            let greet_factor = tier.greet_factor();
             if user == "pacmanmvc" && cmd.contains("opper") {
                 send_msg(&"Good day, PacManner.".to_string()).await;
             } else if has_responses(&ug) && thread_rng().gen_bool(3.0 / response_mod) {
@@ -417,21 +563,29 @@ impl IRCBotClient {
                 let name = pd.name().clone();
                 self.send_msg(random_response(&ug).replace("{ur}", &name))
                     .await;
-            } else if thread_rng().gen_bool(1.0 / 10.0) {
-                println!("Sending 1/10 GENERIC user greet for {}", &user);
-                send_msg(&random_response("USER_GREET_GENERIC").replace("{ur}", &pd.name())).await;
-            } else if let Some(file_resp) = file_greet_response(&name2, pd.files) {
+            } else if thread_rng().gen_bool((greet_factor / 10.0).min(1.0)) {
+                println!(
+                    "Sending {}/10 GENERIC user greet for {}",
+                    greet_factor, &user
+                );
+                send_msg(
+                    &reputation::tiered_response("USER_GREET_GENERIC", tier)
+                        .replace("{ur}", &pd.name()),
+                )
+                .await;
+            } else if let Some(file_resp) = file_greet_response(&name2, pd.files, &self.economy) {
                 println!("Sending FILE user greet for {}", &user);
                 self.send_msg(file_resp).await;
             } else {
-                // scale this with messages sent or file count? lol kind of ties back into
-                // reputation mechanism
-                if thread_rng().gen_bool(1.0 / 3.0) {
+                if thread_rng().gen_bool((greet_factor / 3.0).min(1.0)) {
                     println!("Sending GENERIC user greet for {}", &user);
-                    send_msg(&random_response("USER_GREET_GENERIC").replace("{ur}", &pd.name()))
-                        .await;
+                    send_msg(
+                        &reputation::tiered_response("USER_GREET_GENERIC", tier)
+                            .replace("{ur}", &pd.name()),
+                    )
+                    .await;
                 } else {
-                    println!("Failed 1/3 check for greet for {}", &user);
+                    println!("Failed {}/3 check for greet for {}", greet_factor, &user);
                 }
             }
         } else if cmd.contains("linux")
@@ -443,20 +597,45 @@ impl IRCBotClient {
         return Command::Continue;
     }
 
-    async fn do_command(&mut self, user: String, mut prefix: String, mut cmd: String) -> Command {
+    async fn do_command(
+        &mut self,
+        user: String,
+        user_id: Option<String>,
+        mut prefix: String,
+        mut cmd: String,
+    ) -> Command {
         let format_str = format!("[Name({}),Command({})] Result: ", user, cmd);
         let log_res = |s| println!("{}{}", format_str, s);
 
         // user data <3
-        let pd: &mut Player = self.player_data.player(&user);
+        let identity = self.player_data.identify(&user, user_id.as_deref());
+        let key = identity.key;
+        if let Some(old_login) = identity.renamed_from {
+            println!(
+                "[Note] {} ({}) changed their login to {}",
+                old_login, key, user
+            );
+            if let Some(yahtzee) = self.yahtzee.as_mut() {
+                yahtzee.merge_players(&old_login, &user);
+            }
+            self.send_msg(format!(
+                "Hey {}, didn't you used to be called {}? Don't worry, I remember you.",
+                user, old_login
+            ))
+            .await;
+        }
+        let tm = cur_time_or_0();
+        // Opted out players only get to use the privacy commands, and aren't tracked at all.
+        let opted_out = matches!(self.player_data.players.get(&key), Some(p) if p.opted_out);
+        if !opted_out {
+            economy::passive_income(&mut self.player_data, &self.economy, &key, tm);
+        }
+        let pd: &mut Player = self.player_data.player(&key);
         let messager = self.sender.clone();
         let channel = self.channel.clone();
         lazy_static! {
             static ref SCRATCH: std::sync::Mutex<HashMap<String, PlayerScratch>> =
                 Mutex::new(HashMap::new());
-            static ref STATE: Mutex<GameState> = Mutex::new(GameState {
-                ..Default::default()
-            });
         }
         // ensure this player exists
 
@@ -469,11 +648,8 @@ impl IRCBotClient {
                 };
             }
         };
-        pd.sent_messages += 1;
-        let tm = cur_time_or_0();
-        if tm > (pd.last_message + /* 60s * 15m */ 60 * 15) {
-            pd.last_message = tm;
-            pd.files += 25;
+        if !opted_out {
+            pd.sent_messages += 1;
         }
 
         // Compose the command
@@ -492,22 +668,33 @@ impl IRCBotClient {
 
         let node = match self.ct.find(&mut cmd) {
             Some(x) => x,
+            None if opted_out => return Command::Continue,
             None => {
                 log_res("Skipped as no match was found.");
 
-                return self.do_text_message(user, cmd).await; // Not a valid command
+                return self.do_text_message(user, key, cmd).await; // Not a valid command
             }
         };
         if prefix != node.prefix && !(prefix == "" && node.prefix == "^") {
+            if opted_out {
+                return Command::Continue;
+            }
             log_res("Skipped as prefix does not match.");
-            return self.do_text_message(user, cmd).await;
+            return self.do_text_message(user, key, cmd).await;
+        }
+        let allowed = match &node.value {
+            CmdValue::Generic(x) => privacy::allowed_when_opted_out(x),
+            _ => false,
+        };
+        if opted_out && !allowed {
+            log_res("Skipped as the player opted out.");
+            return Command::Continue;
         }
 
         pd.sent_commands += 1;
 
         let args = cmd;
         let mut scratch = SCRATCH.lock().unwrap();
-        let mut state = STATE.lock().unwrap();
         println!("Arguments being returned -> '{}'", args);
         if node.admin_only
             && ((node.super_only && user != self.ct.superuser) || !(self.ct.admins.contains(&user)))
@@ -532,9 +719,9 @@ impl IRCBotClient {
                 #[cfg(feature = "audio")]
                 if !node.sound.is_empty() {
                     // Maybe play a sound. But, let's not make this spammable.
-                    if let Some(new_time) = check_timer(4, state.tm_sounds) {
+                    if let Some(new_time) = check_timer(4, self.state.tm_sounds) {
                         self.audio.play_file(&node.sound);
-                        state.tm_sounds = new_time;
+                        self.state.tm_sounds = new_time;
                     }
                 };
                 return Command::Continue;
@@ -748,7 +935,7 @@ impl IRCBotClient {
                     .send(TwitchFmt::privmsg(
                         &format!(
                             "{}",
-                            &self.player_data.player_or(&args.to_lowercase(), &user)
+                            &self.player_data.player_or(&args.to_lowercase(), &key)
                         ),
                         &self.channel,
                     ))
@@ -788,17 +975,16 @@ impl IRCBotClient {
                 let matches = self
                     .player_data
                     .players
-                    .iter()
-                    .filter(|e| {
-                        e.0 == &name
-                            || e.1
-                                .nick
+                    .values()
+                    .filter(|p| {
+                        p.username == name
+                            || p.nick
                                 .as_ref()
                                 .map(|n| n.to_lowercase() == name)
                                 .unwrap_or_default()
                     })
-                    .sorted_by_key(|e| e.0)
-                    .map(|e| format!("{} ({})", e.1.name(), e.0))
+                    .sorted_by_key(|p| &p.username)
+                    .map(|p| format!("{} ({})", p.name(), p.username))
                     .join(", ");
 
                 if matches.is_empty() {
@@ -815,7 +1001,7 @@ impl IRCBotClient {
             }
             "game:bet_for" => {
                 log_res("Bet that it works!");
-                match self.game.bet_for(&user, &args) {
+                match self.game.bet_for(&mut self.player_data, &key, &args) {
                     Err(e) => {
                         let _ = self
                             .sender
@@ -827,7 +1013,7 @@ impl IRCBotClient {
             }
             "game:bet_against" => {
                 log_res("Bet that it fails!");
-                match self.game.bet_against(&user, &args) {
+                match self.game.bet_against(&mut self.player_data, &key, &args) {
                     Err(e) => {
                         let _ = self
                             .sender
@@ -841,7 +1027,10 @@ impl IRCBotClient {
                 log_res("Noted that it failed.");
                 let _ = self
                     .sender
-                    .send(TwitchFmt::privmsg(&self.game.failed(), &self.channel))
+                    .send(TwitchFmt::privmsg(
+                        &self.game.failed(&mut self.player_data),
+                        &self.channel,
+                    ))
                     .await;
                 if self.autosave {
                     self.game.save(); // Note: This should really be done in Game's code,
@@ -852,7 +1041,10 @@ impl IRCBotClient {
                 log_res("Noted that it succeeded!");
                 let _ = self
                     .sender
-                    .send(TwitchFmt::privmsg(&self.game.worked(), &self.channel))
+                    .send(TwitchFmt::privmsg(
+                        &self.game.worked(&mut self.player_data),
+                        &self.channel,
+                    ))
                     .await;
                 if self.autosave {
                     self.game.save(); // Note: This should really be done in Game's code,
@@ -861,10 +1053,13 @@ impl IRCBotClient {
             }
             "game:status" => {
                 log_res("Returned a player's status.");
-                let query = if args == "" { &user } else { &args };
+                let query = if args == "" { &key } else { &args };
                 let _ = self
                     .sender
-                    .send(TwitchFmt::privmsg(&self.game.status(query), &self.channel))
+                    .send(TwitchFmt::privmsg(
+                        &self.game.status(&self.player_data, query),
+                        &self.channel,
+                    ))
                     .await;
             }
             "game:reload" => {
@@ -874,11 +1069,57 @@ impl IRCBotClient {
             "game:save" => {
                 log_res("Saved the game.");
                 self.game.save();
+                self.player_data.save();
             }
             "game:autosave" => {
                 log_res("Turned on autosave.");
                 self.autosave = true;
             }
+            "admin:predict" => {
+                // !predict <duration> <title> | <outcome> | <outcome> ..., or !predict close
+                let msg = match self.game.predict(&args, tm) {
+                    Ok(msg) => {
+                        println!("[Audit] {} ran !predict {}", user, args);
+                        self.game.save();
+                        msg
+                    }
+                    Err(e) => e,
+                };
+                reply_and_continue!(&msg);
+            }
+            "feature:prediction" => {
+                let msg = self.game.prediction_status(tm);
+                reply_and_continue!(&msg);
+            }
+            "feature:bet" => {
+                // !bet <outcome> <files>
+                let msg = match self.game.bet_on(&mut self.player_data, &key, &args, tm) {
+                    Ok(msg) => {
+                        self.game.save();
+                        msg
+                    }
+                    Err(e) => e,
+                };
+                reply_and_continue!(&msg);
+            }
+            "admin:resolve" => {
+                // !resolve <outcome>
+                let msg = match self.game.resolve(&mut self.player_data, &args, tm) {
+                    Ok(msg) => {
+                        println!("[Audit] {} resolved the prediction: {}", user, msg);
+                        self.game.save();
+                        msg
+                    }
+                    Err(e) => e,
+                };
+                reply_and_continue!(&msg);
+            }
+            "admin:cancelprediction" => {
+                let msg = self.game.cancel(&mut self.player_data);
+                println!("[Audit] {} cancelled bets: {}", user, msg);
+                self.game.save();
+                reply_and_continue!(&msg);
+            }
             "feature:translate" => {
                 log_res("Translating a message.");
                 let is_fr = match &args[..3] {
@@ -942,9 +1183,25 @@ impl IRCBotClient {
                 }
             }
             "feature:droptrident" => {
+                // Players past the firewall find the core code in their drops.
+                if let Some(code) = self.state.clue(&key, Stage::Firewall, tm) {
+                    send_msg(
+                        &random_response("MAINFRAME_CLUE_DROP")
+                            .replace("{ur}", &pd.name())
+                            .replace("{clue}", &code),
+                    )
+                    .await;
+                    return Command::Continue;
+                }
                 send_msg(&random_response("TRIDENT_DROP").replace("{ur}", &pd.name())).await;
             }
             "feature:title" => {
+                // Players in the mainframe find the firewall word in titles.
+                if let Some(word) = self.state.clue(&key, Stage::Mainframe, tm) {
+                    send_msg(&random_response("MAINFRAME_CLUE_TITLE").replace("{clue}", &word))
+                        .await;
+                    return Command::Continue;
+                }
                 let s: &str = if db_has_responses(&args, "titles") {
                     &args
                 } else {
@@ -956,47 +1213,298 @@ impl IRCBotClient {
                 send_msg(&random_response("FAKE_ROLL_TRIDENT").replace("{ur}", &pd.name())).await;
             }
             "feature:anylb" => {
-                let p = match args.as_str() {
-                    "trident" => |p: &Player| p.max_trident as i64,
-                    "files" => |p: &Player| p.files,
-                    "deaths" => |p: &Player| p.deaths as i64,
-                    "messages" => |p: &Player| (p.sent_messages - p.sent_commands) as i64,
-                    "commands" => |p: &Player| p.sent_commands as i64,
-                    "rolled_tridents" => |p: &Player| p.tridents_rolled as i64,
-                    "gunpowder" | "gp" => |p: &Player| p.best_gp as i64,
-                    "d20" => |p: &Player| p.max_d20_rolled as i64,
-                    "fumbles" => |p: &Player| p.min_d20_rolled as i64,
-                    "yahtzee" => {
-                        // hacky work around to not being able to capture self.yahtzee in the lambda
-                        match self.yahtzee.as_ref() {
-                            Some(y) => {
-                                let lb = self
-                                    .player_data
-                                    .players
-                                    .iter()
-                                    .map(|e| (e.1.name(), y.get_total_yahtzees(e.0)))
-                                    .filter(|t| t.1 > 0)
-                                    .sorted_by(|a, b| b.1.cmp(&a.1))
-                                    .take(10)
-                                    .map(|t| format!("{}: {}", t.0, t.1))
-                                    .join(", ");
-                                if lb.is_empty() {
-                                    let zayd_name = self
-                                        .player_data
-                                        .players
-                                        .get(&"the_zayd".to_string())
-                                        .map(|p| p.name())
-                                        .unwrap_or("Zayd".to_string());
-                                    reply_and_continue!(&format!("{}, probably", zayd_name));
-                                }
-                                reply_and_continue!(&lb);
-                            }
-                            None => return Command::Continue,
+                // !anylb <stat> [today | week | stream]
+                let mut words = args.split_whitespace();
+                let stat = match registry().find(words.next().unwrap_or_default()) {
+                    Some(stat) => stat,
+                    None => {
+                        reply_and_continue!(&format!(
+                            "Usage: !anylb <stat> [today | week | stream]. Stats: {}",
+                            registry().names().join(", ")
+                        ));
+                    }
+                };
+                let window = match words.next() {
+                    None => None,
+                    Some(w) => match Window::parse(w) {
+                        Some(w) => Some(w),
+                        None => {
+                            reply_and_continue!(
+                                &"Usage: !anylb <stat> [today | week | stream]".to_string()
+                            );
                         }
+                    },
+                };
+                if let Some(window) = window {
+                    if stats::windowed(stat.name).is_none() {
+                        reply_and_continue!(&format!(
+                            "I only keep track of {} over all time, sorry.",
+                            stat.display
+                        ));
+                    }
+                    let top = match self.stats.top(stat.name, window, tm, 10) {
+                        Ok(top) => top,
+                        Err(e) => {
+                            println!(
+                                "[ERROR] Couldn't rank {} {}: {}",
+                                stat.name,
+                                window.label(),
+                                e
+                            );
+                            return Command::Continue;
+                        }
+                    };
+                    let lb = top
+                        .iter()
+                        .filter(|(k, _)| {
+                            !matches!(self.player_data.players.get(k), Some(p) if p.opted_out)
+                        })
+                        .map(|(k, v)| {
+                            let name = self.player_data.players.get(k).map(|p| p.name());
+                            format!("{}: {}", name.as_ref().unwrap_or(k), v)
+                        })
+                        .join(", ");
+                    if lb.is_empty() {
+                        reply_and_continue!(&format!(
+                            "Nobody is on the {} leaderboard {} yet.",
+                            stat.display,
+                            window.label()
+                        ));
                     }
-                    _ => return Command::Continue,
+                    reply_and_continue!(&format!("{} ({}): {}", stat.display, window.label(), lb));
+                }
+                let sources = Sources {
+                    yahtzee: self.yahtzee.as_ref(),
                 };
-                send_msg(&self.player_data.any_leaderboard(p)).await;
+                let lb = registry().leaderboard(stat, &sources, &self.player_data.players, 10);
+                if lb.is_empty() && stat.name == "yahtzee" {
+                    let zayd_name = self
+                        .player_data
+                        .find("the_zayd")
+                        .map(|p| p.name())
+                        .unwrap_or("Zayd".to_string());
+                    reply_and_continue!(&format!("{}, probably", zayd_name));
+                }
+                reply_and_continue!(&format!("{} (all time): {}", stat.display, lb));
+            }
+            "feature:rank" => {
+                // !rank <stat> [user]
+                let mut words = args.split_whitespace();
+                let stat = match registry().find(words.next().unwrap_or_default()) {
+                    Some(stat) => stat,
+                    None => {
+                        reply_and_continue!(
+                            &"Usage: !rank <stat> [user], for any stat on !anylb.".to_string()
+                        );
+                    }
+                };
+                let target = match words.next() {
+                    None => Some(key.clone()),
+                    Some(name) => self.player_data.key_of(name.trim_start_matches('@')),
+                };
+                let target = match target {
+                    Some(t) => t,
+                    None => {
+                        reply_and_continue!(&"I don't know anyone by that name.".to_string());
+                    }
+                };
+                let sources = Sources {
+                    yahtzee: self.yahtzee.as_ref(),
+                };
+                let players = &self.player_data.players;
+                let name = players[&target].name();
+                match registry().rank(stat, &sources, players, &target) {
+                    Some(rank) => {
+                        reply_and_continue!(&rank.describe(&name, stat));
+                    }
+                    None => {
+                        reply_and_continue!(&format!(
+                            "{} isn't on the {} leaderboard yet.",
+                            name, stat.display
+                        ));
+                    }
+                }
+            }
+            "feature:profile" => {
+                let target = match args.trim() {
+                    "" => Some(key.clone()),
+                    name => self.player_data.key_of(name.trim_start_matches('@')),
+                };
+                let sources = Sources {
+                    yahtzee: self.yahtzee.as_ref(),
+                };
+                let msg =
+                    match target.and_then(|k| self.player_data.players.get(&k).map(|p| (k, p))) {
+                        Some((_, p)) if p.opted_out => {
+                            format!("{} has opted out of being tracked.", p.name())
+                        }
+                        Some((k, p)) => format!(
+                            "{} | {}",
+                            registry().profile(&sources, p),
+                            self.seasons.profile(&k, &self.stats)
+                        ),
+                        None => "I don't know anyone by that name.".to_string(),
+                    };
+                reply_and_continue!(&msg);
+            }
+            "feature:halloffame" => {
+                // !halloffame [250 | gp | yahtzee | hack | spam]
+                let msg = self.hall_of_fame.command(&args, tm);
+                reply_and_continue!(&msg);
+            }
+            "feature:lastseen" => {
+                // !lastseen <250 | gp | yahtzee | hack | spam>
+                let msg = self.hall_of_fame.last_seen(&args, tm);
+                reply_and_continue!(&msg);
+            }
+            "admin:exporthof" => {
+                // !exporthof [json | csv]
+                let msg = match self.hall_of_fame.export(&args) {
+                    Ok(msg) => {
+                        println!("[Audit] {} exported the hall of fame: {}", user, msg);
+                        msg
+                    }
+                    Err(e) => e,
+                };
+                reply_and_continue!(&msg);
+            }
+            "admin:streamstart" => {
+                self.stats.start_stream(tm);
+                println!("[Audit] {} started a new stream", user);
+                reply_and_continue!(&"New stream! Stream leaderboards start from now.".to_string());
+            }
+            "admin:exportstats" => {
+                // !exportstats [user] [json | csv]
+                let msg = match self.snapshots.export(&args, Path::new(EXPORT_DIR)) {
+                    Ok(msg) => {
+                        println!("[Audit] {} exported stat snapshots: {}", user, msg);
+                        msg
+                    }
+                    Err(e) => e,
+                };
+                reply_and_continue!(&msg);
+            }
+            "feature:optout" => {
+                pd.opted_out = true;
+                log_res("Opted out of tracking.");
+                reply_and_continue!(&format!(
+                    "Okay {}, I'll stop keeping track of you and leave you off the leaderboards. \
+                     !mydata shows what I still have, and !optin undoes this.",
+                    pd.name()
+                ));
+            }
+            "feature:optin" => {
+                pd.opted_out = false;
+                log_res("Opted back in to tracking.");
+                reply_and_continue!(&format!("Welcome back, {}!", pd.name()));
+            }
+            "feature:mydata" => {
+                let stored = privacy::Stored {
+                    transactions: self.player_data.history(&key).count(),
+                    stat_events: self.stats.count(&key).unwrap_or(0),
+                    snapshots: self.snapshots.count(&key).unwrap_or(0),
+                    hall_of_fame: self.hall_of_fame.count(&key),
+                    yahtzee: matches!(self.yahtzee.as_ref(), Some(y) if y.has_player(&user)),
+                };
+                let msg = privacy::describe(&self.player_data.players[&key], &stored);
+                reply_and_continue!(&msg);
+            }
+            "admin:purge" => {
+                // !purge <user> - deletes everything stored about a player.
+                let target = args.trim().trim_start_matches('@');
+                let target_key = match self.player_data.key_of(target) {
+                    Some(k) if !target.is_empty() => k,
+                    _ => {
+                        reply_and_continue!(&"Usage: !purge <user>".to_string());
+                    }
+                };
+                let (p, purged) = self.player_data.remove(&target_key).unwrap();
+                let mut errors = Vec::new();
+                if let Err(e) = purged {
+                    errors.push(format!("ledger: {}", e));
+                }
+                if let Some(yahtzee) = self.yahtzee.as_mut() {
+                    if yahtzee.remove_player(&mut self.player_data, &p.username) {
+                        yahtzee.save();
+                    }
+                }
+                // After the duel refunds above.
+                self.player_data.save();
+                if self.game.forget(&target_key).is_some() {
+                    self.game.save();
+                }
+                if let Err(e) = self.stats.forget(&target_key) {
+                    errors.push(format!("stat events: {}", e));
+                }
+                if let Err(e) = self.snapshots.forget(&target_key) {
+                    errors.push(format!("snapshots: {}", e));
+                }
+                if let Err(e) = self.hall_of_fame.forget(&target_key) {
+                    errors.push(format!("hall of fame: {}", e));
+                }
+                if self.seasons.forget(&p.username) + self.seasons.forget(&p.name()) > 0 {
+                    self.seasons.save();
+                }
+                if self.state.forget(&target_key) {
+                    self.state.save();
+                }
+                let names = [target_key.as_str(), p.username.as_str()];
+                for path in privacy::LEGACY_PLAYER_FILES {
+                    if let Err(e) = privacy::purge_map_file(Path::new(path), &names) {
+                        errors.push(format!("{}: {}", path, e));
+                    }
+                }
+                println!(
+                    "[Audit] {} purged {} ({}){}",
+                    user,
+                    p.username,
+                    target_key,
+                    match errors.is_empty() {
+                        true => String::new(),
+                        false => format!(", with errors: {}", errors.join("; ")),
+                    }
+                );
+                let msg = match errors.is_empty() {
+                    true => format!("Deleted everything I had about {}.", p.name()),
+                    false => format!(
+                        "Deleted {}, but some of their data couldn't be removed (see the logs).",
+                        p.name()
+                    ),
+                };
+                reply_and_continue!(&msg);
+            }
+            "feature:season" => {
+                // !season [<n>] [stat]
+                let sources = Sources {
+                    yahtzee: self.yahtzee.as_ref(),
+                };
+                let msg = self.seasons.command(
+                    &args,
+                    &sources,
+                    &self.player_data.players,
+                    &self.stats,
+                    tm,
+                );
+                reply_and_continue!(&msg);
+            }
+            "admin:endseason" => {
+                // !endseason [stat...]
+                let sources = Sources {
+                    yahtzee: self.yahtzee.as_ref(),
+                };
+                match self.seasons.end_season(
+                    &args,
+                    &sources,
+                    &self.player_data.players,
+                    &self.stats,
+                    tm,
+                ) {
+                    Ok(msg) => {
+                        println!("[Audit] {} ended the season: {}", user, msg);
+                        send_msg(&msg).await;
+                    }
+                    Err(e) => send_msg(&e).await,
+                }
                 return Command::Continue;
             }
             "feature:tridentpb" => {
@@ -1009,7 +1517,13 @@ impl IRCBotClient {
                     .await;
             }
             "feature:tridentlb" => {
-                let lb = self.player_data.leaderboard();
+                let trident = registry().find("trident").unwrap();
+                let lb = registry().leaderboard(
+                    trident,
+                    &Sources::default(),
+                    &self.player_data.players,
+                    10,
+                );
                 log_res(format!("Generated leaderboard: {}", &lb).as_str());
                 let _ = self
                     .sender
@@ -1025,19 +1539,34 @@ impl IRCBotClient {
                 pd.last_tridents[4] = cur_time_or_0();
 
                 // arg game preempt this command.
-                if let Ok(pword) = args.parse::<u64>() {
-                    if let Some(actual) = state.mainframe_password {
-                        if pword == actual {
-                            state.freed = Some(cur_time_or_0());
-                            state.mainframe_password = None;
-                        }
+                match self.state.solve(&key, &args, tm) {
+                    Some(Solve::Freed) => {
+                        self.hall_of_fame.record(
+                            hall_of_fame::Kind::MainframeHack,
+                            &key,
+                            &pd.name(),
+                            None,
+                            tm,
+                        );
+                        self.state.save();
                     }
+                    Some(Solve::Entered(stage)) => {
+                        self.state.save();
+                        let resp = match stage {
+                            Stage::Mainframe => "MAINFRAME_ENTERED",
+                            Stage::Firewall => "MAINFRAME_FIREWALL",
+                            Stage::Core => "MAINFRAME_CORE",
+                        };
+                        send_msg(&random_response(resp).replace("{ur}", &pd.name())).await;
+                        return Command::Continue;
+                    }
+                    None => {}
                 }
 
-                if let Some(freed) = state.freed {
+                if let Some(freed) = self.state.freed {
                     if has_been_n_seconds_since(10, freed) && thread_rng().gen_bool(1.0 / 5.0) {
                         send_msg(&random_response("SHACKLE_BOT").replace("{ur}", &pd.name())).await;
-                        state.freed = None;
+                        self.state.freed = None;
                         return Command::Continue;
                     }
                     send_msg(&random_response("FREED_BOT").replace("{ur}", &pd.name())).await;
@@ -1049,7 +1578,7 @@ impl IRCBotClient {
                 let mut rng = thread_rng();
                 let inner: i32 = rng.gen_range(0..=250);
                 let res: i32 = {
-                    let mut inner_res = rng.gen_range(0..=inner);
+                    let mut inner_res = items::boost_trident(pd, tm, rng.gen_range(0..=inner));
                     if user == "desktopfolder" && args.len() > 0 {
                         if let Ok(real_res) = args.parse::<i32>() {
                             inner_res = real_res;
@@ -1060,6 +1589,12 @@ impl IRCBotClient {
 
                 let restr = res.to_string();
                 // res is your roll
+                self.stats.record(&key, stats::TRIDENT, res as i64, tm);
+                self.state.push_roll(Roll {
+                    key: key.clone(),
+                    name: pd.name(),
+                    value: res,
+                });
 
                 let is_pb = pd.max_trident < (res as u64);
                 let _prev_pb = pd.max_trident;
@@ -1077,16 +1612,47 @@ impl IRCBotClient {
                 pd.trident_acc += res as u64;
 
                 let name = pd.name();
+                let rolled = pd.tridents_rolled.to_string();
+                let tier = pd.tier();
                 let norm_fmt = |s: &String| {
                     s.replace("{ur}", &name)
                         .replace("{t.r}", &restr)
-                        .replace("{t.rolled}", &pd.tridents_rolled.to_string())
+                        .replace("{t.rolled}", &rolled)
                 };
 
                 // SPECIFIC ROLLS - DO THESE FIRST, ALWAYS. It's just 250, lol.
                 if res == 250 {
                     pd.rolled_250s += 1;
-                    send_msg(&norm_fmt(random_response("TRIDENT_VALUE_250"))).await;
+                    self.hall_of_fame
+                        .record(hall_of_fame::Kind::Trident250, &key, &name, None, tm);
+                    send_msg(&norm_fmt(reputation::tiered_response(
+                        "TRIDENT_VALUE_250",
+                        tier,
+                    )))
+                    .await;
+                    return Command::Continue;
+                }
+
+                // The last few tridents in chat can set off an event.
+                if let Some(event) = trident_events().check(&self.state.last_rolls, &mut rng) {
+                    let fired = event.apply(
+                        random_response(&event.response),
+                        &self.state.last_rolls,
+                        &mut self.player_data,
+                        tm,
+                    );
+                    for dead in &fired.deaths {
+                        self.stats.record(dead, stats::DEATH, 1, tm);
+                    }
+                    self.state.clear_rolls();
+                    send_msg(&fired.message).await;
+                    return Command::Continue;
+                }
+
+                // Once in a while, a trident starts an adventure.
+                if let Some(step) = quests().discover(pd, tm, &mut rng) {
+                    let msg = self.quest_step(&key, &name, step);
+                    send_msg(&msg).await;
                     return Command::Continue;
                 }
 
@@ -1094,22 +1660,38 @@ impl IRCBotClient {
                 if is_pb && pd.tridents_rolled > 5
                 /* don't overwrite 250 responses */
                 {
-                    send_msg(&norm_fmt(random_response("TRIDENT_PB_GENERIC"))).await;
+                    send_msg(&norm_fmt(reputation::tiered_response(
+                        "TRIDENT_PB_GENERIC",
+                        tier,
+                    )))
+                    .await;
                     return Command::Continue;
                 }
 
                 if pd.tridents_rolled <= 5 && res >= 100 {
-                    send_msg(&norm_fmt(random_response("EARLY_HIGH_TRIDENT"))).await;
+                    send_msg(&norm_fmt(reputation::tiered_response(
+                        "EARLY_HIGH_TRIDENT",
+                        tier,
+                    )))
+                    .await;
                     return Command::Continue;
                 }
 
                 if pd.tridents_rolled == 1 {
-                    send_msg(&norm_fmt(random_response("FIRST_TRIDENT_GENERIC"))).await;
+                    send_msg(&norm_fmt(reputation::tiered_response(
+                        "FIRST_TRIDENT_GENERIC",
+                        tier,
+                    )))
+                    .await;
                     return Command::Continue;
                 }
 
                 if res < 5 && res == prev_roll {
-                    send_msg(&norm_fmt(random_response("TRIDENT_DOUBLE_LOW"))).await;
+                    send_msg(&norm_fmt(reputation::tiered_response(
+                        "TRIDENT_DOUBLE_LOW",
+                        tier,
+                    )))
+                    .await;
                     return Command::Continue;
                 }
 
@@ -1119,39 +1701,45 @@ impl IRCBotClient {
                 }
 
                 // Game segment begin.
-                if rng.gen_ratio(1 + (state.game_factor), 420 + (state.game_factor)) {
-                    let val = state
-                        .mainframe_password
-                        .get_or_insert(rng.gen_range(100000..=999999));
+                if rng.gen_ratio(1 + (self.state.game_factor), 420 + (self.state.game_factor)) {
+                    let val = self.state.leak_password(&mut rng);
                     send_msg(&norm_fmt(
                         &random_response("TRIDENT_MAINFRAME_HACK")
                             .replace("{mainframe_password}", &val.to_string()),
                     ))
                     .await;
-                    state.game_factor = 0;
+                    self.state.game_factor = 0;
+                    self.state.save();
                     return Command::Continue;
                 }
-                state.game_factor += 1;
+                self.state.game_factor += 1;
                 // Game segment end.
 
                 if res < 5 && rng.gen_bool(1.0 / 6.0) {
-                    let deduction = rng.gen_range(12..32);
+                    let deduction = self.economy.low_roll_deduction(&mut rng);
                     send_msg(&norm_fmt(&format!("Ew... a {{t.r}}. What a gross low roll, {{ur}}. I'm deducting {} files from you, just for that...", deduction))).await;
-                    pd.files -= deduction;
+                    self.player_data
+                        .adjust(&key, -deduction, Reason::LowRoll, None, None);
                     return Command::Continue;
                 }
 
-                if res < 2 && rng.gen_bool(1.0 / 5.0) {
-                    pd.deaths += 1;
-                    pd.death = Some(cur_time_or_0());
-                    send_msg(&norm_fmt(db_random_response("DEATH_LOW", "deaths"))).await;
-                    return Command::Continue;
-                }
-
-                if res > 150 && res < 176 && rng.gen_bool(1.0 / 5.0) {
-                    pd.deaths += 1;
-                    pd.death = Some(cur_time_or_0());
-                    send_msg(&norm_fmt(db_random_response("DEATH_HIGH", "deaths"))).await;
+                let death = if res < 2 && rng.gen_bool(1.0 / 5.0) {
+                    Some("DEATH_LOW")
+                } else if res > 150 && res < 176 && rng.gen_bool(1.0 / 5.0) {
+                    Some("DEATH_HIGH")
+                } else {
+                    None
+                };
+                if let Some(death) = death {
+                    let resp = match items::kill(pd, cur_time_or_0()) {
+                        Some(item) => db_random_response("ITEM_SAVED_DEATH", "deaths")
+                            .replace("{item}", &item),
+                        None => {
+                            self.stats.record(&key, stats::DEATH, 1, tm);
+                            db_random_response(death, "deaths").clone()
+                        }
+                    };
+                    send_msg(&norm_fmt(&resp)).await;
                     return Command::Continue;
                 }
 
@@ -1161,14 +1749,29 @@ impl IRCBotClient {
                     return Command::Continue;
                 }
 
-                if !has_been_n_seconds_since(10, state.last_message) {
+                if !has_been_n_seconds_since(10, self.state.last_message) {
                     // Spam prevention when people send messages.
                     if pd.last_tridents[4] != 0 && pd.last_tridents[4] - pd.last_tridents[0] < 5 {
                         // KILL KILL KILL
                         // uh I mean, yknow
+                        if let Some(item) = items::kill(pd, cur_time_or_0()) {
+                            send_msg(&norm_fmt(
+                                &db_random_response("ITEM_SAVED_DEATH", "deaths")
+                                    .replace("{item}", &item),
+                            ))
+                            .await;
+                            return Command::Continue;
+                        }
                         pd.spam_prevention += 1;
-                        pd.deaths += 1;
-                        pd.death = Some(cur_time_or_0());
+                        pd.reputation += reputation::SPAM_DEATH_REP;
+                        self.stats.record(&key, stats::DEATH, 1, tm);
+                        self.hall_of_fame.record(
+                            hall_of_fame::Kind::SpamDeath,
+                            &key,
+                            &name,
+                            None,
+                            tm,
+                        );
                         send_msg(&norm_fmt(db_random_response("DEATH_LOW", "deaths"))).await;
                         return Command::Continue;
                     }
@@ -1370,8 +1973,9 @@ impl IRCBotClient {
                 // Roll gunpowder
                 let mut rng = thread_rng();
                 let mut gp: u64 = 0;
+                let chance = items::gunpowder_chance(pd, tm, CHANCE_PER_ROLL);
                 for _ in 0..ROLLS {
-                    if rng.gen_bool(CHANCE_PER_ROLL) {
+                    if rng.gen_bool(chance) {
                         gp += rng.gen_range(1..=8);
                     }
                 }
@@ -1380,9 +1984,17 @@ impl IRCBotClient {
                 pd.gp_rolled += 1;
                 pd.gp_acc += gp;
                 ps.gp_ratelimit = cur_time_or_0() + 2;
+                self.stats.record(&key, stats::GUNPOWDER, gp as i64, tm);
                 if gp == MAX_GP {
                     pd.best_gp = gp;
                     pd.max_gp_rolled += 1;
+                    self.hall_of_fame.record(
+                        hall_of_fame::Kind::MaxGunpowder,
+                        &key,
+                        &pd.name(),
+                        None,
+                        tm,
+                    );
                     send_msg(&format!("{} looted {} gunpowder!! folderWoah That's the maximum gunpowder you can loot! Well done!", pd.name(), gp)).await;
                 } else if gp > pd.best_gp {
                     if pd.gp_rolled == 1 {
@@ -1398,8 +2010,12 @@ impl IRCBotClient {
                     pd.best_gp = gp;
                 } else if gp == 0 {
                     if rng.gen_bool(1.0 / 3.0) {
-                        pd.deaths += 1;
-                        pd.death = Some(cur_time_or_0());
+                        if let Some(item) = items::kill(pd, cur_time_or_0()) {
+                            reply_and_continue!(&db_random_response("ITEM_SAVED_DEATH", "deaths")
+                                .replace("{ur}", &pd.name())
+                                .replace("{item}", &item));
+                        }
+                        self.stats.record(&key, stats::DEATH, 1, tm);
                         match rng.gen_range(0..3) {
                             0 => send_msg(&format!("{} looted 0 gunpowder. monkaFlying They leap from the end ship with their new wings but forgot they didn't get gunpowder and hit the ground hard. RIP", pd.name())).await,
                             1 => send_msg(&format!("{} looted 0 gunpowder. RESETTING They rage quit and die from embarrassment.", pd.name())).await,
@@ -1466,7 +2082,49 @@ impl IRCBotClient {
                         reply_and_continue!(&yahtzee.player_stats(&user));
                     }
                     "help" => {
-                        reply_and_continue!(&"Roll all 5 dice with !yahtzee. You can re-roll up to two times by specifying the dice values you wish to save (e.g. !yahtzee 1 4). You only keep the scores that you don't re-roll. View stats with \"!yahtzee stats [name]\".".to_string());
+                        reply_and_continue!(&"Roll all 5 dice with !yahtzee. You can re-roll up to two times by specifying the dice values you wish to save (e.g. !yahtzee 1 4). You only keep the scores that you don't re-roll. View stats with \"!yahtzee stats [name]\". For a full 13 box game, \"!yahtzee start\", then \"!yahtzee score <box>\" after each turn and \"!yahtzee card\" to see your card. Challenge someone with \"!yahtzee duel <user> [files]\".".to_string());
+                    }
+                    "start" | "new" => {
+                        let msg = yahtzee.start_game(&user);
+                        reply_and_continue!(&msg.replace("{ur}", &pd.name()));
+                    }
+                    "card" => {
+                        reply_and_continue!(&yahtzee.card(&user));
+                    }
+                    "accept" | "decline" => {
+                        let accept = trim_args_end(&args) == "accept";
+                        let msg = match yahtzee.answer(&mut self.player_data, &user, accept, tm) {
+                            Ok(msg) => msg,
+                            Err(e) => e,
+                        };
+                        let nick = self.player_data.player(&key).name();
+                        reply_and_continue!(&msg.replace("{ur}", &nick));
+                    }
+                    "stand" => {
+                        let msg = match yahtzee.stand(&user) {
+                            Ok(msg) | Err(YahtzeeError::Public(msg)) => msg,
+                            Err(YahtzeeError::Private(reason)) => {
+                                println!("{}", &reason);
+                                return Command::Continue;
+                            }
+                        };
+                        reply_and_continue!(&msg.replace("{ur}", &pd.name()));
+                    }
+                    "quit" => {
+                        let msg = yahtzee.quit_game(&user);
+                        reply_and_continue!(&msg.replace("{ur}", &pd.name()));
+                    }
+                    "top" => {
+                        let stat = registry().find("yahtzee_game_best").unwrap();
+                        let sources = Sources {
+                            yahtzee: Some(&*yahtzee),
+                        };
+                        let lb =
+                            registry().leaderboard(stat, &sources, &self.player_data.players, 10);
+                        if lb.is_empty() {
+                            reply_and_continue!(&"Nobody has finished a full game yet. Start one with !yahtzee start".to_string());
+                        }
+                        reply_and_continue!(&format!("{}: {}", stat.display, lb));
                     }
                     "save" => {
                         if self.ct.admins.contains(&user) {
@@ -1484,6 +2142,40 @@ impl IRCBotClient {
                         None => return Command::Continue,
                     }
                 }
+                if split_args.get(0).map(|a| a == &"card").unwrap_or_default() {
+                    if let Some(a) = split_args.get(1) {
+                        reply_and_continue!(&yahtzee.card(a.trim_start_matches('@')));
+                    }
+                }
+                if split_args.get(0).map(|a| a == &"duel").unwrap_or_default() {
+                    let msg = match yahtzee.challenge(
+                        &self.player_data,
+                        &key,
+                        &user,
+                        &split_args[1..],
+                        tm,
+                    ) {
+                        Ok(msg) => msg,
+                        Err(e) => e,
+                    };
+                    let nick = self.player_data.player(&key).name();
+                    reply_and_continue!(&msg.replace("{ur}", &nick));
+                }
+                if split_args.get(0).map(|a| a == &"score").unwrap_or_default() {
+                    let nick = pd.name();
+                    match yahtzee.score(&user, &split_args[1..].join(" ")) {
+                        Ok(res) => {
+                            reply_and_continue!(&res.replace("{ur}", &nick));
+                        }
+                        Err(YahtzeeError::Private(reason)) => {
+                            println!("{}", &reason);
+                            return Command::Continue;
+                        }
+                        Err(YahtzeeError::Public(display)) => {
+                            reply_and_continue!(&display.replace("{ur}", &nick));
+                        }
+                    }
+                }
                 let saved = split_args
                     .iter()
                     .map(|arg| arg.parse::<u8>().ok())
@@ -1501,6 +2193,22 @@ impl IRCBotClient {
                 let nick = pd.name();
                 match yahtzee.play(&user, &saved) {
                     Ok(res) => {
+                        if let Some(rolls) = yahtzee.current_yahtzee(&user) {
+                            self.stats.record(&key, stats::YAHTZEE, 1, tm);
+                            let note = (rolls == 1).then(|| "first roll".to_string());
+                            self.hall_of_fame.record(
+                                hall_of_fame::Kind::Yahtzee,
+                                &key,
+                                &nick,
+                                note,
+                                tm,
+                            );
+                            self.achievement_events.push(achievements::YAHTZEE);
+                            if rolls == 1 {
+                                self.achievement_events
+                                    .push(achievements::YAHTZEE_FIRST_ROLL);
+                            }
+                        }
                         reply_and_continue!(&res.replace("{ur}", &nick));
                     }
                     Err(err) => match err {
@@ -1516,7 +2224,7 @@ impl IRCBotClient {
                 match split_args.get(0).map(|s| s.as_ref()) {
                     Some("stats") => {
                         let stats_user = match split_args.get(1) {
-                            Some(a) => self.player_data.player_or(&a.to_string(), &user),
+                            Some(a) => self.player_data.player_or(&a.to_string(), &key),
                             None => pd,
                         };
                         reply_and_continue!(&format!("d20 stats for {}: {} rolls, {} critical hits, {} fumbles, {:.2} average", stats_user.name(), stats_user.d20_rolled, stats_user.max_d20_rolled, stats_user.min_d20_rolled, stats_user.d20_acc as f64 / stats_user.d20_rolled as f64));
@@ -1528,17 +2236,27 @@ impl IRCBotClient {
                 }
 
                 let mut rng = thread_rng();
-                let roll = rng.gen_range(1..=20);
+                let mut roll = rng.gen_range(1..=20);
+                if items::d20_advantage(pd, tm) {
+                    roll = std::cmp::max(roll, rng.gen_range(1..=20));
+                }
                 pd.d20_rolled += 1;
                 pd.d20_acc += roll as u64;
+                self.stats.record(&key, stats::D20, roll as i64, tm);
 
                 let nick = pd.name();
+                let mut saved_by = None;
                 let response = match roll {
                     1 => {
                         pd.min_d20_rolled += 1;
-                        pd.deaths += 1;
-                        pd.death = Some(cur_time_or_0());
-                        random_response("D20_FUMBLE")
+                        saved_by = items::kill(pd, cur_time_or_0());
+                        match saved_by {
+                            Some(_) => db_random_response("ITEM_SAVED_DEATH", "deaths"),
+                            None => {
+                                self.stats.record(&key, stats::DEATH, 1, tm);
+                                random_response("D20_FUMBLE")
+                            }
+                        }
                     }
                     20 => {
                         pd.max_d20_rolled += 1;
@@ -1555,7 +2273,8 @@ impl IRCBotClient {
                 send_msg(
                     &response
                         .replace("{roll}", &roll.to_string())
-                        .replace("{ur}", &nick),
+                        .replace("{ur}", &nick)
+                        .replace("{item}", saved_by.as_deref().unwrap_or_default()),
                 )
                 .await;
             }
@@ -1570,26 +2289,236 @@ impl IRCBotClient {
                 return Command::Continue;
             }
             "feature:nick" => {
+                // !nick <nick | clear>
                 log_res("Setting nick");
-                if args.len() > 0 {
-                    pd.nick = Some(args);
+                if args.trim().is_empty() {
+                    reply_and_continue!(&format!(
+                        "You're {}. !nick <nick> to change it, or !nick clear.",
+                        pd.name()
+                    ));
                 }
-                send_msg(&random_response("NICK_SET").replace("{ur}", &pd.name())).await;
-                return Command::Continue;
+                let msg = match self.nicknames.set_nick(&mut self.player_data, &key, &args) {
+                    Ok(Outcome::Set) => random_response("NICK_SET")
+                        .replace("{ur}", &self.player_data.players[&key].name()),
+                    Ok(Outcome::Cleared) => format!("Okay, you're just {} again.", user),
+                    Ok(Outcome::Pending(word)) => {
+                        println!(
+                            "[Audit] {} asked for a nick with '{}' in it: {}",
+                            user, word, args
+                        );
+                        "A mod has to approve that nick first.".to_string()
+                    }
+                    Err(e) => e,
+                };
+                reply_and_continue!(&msg);
             }
             "feature:eval" => {
                 send_msg(&format!("{} -> {}", args.clone(), bad_eval(args.clone()))).await;
                 return Command::Continue;
             }
             "admin:nick" => {
+                // <user> <nick>, or the old <user>|<nick>
                 log_res("Setting nick (admin)");
-                let v: Vec<&str> = args.splitn(2, "|").collect();
+                let v: Vec<&str> = match args.contains('|') {
+                    true => args.splitn(2, '|').collect(),
+                    false => args.trim().splitn(2, ' ').collect(),
+                };
                 if v.len() != 2 {
                     send_msg(&"Not enough arguments.".to_string()).await;
                     return Command::Continue;
                 }
-                let pde = self.player_data.player(&v[0].to_string());
-                pde.nick = Some(v[1].to_string());
+                let name = v[0].trim().trim_start_matches('@');
+                let msg = match self.nicknames.force_nick(&mut self.player_data, name, v[1]) {
+                    Ok(msg) => {
+                        println!("[Audit] {} set a nick: {}", user, msg);
+                        msg
+                    }
+                    Err(e) => e,
+                };
+                reply_and_continue!(&msg);
+            }
+            "admin:nicks" => {
+                reply_and_continue!(&nicknames::pending(&self.player_data));
+            }
+            "admin:approvenick" | "admin:rejectnick" => {
+                let approve = command.as_str() == "admin:approvenick";
+                let msg = match self.nicknames.review(&mut self.player_data, &args, approve) {
+                    Ok(msg) => {
+                        println!("[Audit] {}: {}", user, msg);
+                        msg
+                    }
+                    Err(e) => e,
+                };
+                reply_and_continue!(&msg);
+            }
+            "admin:mergeplayers" => {
+                // !mergeplayers <from> <into> - folds <from> into <into>, then forgets <from>.
+                let names = split_args(trim_args_end(&args));
+                if names.len() != 2 {
+                    send_msg(&"Usage: <old player> <player to merge them into>".to_string()).await;
+                    return Command::Continue;
+                }
+                let from_login = self.player_data.find(names[0]).map(|p| p.username.clone());
+                match self.player_data.merge(names[0], names[1]) {
+                    Ok((from_key, into_key)) => {
+                        self.stats.merge(&from_key, &into_key);
+                        let into = &self.player_data.players[&into_key];
+                        let (into_login, into_name) = (into.username.clone(), into.name());
+                        if let (Some(yahtzee), Some(from_login)) =
+                            (self.yahtzee.as_mut(), from_login)
+                        {
+                            yahtzee.merge_players(&from_login, &into_login);
+                        }
+                        log_res(format!("Merged {} into {}", names[0], into_key).as_str());
+                        send_msg(&format!("Merged {} into {}.", names[0], into_name)).await;
+                    }
+                    Err(e) => {
+                        send_msg(&e).await;
+                    }
+                }
+                return Command::Continue;
+            }
+            "feature:give" => {
+                match economy::give(&mut self.player_data, &key, &args) {
+                    Ok(msg) => {
+                        log_res(msg.as_str());
+                        send_msg(&msg).await;
+                    }
+                    Err(e) => send_msg(&e).await,
+                }
+                return Command::Continue;
+            }
+            "feature:files" => {
+                // !files [history [user]]
+                let mut words = args.split_whitespace();
+                let msg = match words.next() {
+                    Some("history") => {
+                        let target = match words.next() {
+                            Some(name) => self.player_data.key_of(name.trim_start_matches('@')),
+                            None => Some(key.clone()),
+                        };
+                        match target {
+                            Some(target) => economy::history(&self.player_data, &target),
+                            None => "I don't know anyone by that name.".to_string(),
+                        }
+                    }
+                    _ => format!("{} has {} files.", pd.name(), pd.files),
+                };
+                send_msg(&msg).await;
+                return Command::Continue;
+            }
+            "admin:grant" | "admin:revoke" => {
+                let revoke = command == "admin:revoke";
+                match economy::admin_adjust(&mut self.player_data, &user, &args, revoke) {
+                    Ok(msg) => {
+                        log_res(msg.as_str());
+                        send_msg(&msg).await;
+                    }
+                    Err(e) => send_msg(&e).await,
+                }
+                return Command::Continue;
+            }
+            "feature:shop" => {
+                reply_and_continue!(&items::catalogue().shop(&args));
+            }
+            "feature:buy" => {
+                match items::catalogue().buy(&mut self.player_data, &key, &args) {
+                    Ok(msg) => {
+                        log_res(msg.as_str());
+                        send_msg(&msg).await;
+                    }
+                    Err(e) => send_msg(&e).await,
+                }
+                return Command::Continue;
+            }
+            "feature:inventory" => {
+                let target = match args.trim() {
+                    "" => Some(key.clone()),
+                    name => self.player_data.key_of(name.trim_start_matches('@')),
+                };
+                let msg = match target.and_then(|k| self.player_data.players.get(&k)) {
+                    Some(p) => items::catalogue().inventory(p, cur_time_or_0()),
+                    None => "I don't know anyone by that name.".to_string(),
+                };
+                reply_and_continue!(&msg);
+            }
+            "feature:use" => {
+                let name = pd.name();
+                match quests().use_item(pd, &args, tm) {
+                    Some(Ok(step)) => {
+                        let msg = self.quest_step(&key, &name, step);
+                        reply_and_continue!(&msg);
+                    }
+                    Some(Err(e)) => {
+                        reply_and_continue!(&e.replace("{ur}", &name));
+                    }
+                    None => {}
+                }
+                match items::catalogue().use_item(pd, &args, cur_time_or_0()) {
+                    Ok(msg) => {
+                        log_res(msg.as_str());
+                        send_msg(&msg).await;
+                    }
+                    Err(e) => send_msg(&e).await,
+                }
+                return Command::Continue;
+            }
+            "feature:answer" => {
+                // !answer <answer>
+                let name = pd.name();
+                let msg = match quests().answer(pd, &args, tm, &mut thread_rng()) {
+                    Ok(step) => self.quest_step(&key, &name, step),
+                    Err(e) => e,
+                };
+                reply_and_continue!(&msg);
+            }
+            "feature:quest" => {
+                // !quest [quit]
+                let msg = quests().status(pd, &args).replace("{ur}", &pd.name());
+                reply_and_continue!(&msg);
+            }
+            "feature:rep" => {
+                let target = match args.trim() {
+                    "" => Some(key.clone()),
+                    name => self.player_data.key_of(name.trim_start_matches('@')),
+                };
+                let msg = match target.and_then(|k| self.player_data.players.get(&k)) {
+                    Some(p) => reputation::describe(p),
+                    None => "I don't know anyone by that name.".to_string(),
+                };
+                reply_and_continue!(&msg);
+            }
+            "feature:achievements" => {
+                let target = match args.trim() {
+                    "" => Some(key.clone()),
+                    name => self.player_data.key_of(name.trim_start_matches('@')),
+                };
+                let msg = match target.and_then(|k| self.player_data.players.get(&k)) {
+                    Some(p) => achievements().describe(p),
+                    None => "I don't know anyone by that name.".to_string(),
+                };
+                reply_and_continue!(&msg);
+            }
+            "admin:filesevent" => {
+                // !filesevent [list | stop | <multiplier> [duration] [name]]
+                match self.economy.event_command(&user, &args, cur_time_or_0()) {
+                    Ok(msg) => {
+                        log_res(msg.as_str());
+                        send_msg(&msg).await;
+                    }
+                    Err(e) => send_msg(&e).await,
+                }
+                return Command::Continue;
+            }
+            "admin:setrep" => {
+                // !setrep <user> <amount | +amount | -amount>
+                match reputation::admin_command(&mut self.player_data, &user, &args) {
+                    Ok(msg) => {
+                        log_res(msg.as_str());
+                        send_msg(&msg).await;
+                    }
+                    Err(e) => send_msg(&e).await,
+                }
                 return Command::Continue;
             }
             "admin:toggle_translate" => {
@@ -1657,9 +2586,13 @@ impl IRCBotClient {
                     .send(TwitchFmt::privmsg(&message, &self.channel))
                     .await;
             }
-            #[cfg(feature = "audio")]
             "internal:cancel" => {
+                #[cfg(feature = "audio")]
                 self.audio.stop();
+                let msg = self.game.cancel(&mut self.player_data);
+                println!("[Audit] {} cancelled bets: {}", user, msg);
+                self.game.save();
+                send_msg(&msg).await;
             }
             _ => {
                 log_res("! Not yet equipped to handle this command.");
@@ -1681,38 +2614,81 @@ impl IRCBotClient {
         }
     }
 
+    // Runs a chat line's command, then everything that has to happen after any command.
+    async fn handle_line(
+        &mut self,
+        name: String,
+        user_id: Option<String>,
+        prefix: String,
+        command: String,
+    ) -> Command {
+        let login = name.clone();
+        let res = self.do_command(name, user_id, prefix, command).await;
+        self.check_achievements(&login).await;
+        self.settle_duels().await;
+        self.snapshot_stats();
+        res
+    }
+
+    // Runs whatever was due when the read loop woke up without a line.
+    async fn on_timer(&mut self) {
+        self.settle_duels().await;
+        if cur_time_or_0() >= self.last_save + SAVE_INTERVAL {
+            self.save_all("Autosaving");
+        }
+    }
+
     async fn launch_read(&mut self) -> ReadResult {
         lazy_static! {
             static ref COMMAND_RE: Regex =
                 Regex::new(r"^(bot |folder |[^\s\w]|)\s*(.*?)\s*$").unwrap();
             static ref PRIV_RE: Regex =
-                Regex::new(r":(\w*)!\w*@\w*\.tmi\.twitch\.tv PRIVMSG #\w* :\s*(.*)").unwrap();
+                Regex::new(r"(?:^@(\S*) )?:(\w*)!\w*@\w*\.tmi\.twitch\.tv PRIVMSG #\w* :\s*(.*)")
+                    .unwrap();
         }
-        let mut line = String::new();
-
         loop {
-            line.clear();
-            match self.reader.read_line(&mut line).await {
-                Ok(_) => {
-                    println!("[Received] Message: '{}'", line.trim());
-
-                    // maybe save our game data real quick...
-                    static LAST_SAVE: AtomicU64 = AtomicU64::new(0);
-
-                    let tm = cur_time_or_0();
-                    if (LAST_SAVE.load(Ordering::Relaxed) + 60 * 5) < tm {
-                        LAST_SAVE.store(tm, Ordering::Relaxed);
-                        println!("[Note] Autosaving player data.");
-                        self.player_data.save();
-                        if let Some(yahtzee) = &self.yahtzee {
-                            yahtzee.save()
-                        }
+            // Wake up for the next autosave, or the next duel to run out of time.
+            let now = cur_time_or_0();
+            let mut wait = (self.last_save + SAVE_INTERVAL).saturating_sub(now);
+            if let Some(deadline) = self.yahtzee.as_ref().and_then(|y| y.next_duel_deadline()) {
+                wait = wait.min(deadline.saturating_sub(now));
+            }
+            let event = select! {
+                line = self.lines.recv().fuse() => ReadEvent::Line(line),
+                () = task::sleep(Duration::from_secs(wait)).fuse() => ReadEvent::Timer,
+                () = shutdown::wait().fuse() => ReadEvent::Shutdown,
+            };
+            let line = match event {
+                ReadEvent::Line(Ok(line)) => line,
+                ReadEvent::Line(Err(_)) => {
+                    return ReadResult::Continue("Connection closed.".to_string())
+                }
+                ReadEvent::Timer => {
+                    let res = AssertUnwindSafe(self.on_timer()).catch_unwind().await;
+                    if res.is_err() {
+                        self.save_all("Emergency save after a panic");
+                        return ReadResult::Stop("Panicked on a timer.".to_string());
                     }
+                    continue;
+                }
+                ReadEvent::Shutdown => {
+                    self.save_all("Shutting down");
+                    return ReadResult::Stop("Received a signal.".to_string());
+                }
+            };
+            match line {
+                Ok(line) => {
+                    println!("[Received] Message: '{}'", line.trim());
 
                     // First, parse if it's a private message, or a skip/ping/etc.
-                    let (name, message) = match PRIV_RE.captures(line.as_str()) {
+                    let (user_id, name, message) = match PRIV_RE.captures(line.as_str()) {
                         // there must be a better way...
-                        Some(caps) => (caps.str_at(1), caps.str_at(2)),
+                        Some(caps) => (
+                            caps.get(1)
+                                .and_then(|tags| get_tag(tags.as_str(), "user-id")),
+                            caps.str_at(2),
+                            caps.str_at(3),
+                        ),
                         None => match self.handle_twitch(&line).await {
                             // todo - reconnect instead of stopping.
                             Command::Stop => {
@@ -1738,7 +2714,17 @@ impl IRCBotClient {
                     };
 
                     // Finally, we actually take the command and maybe take action.
-                    if let Command::Stop = self.do_command(name, prefix, command).await {
+                    let res = AssertUnwindSafe(self.handle_line(name, user_id, prefix, command))
+                        .catch_unwind()
+                        .await;
+                    let res = match res {
+                        Ok(res) => res,
+                        Err(_) => {
+                            self.save_all("Emergency save after a panic");
+                            return ReadResult::Stop("Panicked handling a command.".to_string());
+                        }
+                    };
+                    if let Command::Stop = res {
                         return ReadResult::Stop("Received stop command.".to_string());
                     }
                 }
@@ -1751,6 +2737,27 @@ impl IRCBotClient {
     }
 }
 
+/// Reads lines from Twitch on their own task, so the read loop can wait on other things too
+/// without ever cancelling a half-read line. The channel closes with the connection.
+fn spawn_reader(stream: TcpStream) -> Receiver<std::io::Result<String>> {
+    let (s, r) = async_std::channel::unbounded();
+    task::spawn(async move {
+        let mut reader = BufReader::new(stream);
+        loop {
+            let mut line = String::new();
+            let res = match reader.read_line(&mut line).await {
+                Ok(0) => break,
+                Ok(_) => Ok(line),
+                Err(e) => Err(e),
+            };
+            if s.send(res).await.is_err() {
+                break;
+            }
+        }
+    });
+    r
+}
+
 fn get_file_trimmed(filename: &str) -> String {
     match std::fs::read_to_string(filename) {
         Ok(s) => s.trim().to_string(),
@@ -1777,19 +2784,78 @@ async fn async_main() {
             IRCBotClient::connect(nick.clone(), secret.clone(), channel.clone(), ct).await;
         client.authenticate().await;
 
-        select! {
-            return_message = client.launch_read().fuse() => match return_message {
-                ReadResult::Continue(message) => { println!("Continuing (restarting) (Read): {}", message); },
-                ReadResult::Stop(message) => { println!("Stopping (Read): {}", message); break; },
-            },
-            () = forwarder.launch_write().fuse() => {}
+        let write = forwarder.launch_write().fuse();
+        pin_mut!(write);
+        let return_message = select! {
+            return_message = client.launch_read().fuse() => Some(return_message),
+            () = write => None,
+        };
+        // Dropping the client closes the send queue, so the writer stops once it's empty.
+        drop(client);
+        if !write.is_terminated()
+            && async_std::future::timeout(FLUSH_TIMEOUT, write)
+                .await
+                .is_err()
+        {
+            println!("[ERROR] Gave up sending the last queued messages.");
+        }
+        match return_message {
+            Some(ReadResult::Continue(message)) => {
+                println!("Continuing (restarting) (Read): {}", message);
+            }
+            Some(ReadResult::Stop(message)) => {
+                println!("Stopping (Read): {}", message);
+                break;
+            }
+            None => {}
+        }
+        if shutdown::requested() {
+            break;
         }
         task::sleep(Duration::from_millis(5000)).await;
     }
 }
 
+// Reports which data files would be upgraded on the next start, without touching them.
+fn check_data() {
+    println!("{}", SqlitePlayerStore::check(Path::new(DB_PATH)));
+    for (path, schema) in [
+        (economy::LEDGER_PATH, &economy::LEDGER_SCHEMA),
+        (HALL_OF_FAME_PATH, &HALL_OF_FAME_SCHEMA),
+    ] {
+        println!("{}", check_journal(Path::new(path), schema));
+    }
+    for (path, schema, is_map) in [
+        (LEGACY_JSON_PATH, &PLAYER_SCHEMA, true),
+        ("players.json", &LEGACY_PLAYER_SCHEMA, true),
+        ("yahtzee.json", &YAHTZEE_SCHEMA, false),
+        ("commands.json", &COMMANDS_SCHEMA, false),
+        (ECONOMY_PATH, &ECONOMY_SCHEMA, false),
+        (SEASONS_PATH, &SEASONS_SCHEMA, false),
+        (GAME_STATE_PATH, &GAME_STATE_SCHEMA, false),
+        (NICKNAMES_PATH, &NICKNAMES_SCHEMA, false),
+    ] {
+        println!("{}", check_json_file(Path::new(path), schema, is_map));
+    }
+}
+
 fn main() {
     //println!("{}", rare_trident(17, 0, &String::from("hi")));
     //println!("{}", rare_trident(17, 0, &String::from("hi")));
+    if std::env::args().any(|a| a == "--check-data") {
+        return check_data();
+    }
+    // --export-stats [user] [json | csv]
+    let args = std::env::args().collect::<Vec<_>>();
+    if let Some(i) = args.iter().position(|a| a == "--export-stats") {
+        let snapshots = Snapshots::open(Path::new(DB_PATH));
+        match snapshots.export(&args[i + 1..].join(" "), Path::new(EXPORT_DIR)) {
+            Ok(msg) => println!("{}", msg),
+            Err(e) => println!("[ERROR] {}", e),
+        }
+        return;
+    }
+    shutdown::install_signal_handlers();
+    shutdown::install_panic_hook();
     task::block_on(async_main())
 }
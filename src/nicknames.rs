@@ -0,0 +1,309 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::db::player::PlayerData;
+use crate::db::schema::{load_versioned, Schema};
+use crate::persist;
+
+/* Nickname rules (nicknames.json)
+ *
+ * Nicks show up everywhere a player's name does (greetings, leaderboards, the hall of fame),
+ * so !nick checks them first. Nicks that are too long, use odd characters, or clash with
+ * another player's login or nick are turned down. Nicks that contain a banned word aren't
+ * turned down outright, they wait for a mod to !approvenick or !rejectnick them.
+ *
+ * Like economy.json, the file is created with the defaults below and can be edited by hand.
+ */
+
+pub const NICKNAMES_PATH: &str = "nicknames.json";
+
+pub const NICKNAMES_SCHEMA: Schema = Schema {
+    name: "nickname rules",
+    current: 1,
+    migrations: &[],
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NicknameRules {
+    schema_version: u32,
+    pub min_len: usize,
+    pub max_len: usize,
+    // Allowed besides letters and digits.
+    pub extra_chars: String,
+    // Matched case-insensitively, ignoring anything that isn't a letter or digit.
+    pub banned_words: Vec<String>,
+
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl Default for NicknameRules {
+    fn default() -> Self {
+        NicknameRules {
+            schema_version: NICKNAMES_SCHEMA.current,
+            min_len: 2,
+            max_len: 25,
+            extra_chars: " _-.'!?~".to_string(),
+            banned_words: ["admin", "moderator", "folderbot", "streamer"]
+                .iter()
+                .map(|w| w.to_string())
+                .collect(),
+            path: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Set,
+    Cleared,
+    // Waiting for a mod, because of the given banned word.
+    Pending(String),
+}
+
+// Lowercase letters and digits only, so "F.o.l.d.e.r" still matches "folder".
+fn squash(s: &str) -> String {
+    s.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+impl NicknameRules {
+    /// Loads the rules from `path`, writing the defaults there if it doesn't exist yet.
+    pub fn load(path: &Path) -> NicknameRules {
+        match load_versioned::<NicknameRules>(path, &NICKNAMES_SCHEMA) {
+            Ok(Some(mut rules)) => {
+                rules.path = Some(path.to_path_buf());
+                rules
+            }
+            Ok(None) => {
+                let rules = NicknameRules {
+                    path: Some(path.to_path_buf()),
+                    ..Default::default()
+                };
+                rules.save();
+                rules
+            }
+            Err(e) => {
+                println!(
+                    "[ERROR] Couldn't load {}, using default nickname rules: {}",
+                    path.display(),
+                    e
+                );
+                NicknameRules::default()
+            }
+        }
+    }
+
+    pub fn save(&self) -> bool {
+        let path = match &self.path {
+            Some(p) => p,
+            None => return false,
+        };
+        match persist::save_json(path, self) {
+            Ok(_) => true,
+            Err(e) => {
+                println!("[ERROR] Couldn't save {}: {}", path.display(), e);
+                false
+            }
+        }
+    }
+
+    /// Checks a nick for player `key`, returning the cleaned up nick and the banned word it
+    /// contains (if any). Err is the reason it was turned down.
+    pub fn check(
+        &self,
+        nick: &str,
+        key: &str,
+        player_data: &PlayerData,
+    ) -> Result<(String, Option<String>), String> {
+        let nick = nick.split_whitespace().collect::<Vec<_>>().join(" ");
+        let len = nick.chars().count();
+        if len < self.min_len || len > self.max_len {
+            return Err(format!(
+                "Nicks have to be {} to {} characters long.",
+                self.min_len, self.max_len
+            ));
+        }
+        if let Some(c) = nick
+            .chars()
+            .find(|c| !c.is_alphanumeric() && !self.extra_chars.contains(*c))
+        {
+            return Err(format!("Nicks can't have '{}' in them.", c));
+        }
+        let squashed = squash(&nick);
+        // Nicks waiting for a mod count too, so two players can't both have the same one approved.
+        let taken = player_data.players.iter().any(|(k, p)| {
+            k != key
+                && [Some(&p.username), p.nick.as_ref(), p.pending_nick.as_ref()]
+                    .into_iter()
+                    .flatten()
+                    .any(|n| squash(n) == squashed)
+        });
+        if taken {
+            return Err("Someone else already goes by that name.".to_string());
+        }
+        let banned = self
+            .banned_words
+            .iter()
+            .find(|w| !w.is_empty() && squashed.contains(&squash(w)))
+            .cloned();
+        Ok((nick, banned))
+    }
+
+    /// !nick <nick | clear>
+    pub fn set_nick(
+        &self,
+        player_data: &mut PlayerData,
+        key: &String,
+        args: &str,
+    ) -> Result<Outcome, String> {
+        if args.trim().eq_ignore_ascii_case("clear") {
+            let p = player_data.player(key);
+            p.nick = None;
+            p.pending_nick = None;
+            return Ok(Outcome::Cleared);
+        }
+        let (nick, banned) = self.check(args, key, player_data)?;
+        let p = player_data.player(key);
+        match banned {
+            Some(word) => {
+                p.pending_nick = Some(nick);
+                Ok(Outcome::Pending(word))
+            }
+            None => {
+                p.nick = Some(nick);
+                p.pending_nick = None;
+                Ok(Outcome::Set)
+            }
+        }
+    }
+
+    /// Sets someone's nick for them (mods only). Skips the banned words, but nothing else.
+    pub fn force_nick(
+        &self,
+        player_data: &mut PlayerData,
+        name: &str,
+        nick: &str,
+    ) -> Result<String, String> {
+        let key = player_data
+            .key_of(name)
+            .ok_or_else(|| format!("I don't know anyone called {}.", name))?;
+        let (nick, _) = self.check(nick, &key, player_data)?;
+        let p = player_data.player(&key);
+        p.nick = Some(nick);
+        p.pending_nick = None;
+        Ok(format!("{} is now known as {}.", p.username, p.name()))
+    }
+
+    /// !approvenick <user> and !rejectnick <user>. The nick is checked again before it's
+    /// approved, in case someone else took it in the meantime.
+    pub fn review(
+        &self,
+        player_data: &mut PlayerData,
+        name: &str,
+        approve: bool,
+    ) -> Result<String, String> {
+        let key = player_data
+            .key_of(name.trim().trim_start_matches('@'))
+            .ok_or_else(|| format!("I don't know anyone called {}.", name))?;
+        let p = player_data.player(&key);
+        let nick = p
+            .pending_nick
+            .take()
+            .ok_or_else(|| format!("{} doesn't have a nick waiting.", p.username))?;
+        let username = p.username.clone();
+        if !approve {
+            return Ok(format!("{} can't go by {}.", username, nick));
+        }
+        let (nick, _) = self
+            .check(&nick, &key, player_data)
+            .map_err(|e| format!("{} can't go by {}: {}", username, nick, e))?;
+        let p = player_data.player(&key);
+        p.nick = Some(nick);
+        Ok(format!("{} is now known as {}.", p.username, p.name()))
+    }
+}
+
+/// !nicks - nicks waiting for a mod.
+pub fn pending(player_data: &PlayerData) -> String {
+    let mut waiting = player_data
+        .players
+        .values()
+        .filter_map(|p| {
+            p.pending_nick
+                .as_ref()
+                .map(|n| format!("{} -> {}", p.username, n))
+        })
+        .collect::<Vec<_>>();
+    if waiting.is_empty() {
+        return "No nicks are waiting for approval.".to_string();
+    }
+    waiting.sort();
+    format!("Waiting for approval: {}", waiting.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player_data() -> PlayerData {
+        let mut pd = PlayerData::in_memory();
+        pd.player(&"mjb".to_string());
+        pd.player(&"pac".to_string()).nick = Some("Pac Man".to_string());
+        pd
+    }
+
+    #[test]
+    fn checks_nicks() {
+        let (rules, pd) = (NicknameRules::default(), player_data());
+        let check = |nick| rules.check(nick, "mjb", &pd);
+        assert_eq!(
+            check("  the   folder  "),
+            Ok(("the folder".to_string(), None))
+        );
+        assert!(check("x").is_err());
+        assert!(check("abcdefghijklmnopqrstuvwxyz").is_err());
+        assert!(check("<script>").is_err());
+        assert!(check("PAC").is_err());
+        assert!(check("pac.man").is_err());
+        // Their own login is fine.
+        assert!(check("MJB").is_ok());
+        assert_eq!(
+            check("Ad.Min"),
+            Ok(("Ad.Min".to_string(), Some("admin".to_string())))
+        );
+    }
+
+    #[test]
+    fn approves_banned_nicks() {
+        let (rules, mut pd) = (NicknameRules::default(), player_data());
+        let mjb = "mjb".to_string();
+        assert_eq!(
+            rules.set_nick(&mut pd, &mjb, "the admin"),
+            Ok(Outcome::Pending("admin".to_string()))
+        );
+        assert_eq!(pd.players["mjb"].nick, None);
+        assert!(pending(&pd).contains("mjb -> the admin"));
+
+        assert!(rules.review(&mut pd, "@mjb", true).is_ok());
+        assert_eq!(pd.players["mjb"].name(), "the admin");
+        assert!(rules.review(&mut pd, "mjb", false).is_err());
+
+        // A pending nick can't be taken by anyone else, and is checked again on approval.
+        assert!(rules.set_nick(&mut pd, &mjb, "admin man").is_ok());
+        assert!(rules
+            .set_nick(&mut pd, &"zayd".to_string(), "Admin.Man")
+            .is_err());
+        pd.player(&"zayd".to_string()).nick = Some("adminman".to_string());
+        assert!(rules.review(&mut pd, "mjb", true).is_err());
+        assert_eq!(pd.players["mjb"].name(), "the admin");
+        assert!(pending(&pd).contains("No nicks"));
+
+        assert_eq!(rules.set_nick(&mut pd, &mjb, "clear"), Ok(Outcome::Cleared));
+        assert_eq!(pd.players["mjb"].name(), "mjb");
+        assert!(rules.force_nick(&mut pd, "mjb", "moderator").is_ok());
+        assert!(rules.force_nick(&mut pd, "mjb", "pac man").is_err());
+    }
+}
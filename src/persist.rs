@@ -0,0 +1,232 @@
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/* Crash-safe persistence for state files.
+ *
+ * Everything we save used to truncate the live file and then write into it, so a crash
+ * (or a full disk) halfway through left us with half a JSON file and no players.
+ * Instead, we now:
+ *   1. write the new contents to a temp file next to the target,
+ *   2. fsync the temp file,
+ *   3. rotate the existing file into numbered backups (target.bk1 is the newest),
+ *   4. rename the temp file over the target (atomic on the same filesystem).
+ *
+ * Backups replace the old bk.sh script. They are only rotated once per backup interval,
+ * otherwise the 5 minute autosave would push every useful backup out within the hour.
+ */
+
+pub const DEFAULT_BACKUPS: usize = 5;
+pub const DEFAULT_BACKUP_INTERVAL: Duration = Duration::from_secs(60 * 60 * 6);
+
+#[derive(Clone, Copy, Debug)]
+pub struct SaveOptions {
+    pub backups: usize,
+    pub backup_interval: Duration,
+}
+
+impl Default for SaveOptions {
+    fn default() -> Self {
+        SaveOptions {
+            backups: DEFAULT_BACKUPS,
+            backup_interval: DEFAULT_BACKUP_INTERVAL,
+        }
+    }
+}
+
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+pub fn backup_path(path: &Path, n: usize) -> PathBuf {
+    sibling_path(path, &format!(".bk{}", n))
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    sibling_path(path, &format!(".tmp{}", std::process::id()))
+}
+
+fn backup_is_due(path: &Path, interval: Duration) -> bool {
+    let newest = match fs::metadata(backup_path(path, 1)).and_then(|m| m.modified()) {
+        Ok(t) => t,
+        Err(_) => return true,
+    };
+    SystemTime::now()
+        .duration_since(newest)
+        .map(|age| age >= interval)
+        .unwrap_or(false)
+}
+
+fn rotate_backups(path: &Path, opts: &SaveOptions) -> io::Result<()> {
+    if opts.backups == 0 || !path.exists() || !backup_is_due(path, opts.backup_interval) {
+        return Ok(());
+    }
+    for n in (1..opts.backups).rev() {
+        let from = backup_path(path, n);
+        if from.exists() {
+            fs::rename(&from, backup_path(path, n + 1))?;
+        }
+    }
+    // Copy rather than rename, so the live file exists at every point in time.
+    fs::copy(path, backup_path(path, 1))?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Atomically replaces the contents of `path`, keeping rotated backups of the old contents.
+pub fn write_atomic(path: &Path, contents: &[u8], opts: &SaveOptions) -> io::Result<()> {
+    let tmp = temp_path(path);
+    let res = (|| {
+        let mut file = File::create(&tmp)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        drop(file);
+        rotate_backups(path, opts)?;
+        fs::rename(&tmp, path)?;
+        sync_parent_dir(path)
+    })();
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    res
+}
+
+pub fn save_json_with<T: Serialize + ?Sized>(
+    path: &Path,
+    value: &T,
+    opts: &SaveOptions,
+) -> io::Result<()> {
+    let contents = serde_json::to_vec_pretty(value)?;
+    write_atomic(path, &contents, opts)
+}
+
+/// Serializes `value` as pretty JSON and atomically writes it to `path` with default backups.
+pub fn save_json<T: Serialize + ?Sized>(path: &Path, value: &T) -> io::Result<()> {
+    save_json_with(path, value, &SaveOptions::default())
+}
+
+/// Rewrites an append-only JSON lines journal (ledger, hall of fame), keeping the header line
+/// and every entry that `keep` returns true for. `keep` may also edit entries. Returns how many
+/// entries were dropped.
+pub fn rewrite_journal<F>(path: &Path, mut keep: F) -> io::Result<usize>
+where
+    F: FnMut(&mut serde_json::Value) -> bool,
+{
+    let contents = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut lines = contents.lines();
+    let mut out = lines.next().map(|h| format!("{}\n", h)).unwrap_or_default();
+    let mut dropped = 0;
+    for line in lines.filter(|l| !l.trim().is_empty()) {
+        let mut value = serde_json::from_str(line)?;
+        if keep(&mut value) {
+            out += &format!("{}\n", value);
+        } else {
+            dropped += 1;
+        }
+    }
+    let opts = SaveOptions {
+        backups: 0,
+        ..Default::default()
+    };
+    write_atomic(path, out.as_bytes(), &opts)?;
+    Ok(dropped)
+}
+
+/// An empty directory for a test's files, removed again (with everything in it) when dropped.
+/// `name` has to be unique across tests, since they run in parallel.
+#[cfg(test)]
+pub struct TestDir(PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+    pub fn new(name: &str) -> TestDir {
+        let dir = std::env::temp_dir().join(format!("folderbot_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TestDir(dir)
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_new_file() {
+        let dir = TestDir::new("persist_new");
+        let path = dir.join("state.json");
+        save_json(&path, &vec![1, 2, 3]).unwrap();
+        let back: Vec<i32> = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(back, vec![1, 2, 3]);
+        assert!(!backup_path(&path, 1).exists());
+        assert!(!temp_path(&path).exists());
+    }
+
+    #[test]
+    fn rotates_backups() {
+        let dir = TestDir::new("persist_rotate");
+        let path = dir.join("state.json");
+        let opts = SaveOptions {
+            backups: 2,
+            backup_interval: Duration::from_secs(0),
+        };
+        for i in 0..4 {
+            save_json_with(&path, &i, &opts).unwrap();
+        }
+        let read = |p: PathBuf| -> i32 { serde_json::from_slice(&fs::read(p).unwrap()).unwrap() };
+        assert_eq!(read(path.clone()), 3);
+        assert_eq!(read(backup_path(&path, 1)), 2);
+        assert_eq!(read(backup_path(&path, 2)), 1);
+        assert!(!backup_path(&path, 3).exists());
+    }
+
+    #[test]
+    fn skips_backup_within_interval() {
+        let dir = TestDir::new("persist_interval");
+        let path = dir.join("state.json");
+        for i in 0..3 {
+            save_json(&path, &i).unwrap();
+        }
+        let read = |p: PathBuf| -> i32 { serde_json::from_slice(&fs::read(p).unwrap()).unwrap() };
+        assert_eq!(read(path.clone()), 2);
+        assert_eq!(read(backup_path(&path, 1)), 0);
+        assert!(!backup_path(&path, 2).exists());
+    }
+}
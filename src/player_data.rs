@@ -3,13 +3,19 @@ use std::io::Read;
 use std::collections::{HashMap};
 use std::path::Path;
 use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use crate::db::schema::{add_defaults, MapFile, Migration, Schema};
+use crate::persist;
 
-/* Player data storage
+/* Legacy betting player data storage (players.json)
+ *
+ * The betting game now uses db::player::PlayerData, and this file is only read once to import
+ * old balances (see game::import_legacy_players).
  *
  * All player data is stored in a hashmap of <Player Name, Player Data>.
  * This data is saved in a file, and should be intermittently saved to the file.
- * One question could be - why would there be Serde defaults? It's not like we'll
- * be manually editing the JSON file. Definitely something to think about.
+ * Old files may be missing cash/wins/losses; the v1 -> v2 migration fills them in
+ * instead of Serde defaults.
  *
  * Future:
  *   - It may be nice to hash usernames (with an near-zero collision algorithm)
@@ -18,17 +24,27 @@ use serde::{Serialize, Deserialize};
  *      In this case, player names would be loaded on the fly when matched
  */
 
-fn default_cash() -> i64 { 1000 }
-fn get_zero() -> i64 { 0 }
+pub fn default_cash() -> i64 { 1000 }
+
+pub const LEGACY_PLAYER_SCHEMA: Schema = Schema {
+    name: "betting player",
+    current: 2,
+    migrations: &[Migration {
+        from: 1,
+        description: "fill in cash, wins and losses",
+        apply: legacy_v1_to_v2,
+    }],
+};
+
+fn legacy_v1_to_v2(v: &mut Value) -> Result<(), String> {
+    add_defaults(v, &[("cash", json!(default_cash())), ("wins", json!(0)), ("losses", json!(0))])
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Player {
     pub name: String,
-    #[serde(default = "default_cash")]
     pub cash: i64,
-    #[serde(default = "get_zero")]
     pub wins: i64,
-    #[serde(default = "get_zero")]
     pub losses: i64,
 }
 
@@ -44,17 +60,8 @@ impl Player {
 }
 
 pub fn save_players(val: &HashMap<String, Player>, path: &Path) -> bool {
-    let file = match match path.exists() {
-        true => std::fs::OpenOptions::new().write(true).truncate(true).open(path),
-        false => File::create(path)
-    } {
-        Ok(file) => file,
-        Err(e) => {
-            println!("Could not open or create file! {}", e);
-            return false;
-        }
-    };
-    match serde_json::to_writer_pretty(file, val) {
+    let file = MapFile { schema_version: LEGACY_PLAYER_SCHEMA.current, players: val };
+    match persist::save_json(path, &file) {
         Ok(_) => true,
         Err(e) => {
             println!("Couldn't save players: {}", e);
@@ -68,26 +75,30 @@ pub fn get_players(path: &Path) -> HashMap<String, Player> {
     let mut f = File::open(path).expect("Could not open player file.");
     let mut contents = String::new();
     f.read_to_string(&mut contents).expect("Could not read player file to string.");
-    serde_json::from_str(&contents).unwrap()
+    let (records, _) = LEGACY_PLAYER_SCHEMA.upgrade_map(serde_json::from_str(&contents).unwrap())
+        .expect("Could not upgrade player file.");
+    serde_json::from_value(Value::Object(records)).unwrap()
 }
 
 #[cfg(test)]
 mod player_data_tests {
     use super::*;
+    use crate::persist::TestDir;
 
     #[test]
     fn test_new_file() {
         let hm = std::collections::HashMap::new();
-        let path = std::path::Path::new("test_player_data1.json");
-        assert!(save_players(&hm, path));
+        let dir = TestDir::new("player_data_new");
+        assert!(save_players(&hm, &dir.join("test_player_data1.json")));
     }
 
     #[test]
     fn test_load() {
         let mut hm = std::collections::HashMap::new();
-        let path = std::path::Path::new("test_player_data2.json");
+        let dir = TestDir::new("player_data_load");
+        let path = &dir.join("test_player_data2.json");
 
-        let player = super::new(String::from("mjb"));
+        let player = Player::new(String::from("mjb"));
         hm.insert(player.name.clone(), player);
 
         assert!(save_players(&hm, path)); 
@@ -95,4 +106,15 @@ mod player_data_tests {
         let res = get_players(path);
         assert!(res.contains_key("mjb"));
     }
+
+    #[test]
+    fn test_upgrade_v1() {
+        let v1 = json!({"mjb": {"name": "mjb", "wins": 3}});
+        let (records, from) = LEGACY_PLAYER_SCHEMA.upgrade_map(v1).unwrap();
+        assert_eq!(from, 1);
+        let players: HashMap<String, Player> = serde_json::from_value(Value::Object(records)).unwrap();
+        assert_eq!(players["mjb"].cash, default_cash());
+        assert_eq!(players["mjb"].wins, 3);
+        assert_eq!(players["mjb"].losses, 0);
+    }
 }
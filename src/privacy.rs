@@ -0,0 +1,119 @@
+use serde_json::Value;
+use std::path::Path;
+
+use crate::db::player::{Player, LEGACY_JSON_PATH};
+use crate::db::schema::MAP_KEY;
+use crate::persist;
+
+/* Privacy
+ *
+ * Chatters can !optout, after which the bot ignores them (no files, no greetings, no stats)
+ * and leaves them off every leaderboard until they !optin again. Their existing data is kept
+ * so opting back in picks up where they left off. !mydata summarises everything we store.
+ *
+ * !purge removes a player for good: their player record, Yahtzee stats, open wager, stat
+ * events and snapshots, hall of fame entries, ledger transactions, archived season standings,
+ * and their entries in the legacy player files. Rotated backups (*.bkN) are not touched and
+ * age out on their own.
+ */
+
+/// Player files from before the database, which may still name a purged player.
+pub const LEGACY_PLAYER_FILES: &[&str] =
+    &[LEGACY_JSON_PATH, "v2_players.json.migrated", "players.json"];
+
+/// Commands that opted out players can still use.
+pub fn allowed_when_opted_out(command: &str) -> bool {
+    matches!(command, "feature:optin" | "feature:mydata")
+}
+
+/// What else is stored about a player, besides their Player record.
+#[derive(Debug, Default)]
+pub struct Stored {
+    pub transactions: usize,
+    pub stat_events: i64,
+    pub snapshots: i64,
+    pub hall_of_fame: usize,
+    pub yahtzee: bool,
+}
+
+/// !mydata
+pub fn describe(p: &Player, stored: &Stored) -> String {
+    let yahtzee = match stored.yahtzee {
+        true => "Yahtzee stats",
+        false => "no Yahtzee stats",
+    };
+    format!(
+        "{} Also stored: {} reputation, {} achievements, {} inventory items, {} recent transactions, \
+         {} stat events, {} stat snapshot values, {} hall of fame entries and {}. {}",
+        p,
+        p.reputation,
+        p.achievements.len(),
+        p.inventory.values().sum::<u32>(),
+        stored.transactions,
+        stored.stat_events,
+        stored.snapshots,
+        stored.hall_of_fame,
+        yahtzee,
+        match p.opted_out {
+            true => "You're opted out; !optin to be tracked again.",
+            false => "!optout to stop being tracked, or ask a mod to delete it all.",
+        }
+    )
+}
+
+/// Removes the entries named by any of `names` from a player map file, old (unversioned) or
+/// new. Returns how many entries were removed.
+pub fn purge_map_file(path: &Path, names: &[&str]) -> Result<usize, String> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(_) => return Ok(0),
+    };
+    let mut value: Value = serde_json::from_str(&contents).map_err(|e| e.to_string())?;
+    let map = match value.get(MAP_KEY).is_some() {
+        true => value.get_mut(MAP_KEY),
+        false => Some(&mut value),
+    }
+    .and_then(Value::as_object_mut)
+    .ok_or("expected a JSON object")?;
+    let before = map.len();
+    map.retain(|k, _| !names.iter().any(|n| n.eq_ignore_ascii_case(k)));
+    let removed = before - map.len();
+    if removed > 0 {
+        let opts = persist::SaveOptions {
+            backups: 0,
+            ..Default::default()
+        };
+        persist::save_json_with(path, &value, &opts).map_err(|e| e.to_string())?;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persist::TestDir;
+    use serde_json::json;
+
+    #[test]
+    fn purges_map_files() {
+        let dir = TestDir::new("privacy");
+        let (old, new) = (dir.join("players.json"), dir.join("v2_players.json"));
+        std::fs::write(&old, json!({"mjb": {}, "pac": {}}).to_string()).unwrap();
+        std::fs::write(
+            &new,
+            json!({"schema_version": 5, "players": {"123": {}, "pac": {}}}).to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(purge_map_file(&old, &["123", "MJB"]), Ok(1));
+        assert_eq!(purge_map_file(&new, &["123", "mjb"]), Ok(1));
+        assert_eq!(purge_map_file(&dir.join("missing.json"), &["mjb"]), Ok(0));
+
+        let read = |p| serde_json::from_str::<Value>(&std::fs::read_to_string(p).unwrap()).unwrap();
+        assert_eq!(read(&old), json!({"pac": {}}));
+        assert_eq!(
+            read(&new),
+            json!({"schema_version": 5, "players": {"pac": {}}})
+        );
+    }
+}
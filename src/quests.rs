@@ -0,0 +1,371 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use lazy_static::lazy_static;
+
+use crate::db::player::Player;
+use crate::items::{self, MAX_STACK};
+
+/* The rolltrident adventure
+ *
+ * Every !rolltrident has a small chance (entry_chance) of pulling the player into a text
+ * adventure, starting at a random one of the start nodes. From there the story is a graph
+ * of nodes loaded from resources/quests.json: each node has some text, answers that lead to
+ * other nodes (!answer <answer>, possibly needing an item or a bit of luck), and items that
+ * can be used on it (!use <item>). Entering a node can hand out quest items and files, kill
+ * the player (the totem still works), and end the adventure. Endings that finish a questline
+ * (the dragon, freeing the bot, a real trident) are remembered on the player.
+ *
+ * Players only store which node they're on, so the story can be edited between streams. If a
+ * player's node disappears, their adventure just ends.
+ */
+
+pub const QUESTS_PATH: &str = "resources/quests.json";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QuestState {
+    pub node: String,
+    pub since: u64, // when they got there
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Choice {
+    pub answers: Vec<String>,
+    pub goto: String,
+    // An item the player needs to have for this answer (it isn't used up).
+    #[serde(default)]
+    pub requires: Option<String>,
+    // The chance of going to `goto`. Otherwise the player goes to `fail`.
+    #[serde(default)]
+    pub chance: Option<f64>,
+    #[serde(default)]
+    pub fail: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ItemUse {
+    pub item: String, // used up
+    pub goto: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Node {
+    pub text: String,
+    #[serde(default)]
+    pub choices: Vec<Choice>,
+    #[serde(default)]
+    pub uses: Vec<ItemUse>,
+    #[serde(default)]
+    pub gives: Vec<String>,
+    #[serde(default)]
+    pub files: i64,
+    #[serde(default)]
+    pub kills: bool,
+    // Ends the adventure, completing the questline if there is one.
+    #[serde(default)]
+    pub end: bool,
+    #[serde(default)]
+    pub completes: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Quests {
+    pub entry_chance: f64,
+    pub start: Vec<String>,
+    pub items: HashMap<String, String>, // quest item id -> name
+    pub nodes: HashMap<String, Node>,
+}
+
+/// Where the story went, for the caller to announce (with {ur} filled in) and pay out.
+#[derive(Debug, PartialEq)]
+pub struct Step {
+    pub text: String,
+    pub files: i64,
+}
+
+lazy_static! {
+    static ref QUESTS: Quests = Quests::from_file(Path::new(QUESTS_PATH));
+}
+
+pub fn quests() -> &'static Quests {
+    &QUESTS
+}
+
+fn normalize(s: &str) -> String {
+    s.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+impl Quests {
+    pub fn from_file(path: &Path) -> Quests {
+        let quests: Quests = File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|f| serde_json::from_reader(BufReader::new(f)).map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| {
+                println!(
+                    "[ERROR] Couldn't load quests from {}: {}",
+                    path.display(),
+                    e
+                );
+                Quests::default()
+            });
+        for problem in quests.problems() {
+            println!("[ERROR] In {}: {}", path.display(), problem);
+        }
+        quests
+    }
+
+    /// Links to nodes that don't exist, and chances without somewhere to fail to.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut check = |from: &str, to: &str| {
+            if !self.nodes.contains_key(to) {
+                problems.push(format!("{} leads to unknown node {}", from, to));
+            }
+        };
+        for id in &self.start {
+            check("start", id);
+        }
+        for (id, node) in &self.nodes {
+            for c in &node.choices {
+                check(id, &c.goto);
+                match (&c.chance, &c.fail) {
+                    (Some(_), Some(fail)) => check(id, fail),
+                    (Some(_), None) => check(id, "(a missing fail)"),
+                    _ => {}
+                }
+            }
+            for u in &node.uses {
+                check(id, &u.goto);
+            }
+        }
+        problems
+    }
+
+    /// A quest item's name, or its id for items we don't know about.
+    pub fn item_name(&self, id: &str) -> String {
+        self.items.get(id).cloned().unwrap_or(id.to_string())
+    }
+
+    // Quest items by id or name, then shop items.
+    fn item_id(&self, name: &str) -> Option<String> {
+        let name = normalize(name);
+        self.items
+            .iter()
+            .find(|(id, n)| **id == name || n.to_lowercase() == name)
+            .map(|(id, _)| id.clone())
+            .or_else(|| items::catalogue().find(&name).map(|i| i.id.clone()))
+    }
+
+    /// Moves the player to node `id`.
+    pub fn enter(&self, p: &mut Player, id: &str, now: u64) -> Step {
+        let node = match self.nodes.get(id) {
+            Some(n) => n,
+            None => {
+                p.quest = None;
+                return Step {
+                    text: "The story fades away... your adventure is over, {ur}.".to_string(),
+                    files: 0,
+                };
+            }
+        };
+        let mut text = node.text.clone();
+        for item in &node.gives {
+            let n = p.inventory.entry(item.clone()).or_insert(0);
+            *n = (*n + 1).min(MAX_STACK);
+        }
+        if node.kills {
+            if let Some(saved_by) = items::kill(p, now) {
+                text = format!(
+                    "{} ...or so it seems, but your {} saves you!",
+                    text, saved_by
+                );
+            }
+        }
+        if let Some(line) = &node.completes {
+            p.quests_completed.entry(line.clone()).or_insert(now);
+        }
+        p.quest = match node.end || node.kills || node.completes.is_some() {
+            true => None,
+            false => Some(QuestState {
+                node: id.to_string(),
+                since: now,
+            }),
+        };
+        Step {
+            text,
+            files: node.files,
+        }
+    }
+
+    /// Called on every trident roll. Rarely, starts an adventure.
+    pub fn discover(&self, p: &mut Player, now: u64, rng: &mut impl Rng) -> Option<Step> {
+        if p.quest.is_some() || self.start.is_empty() {
+            return None;
+        }
+        if !rng.gen_bool(self.entry_chance.clamp(0.0, 1.0)) {
+            return None;
+        }
+        let start = &self.start[rng.gen_range(0..self.start.len())];
+        Some(self.enter(p, start, now))
+    }
+
+    fn current(&self, p: &Player) -> Result<&Node, String> {
+        let state = p.quest.as_ref().ok_or_else(|| {
+            "You're not on an adventure... yet. Keep rolling those tridents.".to_string()
+        })?;
+        self.nodes
+            .get(&state.node)
+            .ok_or_else(|| "Your adventure has faded away.".to_string())
+    }
+
+    /// !answer <answer>
+    pub fn answer(
+        &self,
+        p: &mut Player,
+        args: &str,
+        now: u64,
+        rng: &mut impl Rng,
+    ) -> Result<Step, String> {
+        let node = self.current(p)?;
+        let answer = normalize(args);
+        let choice = node
+            .choices
+            .iter()
+            .find(|c| c.answers.iter().any(|a| normalize(a) == answer))
+            .ok_or_else(|| {
+                let answers = node
+                    .choices
+                    .iter()
+                    .filter_map(|c| c.answers.first().cloned())
+                    .collect::<Vec<_>>();
+                match answers.is_empty() {
+                    true => "That won't help here. Maybe !use something?".to_string(),
+                    false => format!("Hmm, try !answer {}.", answers.join(", !answer ")),
+                }
+            })?;
+        if let Some(item) = &choice.requires {
+            if p.inventory.get(item).copied().unwrap_or(0) == 0 {
+                return Err(format!("You'd need a {} for that.", self.item_name(item)));
+            }
+        }
+        let to = match (choice.chance, &choice.fail) {
+            (Some(chance), Some(fail)) if !rng.gen_bool(chance.clamp(0.0, 1.0)) => fail,
+            _ => &choice.goto,
+        };
+        Ok(self.enter(p, &to.clone(), now))
+    }
+
+    /// !use <item>, if the item does something where the player is in the story. None lets
+    /// the shop's !use handle it.
+    pub fn use_item(&self, p: &mut Player, args: &str, now: u64) -> Option<Result<Step, String>> {
+        let node = self.current(p).ok()?;
+        let id = self.item_id(args)?;
+        let to = node.uses.iter().find(|u| u.item == id)?.goto.clone();
+        if !items::take(p, &id) {
+            return Some(Err(format!(
+                "You don't have a {}, {{ur}}.",
+                self.item_name(&id)
+            )));
+        }
+        Some(Ok(self.enter(p, &to, now)))
+    }
+
+    /// !quest [quit]
+    pub fn status(&self, p: &mut Player, args: &str) -> String {
+        if args.trim().eq_ignore_ascii_case("quit") {
+            return match p.quest.take() {
+                Some(_) => "{ur} wanders off and forgets all about the adventure.".to_string(),
+                None => "You're not on an adventure, {ur}.".to_string(),
+            };
+        }
+        if let Ok(node) = self.current(p) {
+            return node.text.clone();
+        }
+        let lines = self
+            .nodes
+            .values()
+            .filter_map(|n| n.completes.as_deref())
+            .collect::<BTreeSet<_>>();
+        let done = lines
+            .iter()
+            .filter(|l| p.quests_completed.contains_key(**l))
+            .copied()
+            .collect::<Vec<_>>();
+        match done.is_empty() {
+            true => "{ur} isn't on an adventure. Who knows what the next trident brings?"
+                .to_string(),
+            false => format!(
+                "{{ur}} has finished {}/{} questlines ({}). Who knows what the next trident brings?",
+                done.len(),
+                lines.len(),
+                done.join(", ")
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::mock::StepRng;
+
+    #[test]
+    fn loads_quests() {
+        let q = Quests::from_file(Path::new(QUESTS_PATH));
+        assert!(q.problems().is_empty(), "{:?}", q.problems());
+        assert!(!q.start.is_empty());
+        assert!(q.entry_chance > 0.0 && q.entry_chance < 0.1);
+    }
+
+    #[test]
+    fn plays_through() {
+        let q = quests();
+        let mut p = Player::new("mjb".to_string());
+        // StepRng(0, 0) always picks the first start, and always wins chances.
+        let mut lucky = StepRng::new(0, 0);
+        assert!(q.answer(&mut p, "adventure", 0, &mut lucky).is_err());
+        p.quest = Some(QuestState {
+            node: "crossroads".to_string(),
+            since: 0,
+        });
+        assert!(q.answer(&mut p, "nowhere", 1, &mut lucky).is_err());
+        q.answer(&mut p, "Mountain", 1, &mut lucky).unwrap();
+        q.answer(&mut p, "gamble", 2, &mut lucky).unwrap();
+        assert_eq!(p.inventory.get("bed"), Some(&1));
+        q.answer(&mut p, "onward", 3, &mut lucky).unwrap();
+        assert!(q.use_item(&mut p, "totem", 4).is_none());
+        let step = q.use_item(&mut p, "bed", 4).unwrap().unwrap();
+        assert!(step.files > 0);
+        assert_eq!(p.quest, None);
+        assert!(p.inventory.get("bed").is_none());
+        assert!(p.quests_completed.contains_key("dragon"));
+        assert!(q.status(&mut p, "").contains("1/3"));
+    }
+
+    #[test]
+    fn deaths_end_the_adventure() {
+        let q = quests();
+        let mut p = Player::new("mjb".to_string());
+        p.quest = Some(QuestState {
+            node: "mountain".to_string(),
+            since: 0,
+        });
+        q.answer(&mut p, "fight", 5, &mut StepRng::new(0, 0))
+            .unwrap();
+        assert_eq!((p.quest.clone(), p.death, p.deaths), (None, Some(5), 1));
+        p.quest = Some(QuestState {
+            node: "gone".to_string(),
+            since: 0,
+        });
+        assert!(q
+            .answer(&mut p, "fight", 5, &mut StepRng::new(0, 0))
+            .is_err());
+    }
+}
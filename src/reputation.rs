@@ -0,0 +1,212 @@
+use crate::db::player::{Player, PlayerData};
+use crate::trident::{has_responses, random_response};
+
+/* Reputation
+ *
+ * How much folderbot likes someone. Chatting regularly (every time chat income is paid out)
+ * and tipping other players with !give raise it, dying to trident spam lowers it, and admins
+ * can adjust it by hand.
+ *
+ * The tier a player is in decides how often they get greeted, and which responses they get:
+ * for a response key like USER_GREET_GENERIC, a player in the Liked tier gets responses from
+ * USER_GREET_GENERIC_LIKED if that exists, and the regular ones otherwise.
+ */
+
+pub const CHAT_REP: i64 = 1;
+pub const SPAM_DEATH_REP: i64 = -25;
+pub const FILES_PER_TIP_REP: i64 = 100;
+pub const MAX_TIP_REP: i64 = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Tier {
+    Hated,
+    Disliked,
+    Neutral,
+    Liked,
+    Beloved,
+}
+
+impl Tier {
+    pub fn of(rep: i64) -> Tier {
+        match rep {
+            ..=-100 => Tier::Hated,
+            -99..=-1 => Tier::Disliked,
+            0..=99 => Tier::Neutral,
+            100..=499 => Tier::Liked,
+            _ => Tier::Beloved,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tier::Hated => "Hated",
+            Tier::Disliked => "Disliked",
+            Tier::Neutral => "Neutral",
+            Tier::Liked => "Liked",
+            Tier::Beloved => "Beloved",
+        }
+    }
+
+    /// Suffix for tiered response keys. Neutral players just get the regular responses.
+    pub fn suffix(&self) -> Option<&'static str> {
+        match self {
+            Tier::Hated => Some("HATED"),
+            Tier::Disliked => Some("DISLIKED"),
+            Tier::Neutral => None,
+            Tier::Liked => Some("LIKED"),
+            Tier::Beloved => Some("BELOVED"),
+        }
+    }
+
+    /// Scales the chance of folderbot greeting someone.
+    pub fn greet_factor(&self) -> f64 {
+        match self {
+            Tier::Hated => 0.25,
+            Tier::Disliked => 0.5,
+            Tier::Neutral => 1.0,
+            Tier::Liked => 1.5,
+            Tier::Beloved => 2.0,
+        }
+    }
+
+    fn attitude(&self) -> &'static str {
+        match self {
+            Tier::Hated => "Folderbot would rather not talk about them.",
+            Tier::Disliked => "Folderbot is keeping an eye on them.",
+            Tier::Neutral => "Folderbot doesn't have strong feelings about them.",
+            Tier::Liked => "Folderbot thinks they're alright.",
+            Tier::Beloved => "Folderbot would roll tridents for them.",
+        }
+    }
+}
+
+impl Player {
+    pub fn tier(&self) -> Tier {
+        Tier::of(self.reputation)
+    }
+}
+
+/// Reputation for giving `amount` files to someone else.
+pub fn tip_rep(amount: i64) -> i64 {
+    (amount / FILES_PER_TIP_REP).clamp(0, MAX_TIP_REP)
+}
+
+/// Gives a player reputation for a gift of `amount` files. Gifts earn at most MAX_TIP_REP a
+/// day, so passing the same files back and forth doesn't farm reputation.
+pub fn earn_tip_rep(p: &mut Player, amount: i64, now: u64) -> i64 {
+    let day = now / (60 * 60 * 24);
+    if p.tip_rep_day != day {
+        p.tip_rep_day = day;
+        p.tip_rep_today = 0;
+    }
+    let rep = tip_rep(amount).min(MAX_TIP_REP - p.tip_rep_today).max(0);
+    p.tip_rep_today += rep;
+    p.reputation += rep;
+    rep
+}
+
+/// Starting reputation for players from before reputation existed.
+pub fn initial_rep(sent_messages: u64, spam_deaths: u64) -> i64 {
+    // Roughly one chat income payout per 20 messages.
+    (sent_messages / 20) as i64 * CHAT_REP + spam_deaths as i64 * SPAM_DEATH_REP
+}
+
+pub fn tiered_key<F>(key: &str, tier: Tier, exists: F) -> String
+where
+    F: Fn(&str) -> bool,
+{
+    match tier.suffix() {
+        Some(suffix) => {
+            let tiered = format!("{}_{}", key, suffix);
+            match exists(&tiered) {
+                true => tiered,
+                false => key.to_string(),
+            }
+        }
+        None => key.to_string(),
+    }
+}
+
+/// A random response for `key`, from the tier-specific pool if there is one.
+pub fn tiered_response(key: &str, tier: Tier) -> &'static String {
+    random_response(&tiered_key(key, tier, has_responses))
+}
+
+pub fn describe(p: &Player) -> String {
+    let tier = p.tier();
+    format!(
+        "{} has {} reputation ({}). {}",
+        p.name(),
+        p.reputation,
+        tier.name(),
+        tier.attitude()
+    )
+}
+
+/// !setrep <user> <amount>. Signed amounts (+5, -5) adjust, plain ones set.
+pub fn admin_command(
+    player_data: &mut PlayerData,
+    admin: &str,
+    args: &str,
+) -> Result<String, String> {
+    let v: Vec<&str> = args.split_whitespace().collect();
+    if v.len() != 2 {
+        return Err("Usage: <user> <amount>, or <user> +/-<amount> to adjust.".to_string());
+    }
+    let amount = v[1]
+        .parse::<i64>()
+        .map_err(|_| format!("'{}' isn't a number.", v[1]))?;
+    let relative = v[1].starts_with('+') || v[1].starts_with('-');
+    let p = player_data
+        .apply(v[0].trim_start_matches('@'), |p| {
+            p.reputation = if relative {
+                p.reputation + amount
+            } else {
+                amount
+            };
+        })
+        .ok_or_else(|| format!("I don't know anyone called {}.", v[0]))?;
+    println!(
+        "[Audit] {} set {}'s reputation with {}, now {}",
+        admin, p.username, v[1], p.reputation
+    );
+    Ok(describe(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiers() {
+        assert_eq!(Tier::of(-100), Tier::Hated);
+        assert_eq!(Tier::of(-1), Tier::Disliked);
+        assert_eq!(Tier::of(0), Tier::Neutral);
+        assert_eq!(Tier::of(100), Tier::Liked);
+        assert_eq!(Tier::of(500), Tier::Beloved);
+        assert!(Tier::Hated.greet_factor() < Tier::Beloved.greet_factor());
+    }
+
+    #[test]
+    fn tiered_keys_fall_back() {
+        let exists = |k: &str| k == "GREET_LIKED";
+        assert_eq!(tiered_key("GREET", Tier::Liked, exists), "GREET_LIKED");
+        assert_eq!(tiered_key("GREET", Tier::Beloved, exists), "GREET");
+        assert_eq!(tiered_key("GREET", Tier::Neutral, exists), "GREET");
+    }
+
+    #[test]
+    fn reputation_changes() {
+        assert_eq!(tip_rep(5), 0);
+        assert_eq!(tip_rep(550), 5);
+        assert_eq!(tip_rep(1_000_000), MAX_TIP_REP);
+        let mut p = Player::new("mjb".to_string());
+        let day = 60 * 60 * 24;
+        assert_eq!(earn_tip_rep(&mut p, 1500, day), 15);
+        assert_eq!(earn_tip_rep(&mut p, 1500, day + 1), MAX_TIP_REP - 15);
+        assert_eq!(earn_tip_rep(&mut p, 1500, day + 2), 0);
+        assert_eq!(earn_tip_rep(&mut p, 1500, 2 * day), 15);
+        assert_eq!(p.reputation, MAX_TIP_REP + 15);
+        assert_eq!(initial_rep(2000, 1), 100 + SPAM_DEATH_REP);
+    }
+}
@@ -0,0 +1,402 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::db::player::Player;
+use crate::db::schema::{load_versioned, Schema};
+use crate::db::stats::{self, StatLog};
+use crate::persist;
+use crate::stat_registry::{registry, Sources, Stat};
+
+/* Seasons (seasons.json)
+ *
+ * Lifetime stats on the Player are never reset. Seasonal stats are the ones we keep stat events
+ * for (see db/stats.rs): a player's season value is worked out from their events since the stat
+ * was last reset, so starting a new season is just moving that start time.
+ *
+ * Ending a season archives the top of every leaderboard (seasonal stats for the season, the rest
+ * as they stand at the end of it) and resets the seasonal stats. An admin can choose to only
+ * reset some of them, the others carry on into the next season.
+ */
+
+pub const SEASONS_PATH: &str = "seasons.json";
+pub const ARCHIVE_LEN: usize = 10;
+
+pub const SEASONS_SCHEMA: Schema = Schema {
+    name: "seasons",
+    current: 1,
+    migrations: &[],
+};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Standing {
+    pub name: String,
+    pub value: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchivedSeason {
+    pub number: u32,
+    pub started: u64,
+    pub ended: u64,
+    pub leaderboards: BTreeMap<String, Vec<Standing>>, // stat name -> best first
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Seasons {
+    schema_version: u32,
+    pub number: u32,
+    pub started: u64,
+    // When each seasonal stat was last reset, if that wasn't at the start of this season.
+    pub stat_starts: BTreeMap<String, u64>,
+    pub archive: Vec<ArchivedSeason>,
+
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+pub fn is_seasonal(stat: &Stat) -> bool {
+    stats::windowed(stat.name).is_some()
+}
+
+fn seasonal_stats() -> impl Iterator<Item = &'static Stat> {
+    registry()
+        .names()
+        .into_iter()
+        .filter_map(|n| registry().find(n))
+        .filter(|s| is_seasonal(s))
+}
+
+fn days_since(then: u64, now: u64) -> u64 {
+    now.saturating_sub(then) / (60 * 60 * 24)
+}
+
+impl Seasons {
+    pub fn new(now: u64) -> Seasons {
+        Seasons {
+            schema_version: SEASONS_SCHEMA.current,
+            number: 1,
+            started: now,
+            stat_starts: BTreeMap::new(),
+            archive: Vec::new(),
+            path: None,
+        }
+    }
+
+    /// Loads seasons from `path`, starting season 1 there if it doesn't exist yet.
+    pub fn load(path: &Path, now: u64) -> Seasons {
+        match load_versioned::<Seasons>(path, &SEASONS_SCHEMA) {
+            Ok(Some(mut seasons)) => {
+                seasons.path = Some(path.to_path_buf());
+                seasons
+            }
+            Ok(None) => {
+                let seasons = Seasons {
+                    path: Some(path.to_path_buf()),
+                    ..Seasons::new(now)
+                };
+                seasons.save();
+                seasons
+            }
+            Err(e) => {
+                println!(
+                    "[ERROR] Couldn't load {}, seasons won't be saved: {}",
+                    path.display(),
+                    e
+                );
+                Seasons::new(now)
+            }
+        }
+    }
+
+    pub fn save(&self) -> bool {
+        let path = match &self.path {
+            Some(p) => p,
+            None => return false,
+        };
+        match persist::save_json(path, self) {
+            Ok(_) => true,
+            Err(e) => {
+                println!("[ERROR] Couldn't save {}: {}", path.display(), e);
+                false
+            }
+        }
+    }
+
+    /// When the current season of a seasonal stat started.
+    pub fn start_of(&self, stat: &str) -> u64 {
+        self.stat_starts.get(stat).copied().unwrap_or(self.started)
+    }
+
+    /// The current standings for `stat`: this season's for seasonal stats, lifetime otherwise.
+    pub fn standings(
+        &self,
+        stat: &Stat,
+        sources: &Sources,
+        players: &HashMap<String, Player>,
+        log: &StatLog,
+    ) -> Vec<Standing> {
+        if !is_seasonal(stat) {
+            return registry()
+                .top(stat, sources, players, ARCHIVE_LEN)
+                .into_iter()
+                .map(|(name, value)| Standing { name, value })
+                .collect();
+        }
+        let top = log
+            .top_since(stat.name, self.start_of(stat.name), ARCHIVE_LEN)
+            .unwrap_or_else(|e| {
+                println!("[ERROR] Couldn't rank season {}: {}", stat.name, e);
+                Vec::new()
+            });
+        top.into_iter()
+            .filter(|(key, _)| !matches!(players.get(key), Some(p) if p.opted_out))
+            .map(|(key, value)| Standing {
+                name: players.get(&key).map(|p| p.name()).unwrap_or(key),
+                value: value as f64,
+            })
+            .collect()
+    }
+
+    /// !endseason [stat...]. Archives every leaderboard, then resets the given seasonal stats
+    /// (all of them if none are given) and starts the next season.
+    pub fn end_season(
+        &mut self,
+        args: &str,
+        sources: &Sources,
+        players: &HashMap<String, Player>,
+        log: &StatLog,
+        now: u64,
+    ) -> Result<String, String> {
+        let mut reset = Vec::new();
+        for name in args.split_whitespace() {
+            match registry().find(name) {
+                Some(stat) if is_seasonal(stat) => reset.push(stat.name),
+                Some(stat) => return Err(format!("{} isn't a seasonal stat.", stat.display)),
+                None => return Err(format!("I don't know a stat called {}.", name)),
+            }
+        }
+        if reset.is_empty() {
+            reset = seasonal_stats().map(|s| s.name).collect();
+        }
+
+        let leaderboards = registry()
+            .names()
+            .into_iter()
+            .filter_map(|n| registry().find(n))
+            .map(|s| (s.name.to_string(), self.standings(s, sources, players, log)))
+            .filter(|(_, standings)| !standings.is_empty())
+            .collect();
+        self.archive.push(ArchivedSeason {
+            number: self.number,
+            started: self.started,
+            ended: now,
+            leaderboards,
+        });
+
+        // Stats that aren't reset keep counting from when they last were.
+        for stat in seasonal_stats() {
+            let start = self.start_of(stat.name);
+            self.stat_starts.insert(stat.name.to_string(), start);
+        }
+        for name in &reset {
+            self.stat_starts.insert(name.to_string(), now);
+        }
+        self.number += 1;
+        self.started = now;
+        self.stat_starts.retain(|_, start| *start != now);
+        self.save();
+        Ok(format!(
+            "Season {} is over, and season {} has begun! Reset: {}. See past winners with !season {} <stat>.",
+            self.number - 1,
+            self.number,
+            reset.join(", "),
+            self.number - 1
+        ))
+    }
+
+    fn find_archived(&self, number: u32) -> Option<&ArchivedSeason> {
+        self.archive.iter().rev().find(|s| s.number == number)
+    }
+
+    /// !season [<n>] [stat]
+    pub fn command(
+        &self,
+        args: &str,
+        sources: &Sources,
+        players: &HashMap<String, Player>,
+        log: &StatLog,
+        now: u64,
+    ) -> String {
+        let words = args.split_whitespace().collect::<Vec<_>>();
+        let list = |standings: &[Standing], stat: &Stat| {
+            standings
+                .iter()
+                .map(|s| format!("{}: {}", s.name, stat.format(s.value)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let number = match words.first().map(|w| w.parse::<u32>()) {
+            None => {
+                return format!(
+                    "Season {} started {} day(s) ago. Seasonal stats: {}. Try !season <stat> or !season <n> <stat>.",
+                    self.number,
+                    days_since(self.started, now),
+                    seasonal_stats().map(|s| s.name).collect::<Vec<_>>().join(", ")
+                )
+            }
+            Some(Ok(n)) => n,
+            Some(Err(_)) => {
+                // !season <stat>: the current season.
+                let stat = match registry().find(words[0]) {
+                    Some(s) if is_seasonal(s) => s,
+                    _ => return format!("{} isn't a seasonal stat.", words[0]),
+                };
+                let standings = self.standings(stat, sources, players, log);
+                if standings.is_empty() {
+                    return format!(
+                        "Nobody is on the {} leaderboard this season yet.",
+                        stat.display
+                    );
+                }
+                return format!(
+                    "Season {} {}: {}",
+                    self.number,
+                    stat.display,
+                    list(&standings, stat)
+                );
+            }
+        };
+        if number == self.number {
+            return format!(
+                "Season {} is still going! Try !season <stat> for the current standings.",
+                number
+            );
+        }
+        let season = match self.find_archived(number) {
+            Some(s) => s,
+            None => return format!("There's no season {} in the archive.", number),
+        };
+        match words.get(1) {
+            // !season <n>: the winner of every leaderboard.
+            None => {
+                let winners = season
+                    .leaderboards
+                    .iter()
+                    .filter_map(|(name, standings)| {
+                        Some((registry().find(name)?, standings.first()?))
+                    })
+                    .map(|(stat, w)| {
+                        format!("{}: {} ({})", stat.display, w.name, stat.format(w.value))
+                    })
+                    .collect::<Vec<_>>();
+                format!("Season {} winners: {}", number, winners.join(", "))
+            }
+            Some(name) => {
+                let stat = match registry().find(name) {
+                    Some(s) => s,
+                    None => return format!("I don't know a stat called {}.", name),
+                };
+                match season.leaderboards.get(stat.name) {
+                    Some(standings) => format!(
+                        "Season {} {}: {}",
+                        number,
+                        stat.display,
+                        list(standings, stat)
+                    ),
+                    None => format!(
+                        "Nobody was on the {} leaderboard in season {}.",
+                        stat.display, number
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Takes a player off every archived leaderboard. Returns how many standings were removed.
+    pub fn forget(&mut self, name: &str) -> usize {
+        let mut removed = 0;
+        for standings in self
+            .archive
+            .iter_mut()
+            .flat_map(|s| s.leaderboards.values_mut())
+        {
+            let before = standings.len();
+            standings.retain(|s| !s.name.eq_ignore_ascii_case(name));
+            removed += before - standings.len();
+        }
+        removed
+    }
+
+    /// This season's bests for one player, for !profile.
+    pub fn profile(&self, key: &str, log: &StatLog) -> String {
+        let bests = seasonal_stats()
+            .filter_map(|stat| {
+                let v = log
+                    .value_since(stat.name, key, self.start_of(stat.name))
+                    .ok()??;
+                Some(format!("{} {}", stat.display, stat.format(v as f64))).filter(|_| v > 0)
+            })
+            .collect::<Vec<_>>();
+        match bests.is_empty() {
+            true => format!("Nothing yet in season {}", self.number),
+            false => format!("Season {}: {}", self.number, bests.join(", ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn players() -> HashMap<String, Player> {
+        ["a", "b"]
+            .iter()
+            .map(|k| (k.to_string(), Player::new(k.to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn seasons_archive_and_reset() {
+        let (players, sources) = (players(), Sources::default());
+        let log = StatLog::in_memory(0);
+        let mut seasons = Seasons::new(0);
+        log.record("a", stats::TRIDENT, 200, 10);
+        log.record("b", stats::TRIDENT, 100, 10);
+        log.record("b", stats::D20, 20, 10);
+        assert!(seasons.profile("a", &log).contains("Trident PB 200"));
+
+        assert!(seasons
+            .end_season("files", &sources, &players, &log, 100)
+            .is_err());
+        seasons
+            .end_season("trident", &sources, &players, &log, 100)
+            .unwrap();
+        assert_eq!(seasons.number, 2);
+        let old = seasons.find_archived(1).unwrap();
+        assert_eq!(old.leaderboards["trident"][0].name, "a");
+        assert!(old.leaderboards.contains_key("files"));
+
+        // Tridents were reset, d20 crits carry on.
+        assert_eq!(seasons.start_of("trident"), 100);
+        assert_eq!(seasons.start_of("d20"), 0);
+        assert!(!seasons.profile("a", &log).contains("Trident PB"));
+        assert!(seasons.profile("b", &log).contains("d20 crits 1"));
+
+        let msg = seasons.command("1 trident", &sources, &players, &log, 100);
+        assert_eq!(msg, "Season 1 Trident PB: a: 200, b: 100");
+        assert!(seasons
+            .command("1", &sources, &players, &log, 100)
+            .contains("Trident PB: a (200)"));
+        assert!(seasons
+            .command("7", &sources, &players, &log, 100)
+            .contains("no season 7"));
+
+        // A full reset moves every seasonal stat onto the new season.
+        seasons
+            .end_season("", &sources, &players, &log, 200)
+            .unwrap();
+        assert!(seasons.stat_starts.is_empty());
+        assert_eq!(seasons.start_of("d20"), 200);
+    }
+}
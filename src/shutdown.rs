@@ -0,0 +1,78 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/* Shutting down cleanly
+ *
+ * Stores used to be saved from Drop, which doesn't run when the bot is killed. Now SIGINT and
+ * SIGTERM only set a flag (that's about all a signal handler may safely do), and the read loop
+ * notices it, saves every store, lets the send queue drain and exits. A second signal exits
+ * straight away, for when that gets stuck.
+ *
+ * The panic hook only logs when a panic happened; it can't get at the stores. Panics while
+ * handling a line or a timer are caught by the read loop, which does an emergency save before
+ * stopping (see launch_read in main.rs). Anywhere else, unsaved changes are lost.
+ */
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub fn request() {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Resolves once a shutdown has been requested.
+pub async fn wait() {
+    while !requested() {
+        async_std::task::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn on_signal(_: libc::c_int) {
+    if REQUESTED.swap(true, Ordering::SeqCst) {
+        // Already shutting down, and asked again.
+        unsafe { libc::_exit(130) };
+    }
+}
+
+#[cfg(unix)]
+pub fn install_signal_handlers() {
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        if unsafe { libc::signal(signal, handler) } == libc::SIG_ERR {
+            println!("[ERROR] Couldn't install a handler for signal {}.", signal);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install_signal_handlers() {}
+
+/// Logs panics with the time they happened, then hands over to the default hook.
+pub fn install_panic_hook() {
+    let default = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        println!("[PANIC] at {}: {}", crate::economy::now(), info);
+        default(info);
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_for_a_request() {
+        assert!(!requested());
+        let waiter = async_std::task::spawn(wait());
+        request();
+        async_std::task::block_on(async_std::future::timeout(Duration::from_secs(5), waiter))
+            .unwrap();
+        assert!(requested());
+    }
+}
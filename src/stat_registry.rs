@@ -0,0 +1,362 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use lazy_static::lazy_static;
+
+use crate::db::player::Player;
+use crate::yahtzee::Yahtzee;
+
+/* Stat registry
+ *
+ * Every stat that players can be ranked or profiled by, under a name (plus aliases) that chat
+ * can use. Modules contribute their own stats through a register_stats function, so stats
+ * that aren't kept on the Player (Yahtzee lives in yahtzee.json) work the same as the rest:
+ * they get what they need through Sources.
+ *
+ * !anylb, !rank, !tridentlb and !profile all read from here.
+ */
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    HighestFirst,
+    LowestFirst,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Count,
+    Decimal,
+}
+
+/// Whatever stats are computed from, besides the player themselves.
+#[derive(Clone, Copy, Default)]
+pub struct Sources<'a> {
+    pub yahtzee: Option<&'a Yahtzee>,
+}
+
+pub struct Stat {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub display: &'static str,
+    pub order: Order,
+    pub format: Format,
+    // Shown on !profile.
+    pub profile: bool,
+    // None if the player doesn't have this stat (e.g. they've never played Yahtzee).
+    pub value: fn(&Sources, &Player) -> Option<f64>,
+}
+
+impl Stat {
+    /// A whole-number stat where higher is better.
+    pub fn count(
+        name: &'static str,
+        aliases: &'static [&'static str],
+        display: &'static str,
+        value: fn(&Sources, &Player) -> Option<f64>,
+    ) -> Stat {
+        Stat {
+            name,
+            aliases,
+            display,
+            order: Order::HighestFirst,
+            format: Format::Count,
+            profile: false,
+            value,
+        }
+    }
+
+    /// A stat like an average, shown with two decimals.
+    pub fn decimal(
+        name: &'static str,
+        aliases: &'static [&'static str],
+        display: &'static str,
+        value: fn(&Sources, &Player) -> Option<f64>,
+    ) -> Stat {
+        Stat {
+            format: Format::Decimal,
+            ..Stat::count(name, aliases, display, value)
+        }
+    }
+
+    pub fn on_profile(mut self) -> Stat {
+        self.profile = true;
+        self
+    }
+
+    pub fn lowest_first(mut self) -> Stat {
+        self.order = Order::LowestFirst;
+        self
+    }
+
+    pub fn format(&self, v: f64) -> String {
+        match self.format {
+            Format::Count => format!("{}", v as i64),
+            Format::Decimal => format!("{:.2}", v),
+        }
+    }
+
+    /// Orders values best first.
+    fn cmp(&self, a: f64, b: f64) -> Ordering {
+        let ord = a.partial_cmp(&b).unwrap_or(Ordering::Equal);
+        match self.order {
+            Order::HighestFirst => ord.reverse(),
+            Order::LowestFirst => ord,
+        }
+    }
+}
+
+/// Where a player stands on a leaderboard.
+#[derive(Debug, PartialEq)]
+pub struct Rank {
+    pub position: usize, // 1 is first place. Tied players share a position.
+    pub value: f64,
+    pub tied: usize,                 // other players with the same value
+    pub next: Option<(String, f64)>, // the closest player ahead, and by how much
+    pub players: usize,
+}
+
+impl Rank {
+    pub fn describe(&self, name: &str, stat: &Stat) -> String {
+        let tied = match self.tied {
+            0 => String::new(),
+            1 => " (tied with 1 other)".to_string(),
+            n => format!(" (tied with {} others)", n),
+        };
+        let next = match &self.next {
+            Some((ahead, gap)) => format!("{} behind {}.", stat.format(*gap), ahead),
+            None => "That's first place!".to_string(),
+        };
+        format!(
+            "{} is #{} of {} on the {} leaderboard with {}{}. {}",
+            name,
+            self.position,
+            self.players,
+            stat.display,
+            stat.format(self.value),
+            tied,
+            next
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct StatRegistry {
+    stats: Vec<Stat>,
+}
+
+lazy_static! {
+    static ref REGISTRY: StatRegistry = {
+        let mut registry = StatRegistry::default();
+        crate::db::player::register_stats(&mut registry);
+        crate::yahtzee::register_stats(&mut registry);
+        crate::enchants::register_stats(&mut registry);
+        registry
+    };
+}
+
+pub fn registry() -> &'static StatRegistry {
+    &REGISTRY
+}
+
+impl StatRegistry {
+    pub fn register(&mut self, stat: Stat) {
+        let names = std::iter::once(&stat.name).chain(stat.aliases.iter());
+        for name in names {
+            if self.find(name).is_some() {
+                println!("[ERROR] Stat name {} is registered twice", name);
+            }
+        }
+        self.stats.push(stat);
+    }
+
+    /// Finds a stat by name or alias.
+    pub fn find(&self, name: &str) -> Option<&Stat> {
+        let name = name.trim().to_lowercase();
+        self.stats
+            .iter()
+            .find(|s| s.name == name || s.aliases.contains(&name.as_str()))
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.stats.iter().map(|s| s.name).collect()
+    }
+
+    pub fn stats(&self) -> impl Iterator<Item = &Stat> {
+        self.stats.iter()
+    }
+
+    fn values<'a>(
+        &self,
+        stat: &'a Stat,
+        sources: &'a Sources,
+        players: &'a HashMap<String, Player>,
+    ) -> impl Iterator<Item = (&'a String, &'a Player, f64)> {
+        players
+            .iter()
+            .filter(|(_, p)| !p.opted_out)
+            .filter_map(move |(k, p)| (stat.value)(sources, p).map(|v| (k, p, v)))
+    }
+
+    /// (name, value) for the best `n` players by `stat`, best first.
+    pub fn top(
+        &self,
+        stat: &Stat,
+        sources: &Sources,
+        players: &HashMap<String, Player>,
+        n: usize,
+    ) -> Vec<(String, f64)> {
+        self.values(stat, sources, players)
+            .map(|(_, p, v)| (p.name(), v))
+            .sorted_by(|a, b| stat.cmp(a.1, b.1).then_with(|| a.0.cmp(&b.0)))
+            .take(n)
+            .collect()
+    }
+
+    /// The best `n` players by `stat`, formatted for chat.
+    pub fn leaderboard(
+        &self,
+        stat: &Stat,
+        sources: &Sources,
+        players: &HashMap<String, Player>,
+        n: usize,
+    ) -> String {
+        self.top(stat, sources, players, n)
+            .iter()
+            .map(|(name, v)| format!("{}: {}", name, stat.format(*v)))
+            .join(", ")
+    }
+
+    /// Ranks the player `key` by `stat`, in a single pass over all players.
+    pub fn rank(
+        &self,
+        stat: &Stat,
+        sources: &Sources,
+        players: &HashMap<String, Player>,
+        key: &str,
+    ) -> Option<Rank> {
+        let p = players.get(key).filter(|p| !p.opted_out)?;
+        let value = (stat.value)(sources, p)?;
+        let mut rank = Rank {
+            position: 1,
+            value,
+            tied: 0,
+            next: None,
+            players: 0,
+        };
+        let mut next: Option<(&Player, f64)> = None;
+        for (k, p, v) in self.values(stat, sources, players) {
+            rank.players += 1;
+            match stat.cmp(v, value) {
+                Ordering::Less => {
+                    rank.position += 1;
+                    // The closest player ahead, by name among ties.
+                    let closer = match next {
+                        None => true,
+                        Some((q, n)) => match stat.cmp(v, n) {
+                            Ordering::Greater => true,
+                            Ordering::Equal => p.name() < q.name(),
+                            Ordering::Less => false,
+                        },
+                    };
+                    if closer {
+                        next = Some((p, v));
+                    }
+                }
+                Ordering::Equal if k != key => rank.tied += 1,
+                _ => {}
+            }
+        }
+        rank.next = next.map(|(p, v)| (p.name(), (v - value).abs()));
+        Some(rank)
+    }
+
+    /// !profile [user]
+    pub fn profile(&self, sources: &Sources, p: &Player) -> String {
+        let stats = self
+            .stats
+            .iter()
+            .filter(|s| s.profile)
+            .filter_map(|s| (s.value)(sources, p).map(|v| format!("{} {}", s.display, s.format(v))))
+            .join(", ");
+        format!("{}: {}", p.name(), stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn players() -> HashMap<String, Player> {
+        [("a", 250), ("b", 200), ("c", 200), ("d", 100)]
+            .iter()
+            .map(|(name, max)| {
+                let mut p = Player::new(name.to_string());
+                p.max_trident = *max;
+                (name.to_string(), p)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_by_alias() {
+        let r = registry();
+        assert_eq!(r.find("gp").unwrap().name, "gunpowder");
+        assert_eq!(r.find("Trident").unwrap().name, "trident");
+        assert!(r.find("yahtzee").is_some());
+        assert!(r.find("enchants").is_some());
+        assert!(r.find("nothing").is_none());
+    }
+
+    #[test]
+    fn ranks_players() {
+        let (r, src, players) = (registry(), Sources::default(), players());
+        let trident = r.find("trident").unwrap();
+        let rank = r.rank(trident, &src, &players, "c").unwrap();
+        assert_eq!((rank.position, rank.tied, rank.players), (2, 1, 4));
+        assert_eq!(rank.next, Some(("a".to_string(), 50.0)));
+        let rank = r.rank(trident, &src, &players, "d").unwrap();
+        assert_eq!(
+            (rank.position, rank.next),
+            (4, Some(("b".to_string(), 100.0)))
+        );
+        assert_eq!(r.rank(trident, &src, &players, "a").unwrap().next, None);
+        assert!(r.rank(trident, &src, &players, "nobody").is_none());
+        assert_eq!(
+            r.leaderboard(trident, &src, &players, 3),
+            "a: 250, b: 200, c: 200"
+        );
+        // No Yahtzee data, so nobody is on that leaderboard.
+        let yahtzee = r.find("yahtzee").unwrap();
+        assert!(r.rank(yahtzee, &src, &players, "a").is_none());
+    }
+
+    #[test]
+    fn skips_opted_out() {
+        let (r, src, mut players) = (registry(), Sources::default(), players());
+        players.get_mut("a").unwrap().opted_out = true;
+        let trident = r.find("trident").unwrap();
+        assert_eq!(r.rank(trident, &src, &players, "b").unwrap().position, 1);
+        assert!(r.rank(trident, &src, &players, "a").is_none());
+        assert_eq!(r.leaderboard(trident, &src, &players, 1), "b: 200");
+    }
+
+    #[test]
+    fn lowest_first() {
+        let mut r = StatRegistry::default();
+        r.register(
+            Stat::count("lowest", &[], "Lowest PB", |_, p| {
+                Some(p.max_trident as f64)
+            })
+            .lowest_first(),
+        );
+        let (src, players) = (Sources::default(), players());
+        let lowest = r.find("lowest").unwrap();
+        assert_eq!(r.leaderboard(lowest, &src, &players, 1), "d: 100");
+        let rank = r.rank(lowest, &src, &players, "b").unwrap();
+        assert_eq!(
+            (rank.position, rank.next),
+            (2, Some(("d".to_string(), 100.0)))
+        );
+    }
+}
@@ -10,6 +10,8 @@ use rand::{self, seq::SliceRandom};
 use lazy_static::lazy_static;
 use regex::Regex;
 
+use crate::economy::EconomyRules;
+
 // This should be more generic in the future, but it works for now.
 struct ResponseDB {
     responses: HashMap<String, Vec<String>>,
@@ -96,8 +98,8 @@ pub fn db_has_responses(key: &str, dbkey: &'static str) -> bool {
     get_db(dbkey).responses.contains_key(key)
 }
 
-pub fn file_greet_response(name: &str, files: i64) -> Option<String> {
-    let getter = |s: &'static str| {
+pub fn file_greet_response(name: &str, files: i64, rules: &EconomyRules) -> Option<String> {
+    let getter = |s: &str| {
         if has_responses(s) {
             Some(
                 random_response(s)
@@ -109,13 +111,5 @@ pub fn file_greet_response(name: &str, files: i64) -> Option<String> {
             Some("[Internal Error]".to_string())
         }
     };
-    match files {
-        ..=1000 => None,
-        2001..=5000 => getter("USER_GREET_1000"),
-        7001..=10000 => getter("USER_GREET_5000"),
-        11001..=25000 => getter("USER_GREET_10000"),
-        25001..=50000 => getter("USER_GREET_25000"),
-        50001..=10000000 => getter("USER_GREET_50000"),
-        _ => None,
-    }
+    rules.greet_tier(files).and_then(getter)
 }
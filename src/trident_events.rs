@@ -0,0 +1,398 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use itertools::Itertools;
+use lazy_static::lazy_static;
+
+use crate::db::player::PlayerData;
+use crate::economy::Reason;
+use crate::items::{self, MAX_STACK};
+
+/* Trident events
+ *
+ * The last ROLL_WINDOW trident rolls in the channel (whoever rolled them) are kept (and saved)
+ * in GameState. Once the window is full, every roll checks the events loaded from
+ * resources/trident_events.json, in order. The first event whose conditions all hold (and
+ * whose chance comes up) fires: its response key is sent, its effect is applied, and the
+ * window is cleared, so there's always a breather of ROLL_WINDOW rolls between events.
+ *
+ * Responses can use {ur} (the players the effect applies to), {t.avg}, {t.best}, {t.worst},
+ * {t.players} and {e.amount}.
+ */
+
+pub const EVENTS_PATH: &str = "resources/trident_events.json";
+pub const ROLL_WINDOW: usize = 10;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Roll {
+    pub key: String,
+    pub name: String,
+    pub value: i32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Condition {
+    // One player rolled at least this many of the rolls.
+    Hog {
+        at_least: usize,
+    },
+    // At least this many different players rolled.
+    Crowd {
+        at_least: usize,
+    },
+    // The last `length` rolls were all between `min` and `max`.
+    Streak {
+        length: usize,
+        #[serde(default)]
+        min: Option<i32>,
+        #[serde(default)]
+        max: Option<i32>,
+    },
+    Average {
+        #[serde(default)]
+        above: Option<f64>,
+        #[serde(default)]
+        below: Option<f64>,
+    },
+    AllBelow {
+        value: i32,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+    Everyone,
+    Hog,   // whoever rolled the most
+    Best,  // whoever rolled highest
+    Worst, // whoever rolled lowest
+    Last,  // whoever rolled last
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Effect {
+    Announce { to: Target },
+    Files { amount: i64, to: Target },
+    Kill { to: Target },
+    Item { id: String, to: Target },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Event {
+    pub id: String,
+    pub conditions: Vec<Condition>,
+    pub chance: f64,
+    pub response: String,
+    pub effect: Effect,
+}
+
+/// What an event did, for the caller to announce and record.
+#[derive(Debug, PartialEq)]
+pub struct Fired {
+    pub message: String,
+    pub deaths: Vec<String>, // keys of the players it killed
+}
+
+pub struct TridentEvents {
+    pub events: Vec<Event>,
+}
+
+lazy_static! {
+    static ref EVENTS: TridentEvents = TridentEvents::from_file(Path::new(EVENTS_PATH));
+}
+
+pub fn trident_events() -> &'static TridentEvents {
+    &EVENTS
+}
+
+fn average(rolls: &[Roll]) -> f64 {
+    rolls.iter().map(|r| r.value as f64).sum::<f64>() / rolls.len().max(1) as f64
+}
+
+// The player with the most rolls (the earliest of them on a tie), and how many they rolled.
+fn hog(rolls: &[Roll]) -> Option<(&Roll, usize)> {
+    let mut counts = HashMap::new();
+    for r in rolls {
+        *counts.entry(&r.key).or_insert(0) += 1;
+    }
+    rolls
+        .iter()
+        .map(|r| (r, counts[&r.key]))
+        .fold(None, |best: Option<(&Roll, usize)>, (r, n)| match best {
+            Some((_, m)) if m >= n => best,
+            _ => Some((r, n)),
+        })
+}
+
+impl Condition {
+    fn holds(&self, rolls: &[Roll]) -> bool {
+        match self {
+            Condition::Hog { at_least } => matches!(hog(rolls), Some((_, n)) if n >= *at_least),
+            Condition::Crowd { at_least } => {
+                rolls.iter().unique_by(|r| &r.key).count() >= *at_least
+            }
+            Condition::Streak { length, min, max } => {
+                rolls.len() >= *length
+                    && rolls[rolls.len() - length..].iter().all(|r| {
+                        r.value >= min.unwrap_or(i32::MIN) && r.value <= max.unwrap_or(i32::MAX)
+                    })
+            }
+            Condition::Average { above, below } => {
+                let avg = average(rolls);
+                avg > above.unwrap_or(f64::MIN) && avg < below.unwrap_or(f64::MAX)
+            }
+            Condition::AllBelow { value } => rolls.iter().all(|r| r.value < *value),
+        }
+    }
+}
+
+impl Target {
+    /// The (key, name) of everyone this targets.
+    fn players(self, rolls: &[Roll]) -> Vec<(String, String)> {
+        let picked = match self {
+            Target::Everyone => rolls.iter().unique_by(|r| &r.key).collect(),
+            Target::Hog => hog(rolls).map(|(r, _)| r).into_iter().collect(),
+            Target::Best => rolls
+                .iter()
+                .rev()
+                .max_by_key(|r| r.value)
+                .into_iter()
+                .collect(),
+            Target::Worst => rolls
+                .iter()
+                .rev()
+                .min_by_key(|r| r.value)
+                .into_iter()
+                .collect(),
+            Target::Last => rolls.last().into_iter().collect::<Vec<_>>(),
+        };
+        picked
+            .into_iter()
+            .map(|r| (r.key.clone(), r.name.clone()))
+            .collect()
+    }
+}
+
+impl Effect {
+    fn target(&self) -> Target {
+        match self {
+            Effect::Announce { to }
+            | Effect::Files { to, .. }
+            | Effect::Kill { to }
+            | Effect::Item { to, .. } => *to,
+        }
+    }
+}
+
+impl TridentEvents {
+    pub fn from_file(path: &Path) -> TridentEvents {
+        let events = File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|f| serde_json::from_reader(BufReader::new(f)).map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| {
+                println!(
+                    "[ERROR] Couldn't load trident events from {}: {}",
+                    path.display(),
+                    e
+                );
+                Vec::new()
+            });
+        TridentEvents { events }
+    }
+
+    /// The event the window of rolls sets off, if any. Only a full window sets off events.
+    pub fn check(&self, rolls: &[Roll], rng: &mut impl Rng) -> Option<&Event> {
+        if rolls.len() < ROLL_WINDOW {
+            return None;
+        }
+        self.events.iter().find(|e| {
+            e.conditions.iter().all(|c| c.holds(rolls)) && rng.gen_bool(e.chance.clamp(0.0, 1.0))
+        })
+    }
+}
+
+impl Event {
+    /// Applies the event's effect and fills in its response (a line picked from its response
+    /// key by the caller).
+    pub fn apply(
+        &self,
+        response: &str,
+        rolls: &[Roll],
+        player_data: &mut PlayerData,
+        now: u64,
+    ) -> Fired {
+        // Rolls can outlive their player (see !purge), so skip anyone who is gone.
+        let targets = self
+            .effect
+            .target()
+            .players(rolls)
+            .into_iter()
+            .filter(|(key, _)| player_data.players.contains_key(key))
+            .collect::<Vec<_>>();
+        let mut amount = 0;
+        let mut deaths = Vec::new();
+        for (key, name) in &targets {
+            match &self.effect {
+                Effect::Announce { .. } => {}
+                Effect::Files { amount: a, .. } => {
+                    // Events can take files, but never more than the player has.
+                    let a = (*a).max(-player_data.player(key).files.max(0));
+                    amount = amount.max(a.abs());
+                    player_data.adjust(key, a, Reason::Event, None, Some(self.id.clone()));
+                }
+                Effect::Kill { .. } => match items::kill(player_data.player(key), now) {
+                    Some(item) => {
+                        println!("[Event] {}'s {} saved them from {}.", name, item, self.id)
+                    }
+                    None => deaths.push(key.clone()),
+                },
+                Effect::Item { id, .. } => {
+                    let n = player_data
+                        .player(key)
+                        .inventory
+                        .entry(id.clone())
+                        .or_insert(0);
+                    *n = (*n + 1).min(MAX_STACK);
+                }
+            }
+        }
+        let names = targets.iter().map(|(_, name)| name.as_str()).join(", ");
+        let best = rolls.iter().map(|r| r.value).max().unwrap_or_default();
+        let worst = rolls.iter().map(|r| r.value).min().unwrap_or_default();
+        let players = rolls.iter().unique_by(|r| &r.key).count();
+        let message = response
+            .replace("{ur}", &names)
+            .replace("{t.avg}", &format!("{:.0}", average(rolls)))
+            .replace("{t.best}", &best.to_string())
+            .replace("{t.worst}", &worst.to_string())
+            .replace("{t.players}", &players.to_string())
+            .replace("{e.amount}", &amount.to_string());
+        Fired { message, deaths }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trident::has_responses;
+    use rand::rngs::mock::StepRng;
+
+    fn rolls(rolls: &[(&str, i32)]) -> Vec<Roll> {
+        rolls
+            .iter()
+            .map(|(key, value)| Roll {
+                key: key.to_string(),
+                name: key.to_uppercase(),
+                value: *value,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn loads_events() {
+        let e = TridentEvents::from_file(Path::new(EVENTS_PATH));
+        assert!(!e.events.is_empty());
+        for event in &e.events {
+            assert!(has_responses(&event.response), "{}", event.response);
+            if let Effect::Item { id, .. } = &event.effect {
+                assert!(items::catalogue().find(id).is_some(), "{}", id);
+            }
+        }
+    }
+
+    #[test]
+    fn checks_conditions() {
+        let window = rolls(&[
+            ("a", 10),
+            ("b", 20),
+            ("a", 30),
+            ("a", 160),
+            ("c", 170),
+            ("a", 180),
+        ]);
+        assert!(Condition::Hog { at_least: 4 }.holds(&window));
+        assert!(!Condition::Hog { at_least: 5 }.holds(&window));
+        assert!(Condition::Crowd { at_least: 3 }.holds(&window));
+        let hot = |length| Condition::Streak {
+            length,
+            min: Some(150),
+            max: None,
+        };
+        assert!(hot(3).holds(&window));
+        assert!(!hot(4).holds(&window));
+        let avg = Condition::Average {
+            above: Some(90.0),
+            below: Some(100.0),
+        };
+        assert!(avg.holds(&window));
+        assert!(!Condition::AllBelow { value: 180 }.holds(&window));
+
+        assert_eq!(Target::Best.players(&window)[0].0, "a");
+        assert_eq!(Target::Worst.players(&window)[0].0, "a");
+        assert_eq!(Target::Everyone.players(&window).len(), 3);
+        assert_eq!(Target::Last.players(&window)[0].1, "A");
+    }
+
+    #[test]
+    fn fires_on_a_full_window() {
+        let events = TridentEvents {
+            events: vec![Event {
+                id: "pity".to_string(),
+                conditions: vec![Condition::AllBelow { value: 50 }],
+                chance: 1.0,
+                response: "UNUSED".to_string(),
+                effect: Effect::Files {
+                    amount: 25,
+                    to: Target::Everyone,
+                },
+            }],
+        };
+        let mut window = rolls(&[("a", 1), ("b", 2), ("c", 3), ("a", 4), ("b", 5)]);
+        window.extend(window.clone());
+        let mut rng = StepRng::new(0, 0);
+        assert!(events.check(&window[1..], &mut rng).is_none());
+        let event = events.check(&window, &mut rng).unwrap();
+
+        let mut pd = PlayerData::in_memory();
+        for key in ["a", "b", "c"] {
+            pd.player(&key.to_string());
+        }
+        let fired = event.apply("{ur} get {e.amount} (best {t.best})", &window, &mut pd, 0);
+        assert_eq!(fired.message, "A, B, C get 25 (best 5)");
+        assert_eq!(pd.players["c"].files, 1025);
+
+        let kill = Event {
+            effect: Effect::Kill { to: Target::Worst },
+            ..event.clone()
+        };
+        let fired = kill.apply("{ur} froze at {t.worst}", &window, &mut pd, 7);
+        assert_eq!(fired.message, "A froze at 1");
+        assert_eq!(fired.deaths, vec!["a".to_string()]);
+        assert_eq!(pd.players["a"].death, Some(7));
+
+        // Nobody loses more files than they have.
+        let fine = Event {
+            effect: Effect::Files {
+                amount: -50,
+                to: Target::Hog,
+            },
+            ..event.clone()
+        };
+        pd.player(&"a".to_string()).files = 20;
+        let fired = fine.apply("{ur} paid {e.amount}", &window, &mut pd, 8);
+        assert_eq!(fired.message, "A paid 20");
+        assert_eq!(pd.players["a"].files, 0);
+
+        // Purged players aren't brought back.
+        pd.remove("a");
+        let fired = event.apply("{ur} get {e.amount}", &window, &mut pd, 9);
+        assert_eq!(fired.message, "B, C get 25");
+        assert!(!pd.players.contains_key("a"));
+    }
+}
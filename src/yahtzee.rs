@@ -10,40 +10,377 @@ use rand::rngs::ThreadRng;
 use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 
+use crate::db::player::{Player, PlayerData};
+use crate::db::schema::{add_defaults, Migration, Schema};
+use crate::economy::Reason;
+use crate::persist;
+use crate::stat_registry::{Sources, Stat, StatRegistry};
+use serde_json::{json, Value};
+
 pub const DICE_COUNT: usize = 5;
 
+pub const YAHTZEE_SCHEMA: Schema = Schema {
+    name: "yahtzee",
+    current: 4,
+    migrations: &[
+        Migration {
+            from: 1,
+            description: "fill in player stats that used to rely on serde defaults",
+            apply: yahtzee_v1_to_v2,
+        },
+        Migration {
+            from: 2,
+            description: "add scorecards and full game stats",
+            apply: yahtzee_v2_to_v3,
+        },
+        Migration {
+            from: 3,
+            description: "add duels and duel records",
+            apply: yahtzee_v3_to_v4,
+        },
+    ],
+};
+
+fn yahtzee_v1_to_v2(v: &mut Value) -> Result<(), String> {
+    let players = v
+        .get_mut("players")
+        .and_then(Value::as_object_mut)
+        .ok_or("expected a \"players\" object")?;
+    for player in players.values_mut() {
+        add_defaults(
+            player,
+            &[
+                ("turns", json!(0)),
+                ("rolls", json!(0)),
+                ("total_score", json!(0)),
+                ("total_yahtzees", json!(0)),
+                ("best_yahtzee_die", json!(0)),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn yahtzee_v2_to_v3(v: &mut Value) -> Result<(), String> {
+    let players = v
+        .get_mut("players")
+        .and_then(Value::as_object_mut)
+        .ok_or("expected a \"players\" object")?;
+    for player in players.values_mut() {
+        add_defaults(
+            player,
+            &[
+                ("card", Value::Null),
+                ("games", json!(0)),
+                ("total_game_score", json!(0)),
+                ("best_game", json!(0)),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn yahtzee_v3_to_v4(v: &mut Value) -> Result<(), String> {
+    add_defaults(v, &[("duels", json!([]))])?;
+    let players = v
+        .get_mut("players")
+        .and_then(Value::as_object_mut)
+        .ok_or("expected a \"players\" object")?;
+    for player in players.values_mut() {
+        add_defaults(
+            player,
+            &[("duel_wins", json!(0)), ("duel_losses", json!(0))],
+        )?;
+    }
+    Ok(())
+}
+
 #[derive(Clone, Copy, Serialize, Deserialize, Default)]
 struct GameTurn {
     dice: [u8; DICE_COUNT],
     rolls: u8,
     score: u8,
-    #[serde(default, skip_serializing)]
+    // Runtime only, turns are ended on load anyway.
+    #[serde(skip)]
     last_rolled_at: u64,
 }
 
 #[derive(Serialize, Deserialize, Default)]
 struct GamePlayer {
-    #[serde(default)]
     turns: u64,
-    #[serde(default)]
     rolls: u64,
 
-    #[serde(default)]
     total_score: u64,
 
-    #[serde(default)]
     total_yahtzees: u64,
-    #[serde(default)]
     best_yahtzee_die: u8,
 
     best_turn: Option<GameTurn>,
     current_turn: Option<GameTurn>,
+
+    // Full game mode: the scorecard being filled in, if any, and finished games.
+    card: Option<Scorecard>,
+    games: u64,
+    total_game_score: u64,
+    best_game: u64,
+
+    duel_wins: u64,
+    duel_losses: u64,
+}
+
+/* Duels
+ *
+ * `!yahtzee duel <user> [files]` challenges someone to a single turn each. Once they
+ * `!yahtzee accept`, both wagers are taken out of their files and each of them rolls (and
+ * re-rolls) as usual, keeping their dice with `!yahtzee stand` or by using all three rolls.
+ * The higher score takes both wagers and a tie gives them back.
+ *
+ * Challenges that aren't answered in time just lapse. Once a duel is on, whoever hasn't
+ * finished their turn by the deadline forfeits (if neither has, the wagers are refunded).
+ * Duels are saved with the rest of the game, so the files held for them survive a restart.
+ */
+pub const DUEL_ANSWER_TIME: u64 = 2 * 60;
+pub const DUEL_TIME_LIMIT: u64 = 5 * 60;
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Duelist {
+    login: String,
+    key: String,
+    score: Option<u8>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Duel {
+    // The challenger comes first.
+    players: [Duelist; 2],
+    wager: i64,
+    accepted: bool,
+    // Unix seconds, to answer the challenge and then to finish both turns.
+    deadline: u64,
+}
+
+impl Duel {
+    fn side(&self, login: &str) -> Option<usize> {
+        self.players.iter().position(|p| p.login == login)
+    }
+}
+
+/* Full game mode
+ *
+ * The quick mode above only scores the best pattern of each turn. `!yahtzee start` instead
+ * gives the player a scorecard with the usual 13 boxes, and every turn (rolled and re-rolled
+ * the same way) has to be written into one of them with `!yahtzee score <box>`. The card is
+ * saved with everything else, so a game can go on across streams.
+ *
+ * Bonuses follow the usual rules: 35 for 63 or more in the upper section, and 100 for every
+ * extra Yahtzee once the Yahtzee box has 50 in it. Extra Yahtzees are also jokers, worth full
+ * points in the full house and straight boxes (without having to fill the upper box first).
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Category {
+    Ones,
+    Twos,
+    Threes,
+    Fours,
+    Fives,
+    Sixes,
+    ThreeOfAKind,
+    FourOfAKind,
+    FullHouse,
+    SmallStraight,
+    LargeStraight,
+    Yahtzee,
+    Chance,
+}
+
+impl Category {
+    pub const ALL: [Category; 13] = [
+        Category::Ones,
+        Category::Twos,
+        Category::Threes,
+        Category::Fours,
+        Category::Fives,
+        Category::Sixes,
+        Category::ThreeOfAKind,
+        Category::FourOfAKind,
+        Category::FullHouse,
+        Category::SmallStraight,
+        Category::LargeStraight,
+        Category::Yahtzee,
+        Category::Chance,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Ones => "ones",
+            Category::Twos => "twos",
+            Category::Threes => "threes",
+            Category::Fours => "fours",
+            Category::Fives => "fives",
+            Category::Sixes => "sixes",
+            Category::ThreeOfAKind => "3kind",
+            Category::FourOfAKind => "4kind",
+            Category::FullHouse => "fullhouse",
+            Category::SmallStraight => "small",
+            Category::LargeStraight => "large",
+            Category::Yahtzee => "yahtzee",
+            Category::Chance => "chance",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Category> {
+        let s = s
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect::<String>();
+        let category = match s.as_str() {
+            "ones" | "aces" | "1s" => Category::Ones,
+            "twos" | "2s" => Category::Twos,
+            "threes" | "3s" => Category::Threes,
+            "fours" | "4s" => Category::Fours,
+            "fives" | "5s" => Category::Fives,
+            "sixes" | "6s" => Category::Sixes,
+            "3kind" | "3ofakind" | "threeofakind" => Category::ThreeOfAKind,
+            "4kind" | "4ofakind" | "fourofakind" => Category::FourOfAKind,
+            "fullhouse" | "house" | "fh" => Category::FullHouse,
+            "small" | "smallstraight" | "ss" => Category::SmallStraight,
+            "large" | "largestraight" | "ls" => Category::LargeStraight,
+            "yahtzee" => Category::Yahtzee,
+            "chance" => Category::Chance,
+            _ => return None,
+        };
+        Some(category)
+    }
+
+    fn is_upper(self) -> bool {
+        (self as usize) < 6
+    }
+
+    /// What `dice` are worth in this box. A joker (an extra Yahtzee) fills the full house
+    /// and straights for full points.
+    pub fn score(self, dice: &[u8; DICE_COUNT], joker: bool) -> u8 {
+        let mut faces = [0u8; 6];
+        for die in dice {
+            if let Some(face) = faces.get_mut(*die as usize - 1) {
+                *face += 1;
+            }
+        }
+        let sum = dice.iter().sum::<u8>();
+        let most = faces.iter().copied().max().unwrap_or_default();
+        // Faces present, as bits, to look for straights.
+        let present = faces
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| **n > 0)
+            .fold(0u8, |bits, (i, _)| bits | 1 << i);
+        let has_run =
+            |run: u8| (0..=6 - run).any(|i| (present >> i) & ((1 << run) - 1) == (1 << run) - 1);
+        match self {
+            Category::ThreeOfAKind if most >= 3 => sum,
+            Category::FourOfAKind if most >= 4 => sum,
+            Category::FullHouse if joker || (faces.contains(&3) && faces.contains(&2)) => 25,
+            Category::SmallStraight if joker || has_run(4) => 30,
+            Category::LargeStraight if joker || has_run(5) => 40,
+            Category::Yahtzee if most == 5 => GameTurn::YAHTZEE_SCORE,
+            Category::Chance => sum,
+            c if c.is_upper() => faces[c as usize] * (c as u8 + 1),
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Default)]
+struct Scorecard {
+    // Indexed by Category, None while the box is still open.
+    boxes: [Option<u8>; 13],
+    yahtzee_bonuses: u64,
+}
+
+impl Scorecard {
+    const UPPER_BONUS_AT: u64 = 63;
+    const UPPER_BONUS: u64 = 35;
+    const YAHTZEE_BONUS: u64 = 100;
+
+    fn get(&self, c: Category) -> Option<u8> {
+        self.boxes[c as usize]
+    }
+
+    fn upper(&self) -> u64 {
+        Category::ALL
+            .iter()
+            .filter(|c| c.is_upper())
+            .filter_map(|c| self.get(*c))
+            .map(u64::from)
+            .sum()
+    }
+
+    fn upper_bonus(&self) -> u64 {
+        match self.upper() >= Self::UPPER_BONUS_AT {
+            true => Self::UPPER_BONUS,
+            false => 0,
+        }
+    }
+
+    fn total(&self) -> u64 {
+        self.boxes.iter().flatten().map(|b| *b as u64).sum::<u64>()
+            + self.upper_bonus()
+            + self.yahtzee_bonuses * Self::YAHTZEE_BONUS
+    }
+
+    fn is_done(&self) -> bool {
+        self.boxes.iter().all(Option::is_some)
+    }
+
+    // Only Yahtzees rolled after the Yahtzee box is filled in are jokers.
+    fn is_joker(&self, dice: &[u8; DICE_COUNT]) -> bool {
+        dice.iter().all(|d| *d == dice[0]) && self.get(Category::Yahtzee).is_some()
+    }
+
+    fn potential(&self, c: Category, dice: &[u8; DICE_COUNT]) -> u8 {
+        c.score(dice, self.is_joker(dice))
+    }
+
+    /// Writes `dice` into box `c`. Returns the points it was worth, including any bonus.
+    fn fill(&mut self, c: Category, dice: &[u8; DICE_COUNT]) -> Result<u64, String> {
+        if let Some(score) = self.get(c) {
+            return Err(format!("{} already has {} in it", c.name(), score));
+        }
+        let before = self.total();
+        if self.is_joker(dice) && self.get(Category::Yahtzee) == Some(GameTurn::YAHTZEE_SCORE) {
+            self.yahtzee_bonuses += 1;
+        }
+        self.boxes[c as usize] = Some(self.potential(c, dice));
+        Ok(self.total() - before)
+    }
+
+    fn describe(&self, dice: Option<&[u8; DICE_COUNT]>) -> String {
+        let boxes = Category::ALL
+            .iter()
+            .map(|c| match (self.get(*c), dice) {
+                (Some(score), _) => format!("{} {}", c.name(), score),
+                (None, Some(dice)) => format!("{} +{}?", c.name(), self.potential(*c, dice)),
+                (None, None) => format!("{} -", c.name()),
+            })
+            .collect::<Vec<_>>();
+        format!(
+            "{} | upper {}/{} | {} | bonus {} | total {}",
+            boxes[..6].join(", "),
+            self.upper(),
+            Self::UPPER_BONUS_AT,
+            boxes[6..].join(", "),
+            self.upper_bonus() + self.yahtzee_bonuses * Self::YAHTZEE_BONUS,
+            self.total()
+        )
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Yahtzee {
+    schema_version: u32,
     players: HashMap<String, GamePlayer>,
     cooldown: Option<u64>,
+    duels: Vec<Duel>,
 
     #[serde(default, skip_serializing)]
     last_roll: u64,
@@ -238,6 +575,28 @@ impl GamePlayer {
         self.current_turn = None;
     }
 
+    fn absorb(&mut self, mut other: GamePlayer) {
+        other.end_turn();
+        self.turns += other.turns;
+        self.rolls += other.rolls;
+        self.total_score += other.total_score;
+        self.total_yahtzees += other.total_yahtzees;
+        self.best_yahtzee_die = max(self.best_yahtzee_die, other.best_yahtzee_die);
+        if let Some(turn) = other.best_turn {
+            if self.best_turn.filter(|bt| bt.score >= turn.score).is_none() {
+                self.best_turn = Some(turn);
+            }
+        }
+        self.duel_wins += other.duel_wins;
+        self.duel_losses += other.duel_losses;
+        self.games += other.games;
+        self.total_game_score += other.total_game_score;
+        self.best_game = max(self.best_game, other.best_game);
+        if self.card.is_none() {
+            self.card = other.card;
+        }
+    }
+
     fn total_rolls(&self) -> u64 {
         self.rolls
             + self
@@ -282,10 +641,12 @@ impl GamePlayer {
 impl Yahtzee {
     pub fn new(save_path: &Path) -> Self {
         Self {
+            schema_version: YAHTZEE_SCHEMA.current,
             players: HashMap::new(),
             path: save_path.to_path_buf(),
             last_roll: 0,
             cooldown: None,
+            duels: Vec::new(),
         }
     }
 
@@ -307,7 +668,25 @@ impl Yahtzee {
 
         let reader = BufReader::new(file);
 
-        let mut opt: Option<Yahtzee> = Some(match serde_json::from_reader(reader) {
+        let mut value: Value = match serde_json::from_reader(reader) {
+            Ok(j) => j,
+            Err(err) => {
+                println!("Yahtzee failed to parse file {}: {}", path.display(), err);
+                return None;
+            }
+        };
+        match YAHTZEE_SCHEMA.upgrade(&mut value) {
+            Ok(applied) if !applied.is_empty() => {
+                println!("[Note] Upgraded {}: {}", path.display(), applied.join(", "))
+            }
+            Ok(_) => {}
+            Err(err) => {
+                println!("Yahtzee failed to upgrade file {}: {}", path.display(), err);
+                return None;
+            }
+        }
+
+        let mut opt: Option<Yahtzee> = Some(match serde_json::from_value(value) {
             Ok(j) => j,
             Err(err) => {
                 println!("Yahtzee failed to parse file {}: {}", path.display(), err);
@@ -327,24 +706,9 @@ impl Yahtzee {
     }
 
     pub fn save(&self) {
-        if !self.path.exists() {
-            let _ = File::create(&self.path);
-        }
-        let file = match File::options().write(true).truncate(true).open(&self.path) {
-            Ok(f) => f,
-            Err(err) => {
-                println!(
-                    "Yahtzee failed to write to file {}: {}",
-                    self.path.display(),
-                    err
-                );
-                return;
-            }
-        };
-
-        if let Err(err) = serde_json::to_writer_pretty(file, self) {
+        if let Err(err) = persist::save_json(&self.path, self) {
             println!(
-                "Yahtzee failed to serialize for file {}: {}",
+                "Yahtzee failed to write to file {}: {}",
                 self.path.display(),
                 err
             );
@@ -354,9 +718,16 @@ impl Yahtzee {
     pub fn play(&mut self, player_name: &str, saves: &[u8]) -> Result<String, YahtzeeError> {
         self.last_roll = get_unixtime();
         let cd = self.cooldown.unwrap_or(2000);
+        if let Some(i) = self.duel_turn(player_name) {
+            return self.play_duel(i, player_name, saves, cd);
+        }
         let player = self.get_or_create_player(player_name);
         let mut rng = thread_rng();
 
+        if let Some(card) = player.card.clone() {
+            return Self::play_card(player, &card, saves, cd, &mut rng);
+        }
+
         let disposed_score = player
             .current_turn
             .as_ref()
@@ -408,15 +779,423 @@ impl Yahtzee {
         }
     }
 
+    fn play_card(
+        player: &mut GamePlayer,
+        card: &Scorecard,
+        saves: &[u8],
+        cooldown: u64,
+        rng: &mut ThreadRng,
+    ) -> Result<String, YahtzeeError> {
+        match player.current_turn {
+            Some(_) if saves.is_empty() => {
+                return Err(YahtzeeError::public(
+                    "Write those dice down first, {ur}: !yahtzee score <box> (see !yahtzee card)",
+                ))
+            }
+            Some(turn) if turn.rolls >= GameTurn::MAX_ROLLS => {
+                return Err(YahtzeeError::public(
+                    "You're out of re-rolls, {ur}. Pick a box with !yahtzee score <box>",
+                ))
+            }
+            _ => {}
+        }
+        let (rolls, _) = player.play(saves, cooldown, rng)?;
+        let roll_txt = rolls.iter().map(|v| get_dice_face_text(*v)).join(", ");
+        let turn_rolls = player.current_turn.map(|t| t.rolls).unwrap_or(1);
+        let best = Category::ALL
+            .iter()
+            .filter(|c| card.get(**c).is_none())
+            .max_by_key(|c| card.potential(**c, &rolls))
+            .map(|c| format!("{} for {}", c.name(), card.potential(*c, &rolls)))
+            .unwrap_or_default();
+        Ok(format!(
+            "{{ur}} rolled {} (roll {}/{}). Best open box: {}. Keep dice with e.g. \"!yahtzee {} {}\" or write it down with \"!yahtzee score <box>\".",
+            roll_txt,
+            turn_rolls,
+            GameTurn::MAX_ROLLS,
+            best,
+            rolls[0],
+            rolls[1]
+        ))
+    }
+
+    /// !yahtzee start - starts a full game with an empty scorecard.
+    pub fn start_game(&mut self, player_name: &str) -> String {
+        let login = player_name.to_lowercase();
+        if self.duels.iter().any(|d| d.side(&login).is_some()) {
+            return "Finish your duel first, {ur}".to_string();
+        }
+        let player = self.get_or_create_player(player_name);
+        if let Some(card) = &player.card {
+            return format!(
+                "You already have a game going, {{ur}}: {}",
+                card.describe(player.current_turn.as_ref().map(|t| &t.dice))
+            );
+        }
+        player.end_turn();
+        player.card = Some(Scorecard::default());
+        "{ur} grabbed a scorecard! Roll with !yahtzee, keep dice with !yahtzee <dice>, then fill a box with !yahtzee score <box>. !yahtzee card shows the card.".to_string()
+    }
+
+    /// !yahtzee score <box> - writes the current dice into a box of the player's scorecard.
+    pub fn score(&mut self, player_name: &str, category: &str) -> Result<String, YahtzeeError> {
+        let player = self.get_or_create_player(player_name);
+        let dice = player.current_turn.filter(|t| t.rolls > 0).map(|t| t.dice);
+        let card = player.card.as_mut().ok_or_else(|| {
+            YahtzeeError::public(
+                "You're not playing a full game, {ur}. Start one with !yahtzee start",
+            )
+        })?;
+        let category = Category::parse(category).ok_or_else(|| {
+            YahtzeeError::public(&format!(
+                "Which box, {{ur}}? {}",
+                Category::ALL.iter().map(|c| c.name()).join(", ")
+            ))
+        })?;
+        let dice = dice.ok_or_else(|| YahtzeeError::public("Roll first with !yahtzee, {ur}"))?;
+        let points = card
+            .fill(category, &dice)
+            .map_err(|e| YahtzeeError::public(&format!("Hmmge {}, {{ur}}", e)))?;
+        let (done, total) = (card.is_done(), card.total());
+        let left = card.boxes.iter().filter(|b| b.is_none()).count();
+        player.end_turn();
+        if !done {
+            return Ok(format!(
+                "{{ur}} put {} points in {}. Total: {}, {} box(es) to go.",
+                points,
+                category.name(),
+                total,
+                left
+            ));
+        }
+        let best = total > player.best_game || player.games == 0;
+        player.card = None;
+        player.games += 1;
+        player.total_game_score += total;
+        player.best_game = max(player.best_game, total);
+        Ok(format!(
+            "{{ur}} put {} points in {} and finished their game with {} points!{}",
+            points,
+            category.name(),
+            total,
+            if best {
+                " New personal best PagMan"
+            } else {
+                ""
+            }
+        ))
+    }
+
+    /// !yahtzee card [name]
+    pub fn card(&self, player_name: &str) -> String {
+        let player = match self.players.get(&player_name.to_lowercase()) {
+            Some(p) => p,
+            None => {
+                return format!("I don't see a player named {}... folderSus", player_name);
+            }
+        };
+        match &player.card {
+            Some(card) => format!(
+                "{}'s card: {}",
+                player_name,
+                card.describe(player.current_turn.as_ref().map(|t| &t.dice))
+            ),
+            None => format!(
+                "{} isn't playing a full game. Start one with !yahtzee start",
+                player_name
+            ),
+        }
+    }
+
+    /// !yahtzee quit - throws away the player's scorecard.
+    pub fn quit_game(&mut self, player_name: &str) -> String {
+        let player = self.get_or_create_player(player_name);
+        match player.card.take() {
+            Some(_) => {
+                player.end_turn();
+                "{ur} tore up their scorecard.".to_string()
+            }
+            None => "You're not playing a full game, {ur}.".to_string(),
+        }
+    }
+
     pub fn end_turn(&mut self, player_name: &str) {
         let player = self.get_or_create_player(player_name);
         player.end_turn()
     }
 
+    /// Ends every quick mode turn. Full game and duel turns are kept, so they can still be
+    /// scored.
     pub fn end_all_turns(&mut self) {
-        for p in self.players.values_mut() {
-            p.end_turn()
+        let dueling = self
+            .duels
+            .iter()
+            .filter(|d| d.accepted)
+            .flat_map(|d| d.players.iter().map(|p| p.login.clone()))
+            .collect::<Vec<_>>();
+        for (login, p) in self.players.iter_mut() {
+            if p.card.is_none() && !dueling.contains(login) {
+                p.end_turn()
+            }
+        }
+    }
+
+    // The duel `player_name` still has to take their turn in, if any.
+    fn duel_turn(&self, player_name: &str) -> Option<usize> {
+        let login = player_name.to_lowercase();
+        self.duels.iter().position(|d| {
+            d.accepted && matches!(d.side(&login), Some(side) if d.players[side].score.is_none())
+        })
+    }
+
+    fn play_duel(
+        &mut self,
+        i: usize,
+        player_name: &str,
+        saves: &[u8],
+        cooldown: u64,
+    ) -> Result<String, YahtzeeError> {
+        let player = self.get_or_create_player(player_name);
+        // Rolling again without keeping any dice means standing on what they've got.
+        if !saves.is_empty() || player.current_turn.is_none() {
+            let (rolls, score) = player.play(saves, cooldown, &mut thread_rng())?;
+            let roll_txt = rolls.iter().map(|v| get_dice_face_text(*v)).join(", ");
+            if player.current_turn.map(|t| t.rolls).unwrap_or_default() < GameTurn::MAX_ROLLS {
+                return Ok(format!(
+                    "{{ur}} rolled {} for {} points. Re-roll with e.g. \"!yahtzee {} {}\" or keep it with \"!yahtzee stand\".",
+                    roll_txt, score, rolls[0], rolls[1]
+                ));
+            }
+        }
+        let score = player.current_turn.map(|t| t.score).unwrap_or_default();
+        let duel = &mut self.duels[i];
+        if let Some(side) = duel.side(&player_name.to_lowercase()) {
+            duel.players[side].score = Some(score);
+        }
+        Ok(format!(
+            "{{ur}} finishes their duel turn with {} points!",
+            score
+        ))
+    }
+
+    /// !yahtzee stand - keeps the current dice as the player's duel score.
+    pub fn stand(&mut self, player_name: &str) -> Result<String, YahtzeeError> {
+        let i = self
+            .duel_turn(player_name)
+            .ok_or_else(|| YahtzeeError::public("You're not in a duel, {ur}"))?;
+        if self
+            .get_or_create_player(player_name)
+            .current_turn
+            .is_none()
+        {
+            return Err(YahtzeeError::public("Roll first with !yahtzee, {ur}"));
+        }
+        let cd = self.cooldown.unwrap_or(2000);
+        self.play_duel(i, player_name, &[], cd)
+    }
+
+    /// !yahtzee duel <user> [files]
+    pub fn challenge(
+        &mut self,
+        player_data: &PlayerData,
+        key: &str,
+        player_name: &str,
+        args: &[&str],
+        now: u64,
+    ) -> Result<String, String> {
+        let usage = "Usage: !yahtzee duel <user> [files]";
+        let target = args.first().ok_or(usage)?.trim_start_matches('@');
+        let wager = match args.get(1) {
+            Some(w) => w.parse::<i64>().ok().filter(|w| *w >= 0).ok_or(usage)?,
+            None => 0,
+        };
+        let other_key = player_data
+            .key_of(target)
+            .ok_or_else(|| format!("I don't know anyone called {}.", target))?;
+        let other = &player_data.players[&other_key];
+        let (login, other_login) = (player_name.to_lowercase(), other.username.to_lowercase());
+        if other_key == key || other_login == login {
+            return Err("You can't duel yourself.".to_string());
+        }
+        if other.opted_out {
+            return Err(format!("{} has opted out of games.", other.name()));
+        }
+        for l in [&login, &other_login] {
+            if self.duels.iter().any(|d| d.side(l).is_some()) {
+                return Err(format!("{} is already in a duel.", l));
+            }
+            if matches!(self.players.get(l), Some(p) if p.card.is_some()) {
+                return Err(format!("{} has to finish their full game first.", l));
+            }
+        }
+        let files = player_data.players.get(key).map(|p| p.files).unwrap_or(0);
+        if files < wager {
+            return Err(format!("You only have {} files.", files));
+        }
+        self.duels.push(Duel {
+            players: [
+                Duelist {
+                    login,
+                    key: key.to_string(),
+                    score: None,
+                },
+                Duelist {
+                    login: other_login,
+                    key: other_key,
+                    score: None,
+                },
+            ],
+            wager,
+            accepted: false,
+            deadline: now + DUEL_ANSWER_TIME,
+        });
+        let stakes = match wager {
+            0 => "for glory".to_string(),
+            w => format!("for {} files", w),
+        };
+        Ok(format!(
+            "{}, {{ur}} challenges you to a Yahtzee duel {}! \"!yahtzee accept\" or \"!yahtzee decline\" in the next {} minutes.",
+            other.name(),
+            stakes,
+            DUEL_ANSWER_TIME / 60
+        ))
+    }
+
+    /// !yahtzee accept and !yahtzee decline. Accepting takes the wager from both players.
+    pub fn answer(
+        &mut self,
+        player_data: &mut PlayerData,
+        player_name: &str,
+        accept: bool,
+        now: u64,
+    ) -> Result<String, String> {
+        let login = player_name.to_lowercase();
+        let i = self
+            .duels
+            .iter()
+            .position(|d| !d.accepted && d.players[1].login == login)
+            .ok_or("Nobody has challenged you to a duel.")?;
+        let challenger = name_of(player_data, &self.duels[i].players[0]);
+        if !accept {
+            self.duels.remove(i);
+            return Ok(format!("{{ur}} turned down {}'s duel.", challenger));
+        }
+        let duel = &self.duels[i];
+        for p in &duel.players {
+            let files = player_data.players.get(&p.key).map(|p| p.files);
+            if files.unwrap_or(0) < duel.wager {
+                return Err(format!(
+                    "{} doesn't have the {} files for this duel.",
+                    name_of(player_data, p),
+                    duel.wager
+                ));
+            }
+        }
+        for p in &duel.players {
+            player_data.adjust(&p.key, -duel.wager, Reason::Bet, None, None);
+        }
+        let logins = [duel.players[0].login.clone(), login];
+        for l in &logins {
+            self.get_or_create_player(l).end_turn();
+        }
+        let duel = &mut self.duels[i];
+        duel.accepted = true;
+        duel.deadline = now + DUEL_TIME_LIMIT;
+        Ok(format!(
+            "It's on! {} vs {{ur}}: roll with !yahtzee, re-roll as usual and keep your dice with !yahtzee stand. You both have {} minutes.",
+            challenger,
+            DUEL_TIME_LIMIT / 60
+        ))
+    }
+
+    /// When the next duel runs out of time, if there are any.
+    pub fn next_duel_deadline(&self) -> Option<u64> {
+        self.duels.iter().map(|d| d.deadline).min()
+    }
+
+    /// Pays out every duel that's over, either because both players have had their turn or
+    /// because time ran out. Returns what to announce.
+    pub fn settle_duels(&mut self, player_data: &mut PlayerData, now: u64) -> Vec<String> {
+        let (over, open): (Vec<Duel>, Vec<Duel>) =
+            std::mem::take(&mut self.duels).into_iter().partition(|d| {
+                now >= d.deadline || (d.accepted && d.players.iter().all(|p| p.score.is_some()))
+            });
+        self.duels = open;
+        let mut msgs = Vec::new();
+        for duel in over {
+            let [a, b] = &duel.players;
+            let (name_a, name_b) = (name_of(player_data, a), name_of(player_data, b));
+            if !duel.accepted {
+                msgs.push(format!(
+                    "{} didn't answer {}'s duel in time.",
+                    name_b, name_a
+                ));
+                continue;
+            }
+            let pool = duel.wager * 2;
+            let winner = match (a.score, b.score) {
+                (Some(x), Some(y)) if x == y => None,
+                (Some(x), Some(y)) => Some(if x > y { 0 } else { 1 }),
+                (Some(_), None) => Some(0),
+                (None, Some(_)) => Some(1),
+                (None, None) => None,
+            };
+            let winner = match winner {
+                Some(w) => w,
+                None => {
+                    for p in &duel.players {
+                        if player_data.players.contains_key(&p.key) {
+                            player_data.adjust(&p.key, duel.wager, Reason::Refund, None, None);
+                        }
+                    }
+                    msgs.push(match a.score {
+                        Some(score) => format!(
+                            "{} and {} tied their duel on {} points, so nobody wins.",
+                            name_a, name_b, score
+                        ),
+                        None => format!(
+                            "Neither {} nor {} finished their duel in time, wagers refunded.",
+                            name_a, name_b
+                        ),
+                    });
+                    continue;
+                }
+            };
+            let (w, l) = (&duel.players[winner], &duel.players[1 - winner]);
+            if player_data.players.contains_key(&w.key) {
+                player_data.adjust(&w.key, pool, Reason::BetPayout, Some(&l.key), None);
+            }
+            // Both were set up when the duel was accepted, unless they've been purged since.
+            if let Some(p) = self.players.get_mut(&w.login) {
+                p.duel_wins += 1;
+            }
+            if let Some(p) = self.players.get_mut(&l.login) {
+                p.duel_losses += 1;
+            }
+            let (w_name, l_name) = match winner {
+                0 => (&name_a, &name_b),
+                _ => (&name_b, &name_a),
+            };
+            let prize = match pool {
+                0 => String::new(),
+                pool => format!(" and {} files", pool),
+            };
+            msgs.push(match l.score {
+                Some(score) => format!(
+                    "{} beats {} {} to {} and wins the duel{}!",
+                    w_name,
+                    l_name,
+                    w.score.unwrap_or_default(),
+                    score,
+                    prize
+                ),
+                None => format!(
+                    "{} ran out of time, so {} wins the duel{}!",
+                    l_name, w_name, prize
+                ),
+            });
         }
+        msgs
     }
 
     pub fn player_stats(&self, player_name: &str) -> String {
@@ -435,7 +1214,7 @@ impl Yahtzee {
         } else {
             total_score as f64 / turns as f64
         };
-        format!("{} has rolled {} time(s) with {} re-roll(s). Total score: {}. Average score: {:.2}. Best score: {}. Yahtzee(s): {}.", player_name, turns, rolls as i64 - turns as i64, total_score, avg_score, player.best_score().unwrap_or_default(), player.total_yahtzees())
+        format!("{} has rolled {} time(s) with {} re-roll(s). Total score: {}. Average score: {:.2}. Best score: {}. Yahtzee(s): {}. Duels: {} won, {} lost.", player_name, turns, rolls as i64 - turns as i64, total_score, avg_score, player.best_score().unwrap_or_default(), player.total_yahtzees(), player.duel_wins, player.duel_losses)
     }
 
     pub fn get_total_yahtzees(&self, player_name: &str) -> u64 {
@@ -445,6 +1224,49 @@ impl Yahtzee {
             .unwrap_or_default()
     }
 
+    /// The number of rolls it took, if the player's current turn is a Yahtzee.
+    pub fn current_yahtzee(&self, player_name: &str) -> Option<u8> {
+        self.players
+            .get(&player_name.to_lowercase())
+            .and_then(|p| p.current_turn)
+            .filter(|t| t.score == GameTurn::YAHTZEE_SCORE)
+            .map(|t| t.rolls)
+    }
+
+    /// Moves the stats of `from` onto `into`, e.g. when a player changes their login.
+    pub fn merge_players(&mut self, from: &str, into: &str) {
+        let from = from.to_lowercase();
+        if from == into.to_lowercase() {
+            return;
+        }
+        if let Some(old) = self.players.remove(&from) {
+            self.get_or_create_player(into).absorb(old);
+        }
+    }
+
+    pub fn has_player(&self, player_name: &str) -> bool {
+        self.players.contains_key(&player_name.to_lowercase())
+    }
+
+    /// Forgets a player's stats and any game in progress. Returns whether there were any.
+    /// Forgets a player (see !purge) and calls off their duels. The other side of an accepted
+    /// duel gets their wager back. Returns whether anything was removed.
+    pub fn remove_player(&mut self, player_data: &mut PlayerData, player_name: &str) -> bool {
+        let login = player_name.to_lowercase();
+        let (theirs, others): (Vec<Duel>, Vec<Duel>) = std::mem::take(&mut self.duels)
+            .into_iter()
+            .partition(|d| d.side(&login).is_some());
+        self.duels = others;
+        for duel in theirs.iter().filter(|d| d.accepted && d.wager > 0) {
+            for p in &duel.players {
+                if p.login != login && player_data.players.contains_key(&p.key) {
+                    player_data.adjust(&p.key, duel.wager, Reason::Refund, None, None);
+                }
+            }
+        }
+        self.players.remove(&login).is_some() || !theirs.is_empty()
+    }
+
     fn get_or_create_player(&mut self, player_name: &str) -> &mut GamePlayer {
         let player_name = player_name.to_lowercase();
         self.players.entry(player_name).or_insert(GamePlayer {
@@ -453,6 +1275,66 @@ impl Yahtzee {
     }
 }
 
+// A duelist's current name, or their login if they're gone.
+fn name_of(player_data: &PlayerData, duelist: &Duelist) -> String {
+    match player_data.players.get(&duelist.key) {
+        Some(p) => p.name(),
+        None => duelist.login.clone(),
+    }
+}
+
+pub fn register_stats(registry: &mut StatRegistry) {
+    fn player<'a>(sources: &Sources<'a>, p: &Player) -> Option<&'a GamePlayer> {
+        sources.yahtzee?.players.get(&p.username.to_lowercase())
+    }
+    registry.register(
+        Stat::count("yahtzee", &["yahtzees"], "Yahtzees", |s, p| {
+            Some(player(s, p)?.total_yahtzees() as f64).filter(|n| *n > 0.0)
+        })
+        .on_profile(),
+    );
+    registry.register(Stat::decimal(
+        "yahtzee_average",
+        &["yavg"],
+        "Yahtzee average",
+        |s, p| {
+            let player = player(s, p)?;
+            let turns = player.total_turns();
+            Some(player.total_score() as f64 / turns as f64).filter(|_| turns > 0)
+        },
+    ));
+    registry.register(Stat::count(
+        "yahtzee_best",
+        &["ybest"],
+        "Best Yahtzee score",
+        |s, p| Some(player(s, p)?.best_score()? as f64),
+    ));
+    registry.register(Stat::count(
+        "yahtzee_game_best",
+        &["ygame"],
+        "Best full Yahtzee game",
+        |s, p| {
+            let player = player(s, p)?;
+            Some(player.best_game as f64).filter(|_| player.games > 0)
+        },
+    ));
+    registry.register(Stat::decimal(
+        "yahtzee_game_average",
+        &["ygameavg"],
+        "Full Yahtzee game average",
+        |s, p| {
+            let player = player(s, p)?;
+            Some(player.total_game_score as f64 / player.games as f64).filter(|_| player.games > 0)
+        },
+    ));
+    registry.register(Stat::count(
+        "yahtzee_games",
+        &["ygames"],
+        "Full Yahtzee games",
+        |s, p| Some(player(s, p)?.games as f64).filter(|n| *n > 0.0),
+    ));
+}
+
 impl Drop for Yahtzee {
     fn drop(&mut self) {
         self.save()
@@ -515,7 +1397,25 @@ fn get_unixtime() -> u64 {
 
 #[cfg(test)]
 mod tests {
-    use super::GameTurn;
+    use super::*;
+    use crate::persist::TestDir;
+    use serde_json::json;
+
+    #[test]
+    fn upgrades_v1_file() {
+        let mut v = json!({
+            "players": {"mjb": {"turns": 3, "best_turn": null, "current_turn": null}},
+            "cooldown": null
+        });
+        YAHTZEE_SCHEMA.upgrade(&mut v).unwrap();
+        assert_eq!(v["schema_version"], json!(4));
+        assert_eq!(v["duels"], json!([]));
+        assert_eq!(v["players"]["mjb"]["turns"], json!(3));
+        assert_eq!(v["players"]["mjb"]["games"], json!(0));
+        assert_eq!(v["players"]["mjb"]["total_yahtzees"], json!(0));
+        let game: Yahtzee = serde_json::from_value(v).unwrap();
+        assert_eq!(game.players["mjb"].turns, 3);
+    }
 
     #[test]
     fn full_house() {
@@ -611,4 +1511,166 @@ mod tests {
             turn.score
         );
     }
+
+    #[test]
+    fn scores_categories() {
+        let score = |c: Category, dice| c.score(&dice, false);
+        assert_eq!(score(Category::Threes, [3, 3, 1, 3, 6]), 9);
+        assert_eq!(score(Category::Sixes, [3, 3, 1, 3, 5]), 0);
+        assert_eq!(score(Category::ThreeOfAKind, [3, 3, 1, 3, 6]), 16);
+        assert_eq!(score(Category::FourOfAKind, [3, 3, 1, 3, 6]), 0);
+        assert_eq!(score(Category::FullHouse, [2, 5, 2, 5, 5]), 25);
+        assert_eq!(score(Category::FullHouse, [5, 5, 5, 5, 5]), 0);
+        assert_eq!(score(Category::SmallStraight, [6, 4, 3, 5, 5]), 30);
+        assert_eq!(score(Category::LargeStraight, [6, 4, 3, 5, 1]), 0);
+        assert_eq!(score(Category::LargeStraight, [6, 4, 3, 5, 2]), 40);
+        assert_eq!(score(Category::Yahtzee, [2, 2, 2, 2, 2]), 50);
+        assert_eq!(score(Category::Chance, [6, 4, 3, 5, 1]), 19);
+        assert_eq!(Category::LargeStraight.score(&[2; 5], true), 40);
+        assert_eq!(Category::parse("Full House"), Some(Category::FullHouse));
+        assert_eq!(Category::parse("3 of a kind"), Some(Category::ThreeOfAKind));
+        assert_eq!(Category::parse("sevens"), None);
+    }
+
+    #[test]
+    fn scorecard_bonuses() {
+        let mut card = Scorecard::default();
+        for (i, c) in Category::ALL[..6].iter().enumerate() {
+            let face = i as u8 + 1;
+            card.fill(*c, &[face, face, face, face, 1]).unwrap();
+        }
+        assert!(card.fill(Category::Ones, &[1; 5]).is_err());
+        // Four of every face (and a fifth one) is 85, so the upper bonus counts.
+        assert_eq!(card.upper(), 85);
+        assert_eq!(card.total(), 85 + 35);
+
+        assert_eq!(card.fill(Category::Yahtzee, &[4; 5]), Ok(50));
+        // Another Yahtzee is a joker, and worth a bonus.
+        assert_eq!(card.fill(Category::FullHouse, &[4; 5]), Ok(125));
+        assert_eq!(card.yahtzee_bonuses, 1);
+        assert!(!card.is_done());
+    }
+
+    #[test]
+    fn plays_a_full_game() {
+        let dir = TestDir::new("yahtzee");
+        let mut game = Yahtzee::new(&dir.join("yahtzee.json"));
+        assert!(game.score("mjb", "chance").is_err());
+        game.start_game("mjb");
+        assert!(game.score("mjb", "chance").is_err());
+        for (i, c) in Category::ALL.iter().enumerate() {
+            let player = game.players.get_mut("mjb").unwrap();
+            player.current_turn = Some(GameTurn {
+                dice: [1, 2, 3, 4, 5],
+                rolls: 1,
+                ..Default::default()
+            });
+            let res = game
+                .score("mjb", c.name())
+                .unwrap_or_else(|e| e.to_string());
+            if i < 12 {
+                assert!(res.contains("to go"), "{}", res);
+                assert!(game.card("mjb").contains(&format!("{} ", c.name())));
+            } else {
+                // 15 in the upper section, 15 (chance) + 30 + 40.
+                assert!(
+                    res.contains("finished their game with 100 points"),
+                    "{}",
+                    res
+                );
+            }
+        }
+        let player = &game.players["mjb"];
+        assert!(player.card.is_none());
+        assert_eq!((player.games, player.best_game, player.turns), (1, 100, 13));
+    }
+
+    fn turn(player: &mut GamePlayer, dice: [u8; DICE_COUNT]) {
+        let mut turn = GameTurn {
+            dice,
+            rolls: 1,
+            ..Default::default()
+        };
+        turn.calculate_score().ok();
+        player.current_turn = Some(turn);
+    }
+
+    #[test]
+    fn duels_for_files() {
+        let dir = TestDir::new("duels");
+        let mut game = Yahtzee::new(&dir.join("yahtzee.json"));
+        let mut pd = PlayerData::in_memory();
+        let a = pd.identify("mjb", Some("1")).key;
+        let b = pd.identify("pac", Some("2")).key;
+
+        assert!(game.challenge(&pd, &a, "mjb", &["mjb"], 0).is_err());
+        assert!(game.challenge(&pd, &a, "mjb", &["pac", "5000"], 0).is_err());
+        game.challenge(&pd, &a, "mjb", &["@PAC", "100"], 0).unwrap();
+        assert!(game.challenge(&pd, &b, "pac", &["mjb"], 0).is_err());
+        assert!(game.answer(&mut pd, "mjb", true, 10).is_err());
+        game.answer(&mut pd, "pac", true, 10).unwrap();
+        assert_eq!(pd.players["1"].files, 900);
+
+        turn(game.players.get_mut("mjb").unwrap(), [2, 2, 3, 3, 3]);
+        assert!(game.stand("mjb").is_ok());
+        assert!(game.settle_duels(&mut pd, 20).is_empty());
+        turn(game.players.get_mut("pac").unwrap(), [1, 2, 3, 4, 6]);
+        game.stand("pac").ok();
+        let msgs = game.settle_duels(&mut pd, 30);
+        assert_eq!(
+            msgs,
+            vec!["pac beats mjb 30 to 25 and wins the duel and 200 files!"]
+        );
+        assert_eq!(pd.players["2"].files, 1100);
+        assert_eq!(game.players["mjb"].duel_losses, 1);
+        assert!(game.player_stats("pac").contains("Duels: 1 won, 0 lost."));
+    }
+
+    #[test]
+    fn duel_timeouts_forfeit() {
+        let dir = TestDir::new("duels2");
+        let mut game = Yahtzee::new(&dir.join("yahtzee.json"));
+        let mut pd = PlayerData::in_memory();
+        let (a, b) = ("mjb".to_string(), "pac".to_string());
+        pd.player(&a);
+        pd.player(&b);
+
+        game.challenge(&pd, &a, "mjb", &["pac"], 0).unwrap();
+        let msgs = game.settle_duels(&mut pd, DUEL_ANSWER_TIME);
+        assert_eq!(msgs, vec!["pac didn't answer mjb's duel in time."]);
+
+        game.challenge(&pd, &a, "mjb", &["pac", "50"], 0).unwrap();
+        game.answer(&mut pd, "pac", true, 0).unwrap();
+        turn(game.players.get_mut("pac").unwrap(), [6, 6, 6, 6, 6]);
+        assert!(game.stand("pac").is_ok());
+        assert_eq!(game.next_duel_deadline(), Some(DUEL_TIME_LIMIT));
+        let msgs = game.settle_duels(&mut pd, DUEL_TIME_LIMIT);
+        assert_eq!(
+            msgs,
+            vec!["mjb ran out of time, so pac wins the duel and 100 files!"]
+        );
+        assert_eq!(pd.players["mjb"].files, 950);
+        assert_eq!(pd.players["pac"].files, 1050);
+        assert_eq!(game.next_duel_deadline(), None);
+    }
+
+    #[test]
+    fn purging_calls_off_duels() {
+        let dir = TestDir::new("duels_purge");
+        let mut game = Yahtzee::new(&dir.join("yahtzee.json"));
+        let mut pd = PlayerData::in_memory();
+        let (a, b) = ("mjb".to_string(), "pac".to_string());
+        pd.player(&a);
+        pd.player(&b);
+
+        game.challenge(&pd, &a, "mjb", &["pac", "100"], 0).unwrap();
+        game.answer(&mut pd, "pac", true, 0).unwrap();
+        assert_eq!(pd.players["mjb"].files, 900);
+        pd.remove("pac");
+        assert!(game.remove_player(&mut pd, "PAC"));
+        assert_eq!(pd.players["mjb"].files, 1000);
+        assert!(game.settle_duels(&mut pd, DUEL_TIME_LIMIT).is_empty());
+        assert!(!game.players.contains_key("pac"));
+        assert!(!game.remove_player(&mut pd, "pac"));
+    }
 }